@@ -0,0 +1,12 @@
+CREATE TABLE swap_history
+(
+    id            SERIAL PRIMARY KEY,
+    username      VARCHAR   NOT NULL,
+    model_id      INTEGER,
+    model_img_url VARCHAR   NOT NULL,
+    output_url    VARCHAR   NOT NULL,
+    status        VARCHAR   NOT NULL,
+    created_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX swap_history_username_created_at_idx ON swap_history (username, created_at DESC);
@@ -0,0 +1,17 @@
+CREATE TABLE swap_jobs
+(
+    id            SERIAL PRIMARY KEY,
+    username      VARCHAR   NOT NULL,
+    model_id      INTEGER,
+    user_img_url  TEXT      NOT NULL,
+    model_img_url TEXT      NOT NULL,
+    status        VARCHAR   NOT NULL DEFAULT 'queued',
+    attempts      INTEGER   NOT NULL DEFAULT 0,
+    output_url    VARCHAR,
+    error         VARCHAR,
+    run_after     TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX swap_jobs_status_run_after_idx ON swap_jobs (status, run_after);
@@ -0,0 +1,8 @@
+DELETE FROM swap_jobs
+WHERE batch_id IS NOT NULL;
+
+ALTER TABLE swap_jobs
+    DROP COLUMN batch_id,
+    ALTER COLUMN user_img_url SET NOT NULL;
+
+DROP TABLE swap_batches;
@@ -0,0 +1,15 @@
+CREATE TABLE swap_batches
+(
+    id           SERIAL PRIMARY KEY,
+    username     VARCHAR   NOT NULL,
+    -- preprocessed once for every child; emptied once they have all finished
+    user_img_url TEXT      NOT NULL,
+    created_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- a batch's children use its user image, rather than each holding a copy
+ALTER TABLE swap_jobs
+    ADD COLUMN batch_id INTEGER REFERENCES swap_batches (id) ON DELETE CASCADE,
+    ALTER COLUMN user_img_url DROP NOT NULL;
+
+CREATE INDEX swap_jobs_batch_id_idx ON swap_jobs (batch_id);
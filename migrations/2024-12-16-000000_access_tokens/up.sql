@@ -0,0 +1,13 @@
+-- Metadata serve-replica keeps per bearer token; the token itself is only stored hashed
+CREATE TABLE access_tokens
+(
+    id         SERIAL PRIMARY KEY,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    username   VARCHAR,
+    scopes     VARCHAR     NOT NULL DEFAULT '',
+    expires_at TIMESTAMP,
+    created_at TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX access_tokens_username_idx ON access_tokens (username);
@@ -0,0 +1,5 @@
+DROP TABLE refresh_tokens;
+
+ALTER TABLE access_tokens
+    DROP COLUMN local,
+    DROP COLUMN family_id;
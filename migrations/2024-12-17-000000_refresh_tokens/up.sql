@@ -0,0 +1,23 @@
+-- A family is every access and refresh token descending from one password/authorization code grant
+ALTER TABLE access_tokens
+    ADD COLUMN family_id VARCHAR,
+    -- minted by serve-replica itself rather than rust-actix-diesel-auth-scaffold
+    ADD COLUMN local     BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX access_tokens_family_id_idx ON access_tokens (family_id);
+
+CREATE TABLE refresh_tokens
+(
+    id         SERIAL PRIMARY KEY,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    family_id  VARCHAR     NOT NULL,
+    username   VARCHAR     NOT NULL,
+    scopes     VARCHAR     NOT NULL,
+    expires_at TIMESTAMP   NOT NULL,
+    created_at TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- set once rotated; presenting a used refresh token again revokes its family
+    used_at    TIMESTAMP,
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX refresh_tokens_family_id_idx ON refresh_tokens (family_id);
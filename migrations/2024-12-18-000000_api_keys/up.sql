@@ -0,0 +1,16 @@
+CREATE TABLE api_keys
+(
+    id           SERIAL PRIMARY KEY,
+    username     VARCHAR     NOT NULL,
+    name         VARCHAR     NOT NULL,
+    -- start of the key, so users can tell their keys apart
+    prefix       VARCHAR     NOT NULL,
+    key_hash     VARCHAR(64) NOT NULL UNIQUE,
+    scopes       VARCHAR     NOT NULL,
+    expires_at   TIMESTAMP,
+    created_at   TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP,
+    revoked_at   TIMESTAMP
+);
+
+CREATE INDEX api_keys_username_idx ON api_keys (username);
@@ -0,0 +1 @@
+DROP TABLE confidential_clients;
@@ -0,0 +1,16 @@
+-- Machine clients using the client_credentials grant; secrets are only stored hashed
+CREATE TABLE confidential_clients
+(
+    id                SERIAL PRIMARY KEY,
+    client_id         VARCHAR     NOT NULL UNIQUE,
+    secret_hash       VARCHAR(64) NOT NULL,
+    name              VARCHAR     NOT NULL,
+    -- user the client acts as
+    owner             VARCHAR     NOT NULL,
+    scopes            VARCHAR     NOT NULL,
+    created_at        TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    secret_rotated_at TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    revoked_at        TIMESTAMP
+);
+
+CREATE INDEX confidential_clients_owner_idx ON confidential_clients (owner);
@@ -0,0 +1,11 @@
+-- Per-user state serve-replica keeps alongside the scaffold's users; rows are created on first authentication
+CREATE TABLE user_accounts
+(
+    username     VARCHAR PRIMARY KEY,
+    role         VARCHAR   NOT NULL DEFAULT 'user',
+    disabled_at  TIMESTAMP,
+    -- completed swaps since credits were last reset
+    credits_used BIGINT    NOT NULL DEFAULT 0,
+    created_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
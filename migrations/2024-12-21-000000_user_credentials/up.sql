@@ -0,0 +1,23 @@
+-- Credentials of users who registered through serve-replica, rather than through the scaffold
+CREATE TABLE user_credentials
+(
+    username          VARCHAR PRIMARY KEY,
+    email             VARCHAR   NOT NULL UNIQUE,
+    -- PHC string, e.g., $argon2id$...
+    password_hash     VARCHAR   NOT NULL,
+    email_verified_at TIMESTAMP,
+    created_at        TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at        TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Single-use tokens mailed to users, to verify their email or reset their password
+CREATE TABLE email_tokens
+(
+    id         SERIAL PRIMARY KEY,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    username   VARCHAR     NOT NULL REFERENCES user_credentials (username) ON DELETE CASCADE,
+    purpose    VARCHAR     NOT NULL,
+    expires_at TIMESTAMP   NOT NULL,
+    created_at TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    used_at    TIMESTAMP
+);
@@ -0,0 +1,20 @@
+-- TOTP second factor; enrolment only takes effect once confirmed with a code
+CREATE TABLE totp_credentials
+(
+    username       VARCHAR PRIMARY KEY,
+    secret         BYTEA     NOT NULL,
+    confirmed_at   TIMESTAMP,
+    -- time step of the last accepted code, so a code cannot be replayed
+    last_used_step BIGINT,
+    created_at     TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE totp_recovery_codes
+(
+    id        SERIAL PRIMARY KEY,
+    username  VARCHAR     NOT NULL REFERENCES totp_credentials (username) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL,
+    used_at   TIMESTAMP
+);
+
+CREATE INDEX totp_recovery_codes_username_idx ON totp_recovery_codes (username);
@@ -0,0 +1,27 @@
+-- Users' accounts at an external OpenID Connect provider, which they sign in with
+CREATE TABLE federated_identities
+(
+    issuer        VARCHAR   NOT NULL,
+    -- the provider's `sub` claim
+    subject       VARCHAR   NOT NULL,
+    username      VARCHAR   NOT NULL,
+    email         VARCHAR,
+    created_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_login_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (issuer, subject)
+);
+
+CREATE INDEX federated_identities_username_idx ON federated_identities (username);
+
+-- Logins started at /api/federated/login, awaiting the provider's callback
+CREATE TABLE federated_logins
+(
+    state_hash    VARCHAR(64) PRIMARY KEY,
+    nonce         VARCHAR     NOT NULL,
+    -- PKCE
+    code_verifier VARCHAR     NOT NULL,
+    -- frontend URL to send the token to, instead of answering with JSON
+    return_to     VARCHAR,
+    expires_at    TIMESTAMP   NOT NULL,
+    created_at    TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
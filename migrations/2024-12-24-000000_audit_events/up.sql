@@ -0,0 +1,32 @@
+-- Security audit log of authentication and data-changing events; rows are never changed or removed
+CREATE TABLE audit_events
+(
+    id          BIGSERIAL PRIMARY KEY,
+    -- e.g., `login`, `login_failed`, `logout`, `token_revoked`, `profile_upserted`
+    event       VARCHAR   NOT NULL,
+    -- username, or client id for client authentication, if known
+    actor       VARCHAR,
+    ip          VARCHAR,
+    user_agent  VARCHAR,
+    request_id  VARCHAR   NOT NULL,
+    method      VARCHAR   NOT NULL,
+    path        VARCHAR   NOT NULL,
+    status      INT4      NOT NULL,
+    created_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX audit_events_actor_idx ON audit_events (actor, id);
+CREATE INDEX audit_events_event_idx ON audit_events (event, id);
+
+CREATE FUNCTION audit_events_append_only() RETURNS TRIGGER AS
+$$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE OR DELETE
+    ON audit_events
+    FOR EACH ROW
+EXECUTE FUNCTION audit_events_append_only();
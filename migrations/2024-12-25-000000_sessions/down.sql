@@ -0,0 +1,4 @@
+ALTER TABLE access_tokens
+    DROP COLUMN last_used_at,
+    DROP COLUMN user_agent,
+    DROP COLUMN ip;
@@ -0,0 +1,5 @@
+-- Where each token was issued to and last used from, so users can tell their sessions apart
+ALTER TABLE access_tokens
+    ADD COLUMN ip           VARCHAR,
+    ADD COLUMN user_agent   VARCHAR,
+    ADD COLUMN last_used_at TIMESTAMP;
@@ -0,0 +1,4 @@
+DROP TABLE authorization_codes;
+
+ALTER TABLE confidential_clients
+    DROP COLUMN redirect_uris;
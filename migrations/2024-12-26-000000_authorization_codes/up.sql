@@ -0,0 +1,20 @@
+-- Where clients of the authorization code flow may have codes sent; space-separated
+ALTER TABLE confidential_clients
+    ADD COLUMN redirect_uris VARCHAR NOT NULL DEFAULT '';
+
+-- Codes issued at /api/authorise, awaiting exchange at /api/token
+CREATE TABLE authorization_codes
+(
+    code_hash      VARCHAR(64) PRIMARY KEY,
+    client_id      VARCHAR     NOT NULL,
+    username       VARCHAR     NOT NULL,
+    redirect_uri   VARCHAR     NOT NULL,
+    -- the scopes the user approved
+    scopes         VARCHAR     NOT NULL,
+    -- PKCE, S256
+    code_challenge VARCHAR     NOT NULL,
+    -- echoed in the ID token
+    nonce          VARCHAR,
+    expires_at     TIMESTAMP   NOT NULL,
+    created_at     TIMESTAMP   NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,170 @@
+//! Security audit log: which requests are audited, and the middleware recording them
+
+use actix_web::HttpMessage;
+
+use crate::auth::token::{
+    bytes_to_payload, client_credentials, generate_token, TokenForm, TOKEN_PATH,
+};
+use crate::auth::AuthenticatedUser;
+use crate::models::audit_event::{AuditEvent, NewAuditEvent};
+use crate::DbPool;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Logins are audited as this on success, [`LOGIN_FAILED`] on `400`/`401`, and
+/// [`LOGIN_THROTTLED`] when refused for too many failures
+pub const LOGIN: &str = "login";
+pub const LOGIN_FAILED: &str = "login_failed";
+pub const LOGIN_THROTTLED: &str = "login_throttled";
+
+/// Who made the request, for handlers that only learn it as they run, like federated login
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditActor(pub String);
+
+/// Audit event of a `method` `path` request, or `None` if it is not audited. Every event but
+/// [`LOGIN`] is only recorded when the request succeeds.
+pub fn event_for(method: &actix_web::http::Method, path: &str) -> Option<&'static str> {
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').skip(1).collect();
+    let event = match (method.as_str(), segments.as_slice()) {
+        ("POST", ["api", "token"]) | ("GET", ["api", "federated", "callback"]) => LOGIN,
+        ("POST", ["secured", "logout"]) => "logout",
+        ("DELETE", ["secured", "sessions", _]) => "session_revoked",
+        ("DELETE", ["secured", "sessions"]) => "other_sessions_revoked",
+        ("POST", ["api", "token", "revoke"]) => "token_revoked",
+        ("POST", ["api", "register"]) => "registered",
+        ("POST", ["api", "reset_password"]) => "password_reset",
+        ("POST", ["secured", "api_keys"]) => "api_key_created",
+        ("DELETE", ["secured", "api_keys", _]) => "api_key_revoked",
+        ("POST", ["secured", "clients"]) => "client_registered",
+        ("POST", ["secured", "clients", _, "secret"]) => "client_secret_rotated",
+        ("DELETE", ["secured", "clients", _]) => "client_revoked",
+        ("POST", ["secured", "mfa", "totp", "confirm"]) => "mfa_enabled",
+        ("POST", ["secured", "mfa", "totp", "disable"]) => "mfa_disabled",
+        ("PUT", ["api", "admin", "users", _, "role"]) => "role_changed",
+        ("POST", ["api", "admin", "users", _, "disable"]) => "user_disabled",
+        ("POST", ["api", "admin", "users", _, "enable"]) => "user_enabled",
+        ("POST", ["api", "admin", "users", _, "credits", "reset"]) => "credits_reset",
+        ("GET" | "HEAD", _) => return None,
+        (_, ["api", "v0", "model", ..]) => "person_model_upserted",
+        (_, ["api", "v0", "profile", ..]) => "profile_upserted",
+        _ => return None,
+    };
+    Some(event)
+}
+
+/// What to record for `event` given the response `status`, if anything
+pub fn outcome(event: &'static str, status: actix_web::http::StatusCode) -> Option<&'static str> {
+    use actix_web::http::StatusCode;
+
+    match (event, status) {
+        (_, status) if status.is_success() || status.is_redirection() => Some(event),
+        (LOGIN, StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED) => Some(LOGIN_FAILED),
+        (LOGIN, StatusCode::TOO_MANY_REQUESTS) => Some(LOGIN_THROTTLED),
+        _ => None,
+    }
+}
+
+/// Id of the request: the caller's `X-Request-Id` if sensible, else a generated one
+fn request_id(req: &actix_web::dev::ServiceRequest) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 64 && id.bytes().all(|b| b.is_ascii_graphic()))
+        .map(str::to_owned)
+        .unwrap_or_else(|| generate_token()[..16].to_owned())
+}
+
+/// Middleware echoing or assigning every request an `X-Request-Id`, and recording the
+/// [`event_for`] it, if any, in `audit_events` along with actor, IP and user agent.
+///
+/// The actor is an [`AuditActor`] or [`AuthenticatedUser`] in the request's extensions, else
+/// the `username` or client id of an `/api/token` request, else a client id sent with HTTP Basic
+/// authentication. A failure to record is logged, not passed on to the caller.
+pub async fn audit(
+    mut req: actix_web::dev::ServiceRequest,
+    next: actix_web::middleware::Next<impl actix_web::body::MessageBody + 'static>,
+) -> Result<actix_web::dev::ServiceResponse, actix_web::Error> {
+    let request_id = request_id(&req);
+    let mut event = event_for(req.method(), req.path());
+    let authorization = req
+        .headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let mut actor = client_credentials(authorization.as_deref(), None, None).map(|(id, _)| id);
+    if event.is_some() && req.method() == actix_web::http::Method::POST && req.path() == TOKEN_PATH
+    {
+        let body = req.extract::<actix_web::web::Bytes>().await?;
+        let form = serde_urlencoded::from_bytes::<TokenForm>(&body).ok();
+        req.set_payload(bytes_to_payload(body));
+        match form {
+            // rotating a refresh token is not a login
+            Some(form) if form.grant_type == "refresh_token" => event = None,
+            Some(form) => actor = form.username.or(form.client_id).or(actor),
+            None => {}
+        }
+    }
+    let http_req = req.request().clone();
+    let method = req.method().to_string();
+    let path = req.path().to_owned();
+    let ip = crate::proxy::client_ip(req.request());
+    let user_agent = req
+        .headers()
+        .get(actix_web::http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+
+    let mut res = match next.call(req).await {
+        Ok(res) => res.map_into_boxed_body(),
+        Err(err) => actix_web::dev::ServiceResponse::from_err(err, http_req.clone()),
+    };
+    if let Ok(value) = actix_web::http::header::HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(
+            actix_web::http::header::HeaderName::from_static(REQUEST_ID_HEADER),
+            value,
+        );
+    }
+    let Some(event) = event.and_then(|event| outcome(event, res.status())) else {
+        return Ok(res);
+    };
+    let pool = http_req.app_data::<actix_web::web::Data<DbPool>>().cloned();
+    let Some(pool) = pool else {
+        return Ok(res);
+    };
+    let actor = {
+        let extensions = http_req.extensions();
+        extensions
+            .get::<AuditActor>()
+            .map(|actor| actor.0.clone())
+            .or_else(|| {
+                extensions
+                    .get::<AuthenticatedUser>()
+                    .map(|user| user.username.clone())
+            })
+            .or(actor)
+    };
+    let status = res.status().as_u16() as i32;
+    let recorded = actix_web::web::block(move || {
+        let mut conn = pool.get().map_err(|err| err.to_string())?;
+        AuditEvent::insert(
+            &mut conn,
+            &NewAuditEvent {
+                event,
+                actor: actor.as_deref(),
+                ip: ip.as_deref(),
+                user_agent: user_agent.as_deref(),
+                request_id: &request_id,
+                method: &method,
+                path: &path,
+                status,
+            },
+        )
+        .map_err(|err| err.to_string())
+    })
+    .await
+    .map_err(|err| err.to_string())
+    .and_then(|recorded| recorded);
+    if let Err(err) = recorded {
+        log::error!("recording audit event {}: {}", event, err);
+    }
+    Ok(res)
+}
@@ -0,0 +1,177 @@
+use crate::auth::token::generate_token;
+use crate::errors::ServeReplicaError;
+use crate::mail::Email;
+use crate::models::access_token::hash_token;
+use crate::models::email_token::{EmailToken, NewEmailToken, PURPOSE_VERIFY_EMAIL};
+use crate::models::user_account::UserAccount;
+use crate::models::user_credential::UserCredential;
+use diesel::prelude::*;
+use rust_actix_diesel_auth_scaffold::schema::users;
+
+pub const MIN_PASSWORD_LEN: usize = 8;
+pub const MAX_USERNAME_LEN: usize = 64;
+
+/// Settings of self-registration, email verification and password reset
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountConfig {
+    /// base URL of the frontend, which links in emails point into
+    pub public_url: String,
+    pub verification_ttl: std::time::Duration,
+    pub reset_ttl: std::time::Duration,
+}
+
+impl Default for AccountConfig {
+    fn default() -> Self {
+        Self {
+            public_url: String::from("http://localhost:3000"),
+            verification_ttl: std::time::Duration::from_secs(24 * 60 * 60),
+            reset_ttl: std::time::Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+pub fn validate_username(username: &str) -> Result<(), ServeReplicaError> {
+    if username.is_empty()
+        || username.len() > MAX_USERNAME_LEN
+        || !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err(ServeReplicaError::BadRequest(format!(
+            "username must be 1 to {} letters, digits, '_', '-' or '.'",
+            MAX_USERNAME_LEN
+        )));
+    }
+    Ok(())
+}
+
+pub fn validate_password(password: &str) -> Result<(), ServeReplicaError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(ServeReplicaError::BadRequest(format!(
+            "password must be at least {} characters",
+            MIN_PASSWORD_LEN
+        )));
+    }
+    Ok(())
+}
+
+pub fn validate_email(email: &str) -> Result<(), ServeReplicaError> {
+    match email.split_once('@') {
+        Some((local, domain))
+            if !local.is_empty()
+                && domain.contains('.')
+                && !email.contains(char::is_whitespace) =>
+        {
+            Ok(())
+        }
+        _ => Err(ServeReplicaError::BadRequest(String::from("invalid email"))),
+    }
+}
+
+/// Query for `username` in the scaffold's own `users` table, whose rows the password grant
+/// issues tokens for
+pub fn scaffold_user(
+    username: &str,
+) -> users::BoxedQuery<'_, diesel::pg::Pg, diesel::sql_types::Text> {
+    users::table
+        .select(users::username)
+        .filter(users::username.eq(username))
+        .into_boxed()
+}
+
+/// Whether `username` belongs to anyone already: a registered credential, a user account, or a
+/// user of the scaffold
+pub fn username_taken(conn: &mut diesel::PgConnection, username: &str) -> QueryResult<bool> {
+    Ok(UserCredential::find(conn, username)?.is_some()
+        || UserAccount::find(conn, username)?.is_some()
+        || scaffold_user(username)
+            .first::<String>(conn)
+            .optional()?
+            .is_some())
+}
+
+/// Store a new single-use token for `purpose`, returning the token to mail
+pub fn issue_email_token(
+    conn: &mut diesel::PgConnection,
+    username: &str,
+    purpose: &str,
+    ttl: std::time::Duration,
+) -> diesel::QueryResult<String> {
+    let token = generate_token();
+    EmailToken::insert(
+        conn,
+        &NewEmailToken {
+            token_hash: &hash_token(&token),
+            username,
+            purpose,
+        },
+        ttl,
+    )?;
+    Ok(token)
+}
+
+impl AccountConfig {
+    pub fn verification_email(&self, credential: &UserCredential, token: &str) -> Email {
+        Email {
+            to: credential.email.clone(),
+            subject: String::from("Verify your email"),
+            body: format!(
+                "Hi {},\n\nConfirm your email address by opening {}/verify_email?token={}\n\n\
+                 This link expires in {} hours.",
+                credential.username,
+                self.public_url.trim_end_matches('/'),
+                token,
+                self.verification_ttl.as_secs() / 3600
+            ),
+        }
+    }
+
+    pub fn reset_email(&self, credential: &UserCredential, token: &str) -> Email {
+        Email {
+            to: credential.email.clone(),
+            subject: String::from("Reset your password"),
+            body: format!(
+                "Hi {},\n\nChoose a new password at {}/reset_password?token={}\n\n\
+                 This link expires in {} minutes. If you did not ask to reset your password, \
+                 ignore this email.",
+                credential.username,
+                self.public_url.trim_end_matches('/'),
+                token,
+                self.reset_ttl.as_secs() / 60
+            ),
+        }
+    }
+
+    /// Tell the owner of `credential` that someone tried to register their email again, in
+    /// place of telling whoever tried
+    pub fn already_registered_email(&self, credential: &UserCredential) -> Email {
+        Email {
+            to: credential.email.clone(),
+            subject: String::from("You already have an account"),
+            body: format!(
+                "Hi {},\n\nSomeone, hopefully you, tried to register a new account with this email \
+                 address, which already belongs to yours. Sign in as {}, or if you forgot your \
+                 password, reset it at {}/forgot_password\n\n\
+                 If this was not you, ignore this email.",
+                credential.username,
+                credential.username,
+                self.public_url.trim_end_matches('/'),
+            ),
+        }
+    }
+
+    /// Issue a verification token for `credential` and compose its email
+    pub fn start_verification(
+        &self,
+        conn: &mut diesel::PgConnection,
+        credential: &UserCredential,
+    ) -> diesel::QueryResult<Email> {
+        let token = issue_email_token(
+            conn,
+            &credential.username,
+            PURPOSE_VERIFY_EMAIL,
+            self.verification_ttl,
+        )?;
+        Ok(self.verification_email(credential, &token))
+    }
+}
@@ -0,0 +1,283 @@
+//! The authorization code flow with PKCE: `/api/authorise` signs the user in and has them
+//! approve the client's scopes, then sends a single-use code to the client's registered
+//! redirect URI, which the client exchanges with its `code_verifier` at `/api/token`
+
+use crate::auth::scopes::{parse_scopes, Scope};
+use crate::errors::ServeReplicaError;
+use crate::models::confidential_client::ConfidentialClient;
+
+pub const AUTHORISE_PATH: &str = "/api/authorise";
+/// How long a code waits to be exchanged
+pub const CODE_TTL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+/// The only PKCE method accepted; `plain` would send the verifier in the clear
+pub const CODE_CHALLENGE_METHOD: &str = "S256";
+
+/// An authorization request, as the client sends the user to `/api/authorise`
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::IntoParams,
+    utoipa::ToSchema,
+)]
+#[into_params(parameter_in = Query)]
+pub struct AuthoriseQuery {
+    /// must be `code`
+    pub response_type: String,
+    pub client_id: String,
+    /// exactly one of the client's registered `redirect_uris`
+    pub redirect_uri: String,
+    /// space-separated scopes, within the client's; defaults to all user scopes
+    pub scope: Option<String>,
+    /// echoed back to `redirect_uri`
+    pub state: Option<String>,
+    /// base64url SHA-256 of the client's `code_verifier`
+    pub code_challenge: Option<String>,
+    /// must be `S256`
+    pub code_challenge_method: Option<String>,
+    /// echoed in the ID token, if `openid` is approved
+    pub nonce: Option<String>,
+}
+
+/// The sign-in form `/api/authorise` shows, posted back to it with the request's parameters
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, utoipa::ToSchema)]
+pub struct AuthoriseForm {
+    #[serde(flatten)]
+    pub query: AuthoriseQuery,
+    pub username: String,
+    pub password: String,
+    /// for users with TOTP enabled: the current code, or a recovery code
+    pub otp: Option<String>,
+}
+
+/// An error sent back to the client's redirect URI, as `error` and `error_description`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectError {
+    pub error: &'static str,
+    pub description: String,
+}
+
+impl RedirectError {
+    fn new(error: &'static str, description: &str) -> Self {
+        Self {
+            error,
+            description: description.to_owned(),
+        }
+    }
+}
+
+/// Check a redirect URI a client registers: absolute, with no fragment, and `https` unless it
+/// is on the user's own machine
+pub fn validate_redirect_uri(redirect_uri: &str) -> Result<(), ServeReplicaError> {
+    let invalid = || {
+        ServeReplicaError::BadRequest(format!(
+            "invalid_redirect_uri: {} must be an https URI without a fragment, or http on localhost",
+            redirect_uri
+        ))
+    };
+    let uri = redirect_uri
+        .parse::<actix_web::http::Uri>()
+        .map_err(|_| invalid())?;
+    let loopback = matches!(
+        uri.host(),
+        Some("localhost" | "127.0.0.1" | "[::1]" | "::1")
+    );
+    let allowed = match uri.scheme_str() {
+        Some("https") => uri.host().is_some(),
+        Some("http") => loopback,
+        _ => false,
+    };
+    if !allowed || redirect_uri.contains('#') {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+/// The registered client `query` is from, if it may have codes sent to `query.redirect_uri`.
+///
+/// Failing this the user is told so, rather than sent to a redirect URI nobody vouched for.
+pub fn check_client(
+    client: Option<ConfidentialClient>,
+    query: &AuthoriseQuery,
+) -> Result<ConfidentialClient, ServeReplicaError> {
+    client
+        .filter(|client| client.allows_redirect(&query.redirect_uri))
+        .ok_or_else(|| {
+            ServeReplicaError::BadRequest(String::from(
+                "invalid_request: unknown client_id, or redirect_uri not registered for it",
+            ))
+        })
+}
+
+/// The scopes `query` asks `client` be granted, once the rest of the request checks out
+pub fn requested_scopes(
+    client: &ConfidentialClient,
+    query: &AuthoriseQuery,
+) -> Result<Vec<Scope>, RedirectError> {
+    if query.response_type != "code" {
+        return Err(RedirectError::new(
+            "unsupported_response_type",
+            "response_type must be code",
+        ));
+    }
+    if query.code_challenge_method.as_deref() != Some(CODE_CHALLENGE_METHOD) {
+        return Err(RedirectError::new(
+            "invalid_request",
+            "code_challenge_method must be S256",
+        ));
+    }
+    // a base64url SHA-256 digest, without padding
+    if !query.code_challenge.as_deref().is_some_and(|challenge| {
+        challenge.len() == 43
+            && challenge
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+    }) {
+        return Err(RedirectError::new(
+            "invalid_request",
+            "code_challenge must be a base64url SHA-256 digest",
+        ));
+    }
+    let scopes = match &query.scope {
+        Some(scope) => {
+            parse_scopes(scope).map_err(|_| RedirectError::new("invalid_scope", "unknown scope"))?
+        }
+        None => Scope::user_defaults(),
+    };
+    let allowed = parse_scopes(&client.scopes).unwrap_or_default();
+    if scopes.is_empty() || !scopes.iter().all(|scope| allowed.contains(scope)) {
+        return Err(RedirectError::new(
+            "invalid_scope",
+            "scopes must be non-empty and within those registered for the client",
+        ));
+    }
+    Ok(scopes)
+}
+
+/// PKCE S256 `code_challenge` of `code_verifier`
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    use base64::Engine;
+    use sha2::Digest;
+
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .encode(sha2::Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// Whether `code_verifier` is the one `code_challenge` was derived from, per RFC 7636 S256
+pub fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+    let unreserved = |byte: u8| byte.is_ascii_alphanumeric() || b"-._~".contains(&byte);
+    (43..=128).contains(&code_verifier.len())
+        && code_verifier.bytes().all(unreserved)
+        && pkce_challenge(code_verifier) == code_challenge
+}
+
+/// `redirect_uri` with `params` added to its query
+pub fn redirect_location(redirect_uri: &str, params: &[(&str, &str)]) -> String {
+    let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+    format!(
+        "{}{}{}",
+        redirect_uri,
+        separator,
+        serde_urlencoded::to_string(params).unwrap_or_default()
+    )
+}
+
+/// Where to send the user with `code`, or with an `error`, and the request's `state`
+pub fn code_location(query: &AuthoriseQuery, outcome: Result<&str, &RedirectError>) -> String {
+    let mut params = match outcome {
+        Ok(code) => vec![("code", code)],
+        Err(err) => vec![
+            ("error", err.error),
+            ("error_description", err.description.as_str()),
+        ],
+    };
+    if let Some(state) = &query.state {
+        params.push(("state", state));
+    }
+    redirect_location(&query.redirect_uri, &params)
+}
+
+fn escape_html(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '&' => String::from("&amp;"),
+            '<' => String::from("&lt;"),
+            '>' => String::from("&gt;"),
+            '"' => String::from("&quot;"),
+            '\'' => String::from("&#39;"),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// The sign-in page asking the user to let `client` have `scopes`, carrying `query` along
+pub fn sign_in_page(
+    client: &ConfidentialClient,
+    query: &AuthoriseQuery,
+    scopes: &[Scope],
+    error: Option<&str>,
+) -> String {
+    let hidden = serde_urlencoded::to_string(query)
+        .ok()
+        .and_then(|encoded| serde_urlencoded::from_str::<Vec<(String, String)>>(&encoded).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| {
+            format!(
+                r#"<input type="hidden" name="{}" value="{}">"#,
+                escape_html(&name),
+                escape_html(&value)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let scopes = Scope::ALL
+        .iter()
+        .filter(|(scope, _)| scopes.contains(scope))
+        .map(|(scope, description)| {
+            format!(
+                "<li><code>{}</code>: {}</li>",
+                scope,
+                escape_html(&description.replace('`', ""))
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let error = error
+        .map(|error| format!(r#"<p role="alert">{}</p>"#, escape_html(error)))
+        .unwrap_or_default();
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Sign in to {name}</title>
+</head>
+<body>
+<h1>Sign in to {name}</h1>
+<p>{name} would like to:</p>
+<ul>
+{scopes}
+</ul>
+{error}
+<form method="post" action="{action}">
+{hidden}
+<label>Username <input name="username" autocomplete="username" required></label>
+<label>Password <input name="password" type="password" autocomplete="current-password" required></label>
+<label>One-time code, if enabled <input name="otp" autocomplete="one-time-code"></label>
+<button type="submit">Allow</button>
+</form>
+</body>
+</html>
+"#,
+        name = escape_html(&client.name),
+        scopes = scopes,
+        error = error,
+        action = AUTHORISE_PATH,
+        hidden = hidden,
+    )
+}
@@ -0,0 +1,166 @@
+use actix_web::HttpMessage;
+
+use crate::auth::roles::Role;
+use crate::auth::scopes::{parse_scopes, Scope};
+use crate::auth::{AuthenticatedUser, Credential};
+use crate::errors::ServeReplicaError;
+use crate::models::access_token::AccessToken;
+use crate::models::api_key::{ApiKey, API_KEY_PREFIX};
+use crate::models::confidential_client::ConfidentialClient;
+use crate::models::user_account::UserAccount;
+use crate::DbPool;
+
+/// Bearer validator for serve-replica's protected scopes.
+///
+/// Tokens serve-replica minted itself (e.g., by refreshing) are checked against
+/// `access_tokens` alone. Others are delegated to
+/// `rust_actix_diesel_auth_scaffold::middleware::bearer::validator` (which stores the token's
+/// username in the request extensions), and must also not be revoked or expired in
+/// `access_tokens`. Uses of tokens in `access_tokens` are recorded there for `/secured/sessions`.
+/// Personal API keys, told apart by [`API_KEY_PREFIX`], are checked against
+/// `api_keys`. Disabled users are turned away whichever way they authenticate; everyone else
+/// is attached to the request as an [`AuthenticatedUser`] with the credential's scopes.
+pub async fn validator(
+    req: actix_web::dev::ServiceRequest,
+    credentials: actix_web_httpauth::extractors::bearer::BearerAuth,
+) -> Result<actix_web::dev::ServiceRequest, (actix_web::Error, actix_web::dev::ServiceRequest)> {
+    let Some(pool) = req.app_data::<actix_web::web::Data<DbPool>>().cloned() else {
+        return Err((
+            ServeReplicaError::Database(String::from("no database pool")).into(),
+            req,
+        ));
+    };
+    let token = credentials.token().to_owned();
+    if token.starts_with(API_KEY_PREFIX) {
+        let (find_pool, key) = (pool.clone(), token.clone());
+        match actix_web::web::block(move || {
+            let mut conn = find_pool.get()?;
+            ApiKey::authenticate(&mut conn, &key).map_err(ServeReplicaError::from)
+        })
+        .await
+        {
+            Ok(Ok(Some(api_key))) => {
+                return authenticate(
+                    req,
+                    pool,
+                    api_key.username,
+                    parse_scopes(&api_key.scopes),
+                    Credential::ApiKey,
+                )
+                .await;
+            }
+            // an ordinary token may happen to start with the prefix too
+            Ok(Ok(None)) => {}
+            Ok(Err(err)) => return Err((err.into(), req)),
+            Err(err) => return Err((err.into(), req)),
+        }
+    }
+    let find_pool = pool.clone();
+    let ip = crate::proxy::client_ip(req.request());
+    let record = match actix_web::web::block(move || {
+        let mut conn = find_pool.get()?;
+        let record = AccessToken::find(&mut conn, &token)?;
+        if let Some(record) = record.as_ref().filter(|record| record.is_active()) {
+            AccessToken::touch(&mut conn, record.id, ip.as_deref())?;
+        }
+        Ok::<_, ServeReplicaError>(record)
+    })
+    .await
+    {
+        Ok(Ok(record)) => record,
+        Ok(Err(err)) => return Err((err.into(), req)),
+        Err(err) => return Err((err.into(), req)),
+    };
+    let unauthorised = |msg: &str| -> actix_web::Error {
+        ServeReplicaError::Unauthorised(String::from(msg)).into()
+    };
+
+    match record {
+        Some(record) if !record.is_active() => Err((unauthorised("token revoked or expired"), req)),
+        Some(record) if record.local => {
+            let credential = match record
+                .family_id
+                .as_deref()
+                .and_then(ConfidentialClient::of_token_family)
+            {
+                Some(_) => Credential::ClientToken,
+                None => Credential::UserToken,
+            };
+            match record.username {
+                Some(username) => {
+                    let scopes = parse_scopes(&record.scopes);
+                    authenticate(req, pool, username, scopes, credential).await
+                }
+                None => Err((unauthorised("token has no user"), req)),
+            }
+        }
+        record => {
+            let req =
+                rust_actix_diesel_auth_scaffold::middleware::bearer::validator(req, credentials)
+                    .await?;
+            let Some(username) = req.extensions().get::<String>().cloned() else {
+                return Err((unauthorised("no authenticated user"), req));
+            };
+            let scopes = match record {
+                Some(record) => {
+                    if record.username.is_none() {
+                        let (id, username, pool) = (record.id, username.clone(), pool.clone());
+                        // best effort; the username is only informational here
+                        let _ = actix_web::web::block(move || {
+                            let mut conn = pool.get()?;
+                            AccessToken::set_username(&mut conn, id, &username)
+                                .map_err(ServeReplicaError::from)
+                        })
+                        .await;
+                    }
+                    parse_scopes(&record.scopes)
+                }
+                // issued before tokens carried scopes, when every token could do everything
+                None => Ok(Scope::user_defaults()),
+            };
+            authenticate(req, pool, username, scopes, Credential::UserToken).await
+        }
+    }
+}
+
+/// Look up `username`'s account and, unless it is disabled, attach them to `req`, both as an
+/// [`AuthenticatedUser`] and as the scaffold's username `String`
+async fn authenticate(
+    req: actix_web::dev::ServiceRequest,
+    pool: actix_web::web::Data<DbPool>,
+    username: String,
+    scopes: Result<Vec<Scope>, ServeReplicaError>,
+    credential: Credential,
+) -> Result<actix_web::dev::ServiceRequest, (actix_web::Error, actix_web::dev::ServiceRequest)> {
+    let scopes = match scopes {
+        Ok(scopes) => scopes,
+        Err(err) => return Err((err.into(), req)),
+    };
+    let account = match actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        UserAccount::touch(&mut conn, &username).map_err(ServeReplicaError::from)
+    })
+    .await
+    {
+        Ok(Ok(account)) => account,
+        Ok(Err(err)) => return Err((err.into(), req)),
+        Err(err) => return Err((err.into(), req)),
+    };
+    if account.disabled_at.is_some() {
+        return Err((
+            ServeReplicaError::Unauthorised(String::from("account disabled")).into(),
+            req,
+        ));
+    }
+    // an unrecognised role grants nothing beyond a plain user's
+    let role = account.role.parse().unwrap_or(Role::User);
+    // as the scaffold's validator would, for the replica-backend routes that read it
+    req.extensions_mut().insert(account.username.clone());
+    req.extensions_mut().insert(AuthenticatedUser {
+        username: account.username,
+        scopes,
+        role,
+        credential,
+    });
+    Ok(req)
+}
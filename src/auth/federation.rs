@@ -0,0 +1,395 @@
+use crate::auth::account::{username_taken, MAX_USERNAME_LEN};
+use crate::auth::authorise::pkce_challenge;
+use crate::errors::ServeReplicaError;
+use crate::models::federated_identity::{FederatedIdentity, NewFederatedIdentity};
+use crate::models::user_account::UserAccount;
+use crate::models::user_credential::UserCredential;
+
+pub const CALLBACK_PATH: &str = "/api/federated/callback";
+/// Cookie holding a login's `state` in the browser that started it
+pub const STATE_COOKIE: &str = "federated_state";
+/// leeway for the provider's clock when checking `exp`
+pub const CLOCK_SKEW_SECS: i64 = 60;
+
+/// The external OpenID Connect provider users can sign in with
+#[derive(Debug, Clone, PartialEq)]
+pub struct FederationConfig {
+    /// e.g., `https://accounts.google.com`; its metadata is at `/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// space-separated scopes to request
+    pub scope: String,
+    /// [`CALLBACK_PATH`] under serve-replica's public URL; must be registered with the provider
+    pub redirect_uri: String,
+    /// how long a user has to sign in at the provider
+    pub login_ttl: std::time::Duration,
+}
+
+impl FederationConfig {
+    pub fn new(issuer: &str, client_id: &str, client_secret: &str, base_url: &str) -> Self {
+        Self {
+            issuer: issuer.trim_end_matches('/').to_owned(),
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            scope: String::from("openid email profile"),
+            redirect_uri: format!("{}{}", base_url.trim_end_matches('/'), CALLBACK_PATH),
+            login_ttl: std::time::Duration::from_secs(10 * 60),
+        }
+    }
+
+    /// Cookie binding `state` to the browser being sent to the provider, so that a callback
+    /// URL from someone else's login cannot sign it in as them. Only sent to [`CALLBACK_PATH`],
+    /// and `Lax`, as the provider's redirect back is a cross-site navigation.
+    pub fn state_cookie(&self, state: &str) -> actix_web::cookie::Cookie<'static> {
+        actix_web::cookie::Cookie::build(STATE_COOKIE, state.to_owned())
+            .path(CALLBACK_PATH)
+            .http_only(true)
+            .secure(self.redirect_uri.starts_with("https://"))
+            .same_site(actix_web::cookie::SameSite::Lax)
+            .max_age(actix_web::cookie::time::Duration::seconds(
+                self.login_ttl.as_secs() as i64,
+            ))
+            .finish()
+    }
+}
+
+/// The parts of the provider's discovery document serve-replica uses
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct IdpMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+struct IdpTokenResponse {
+    id_token: Option<String>,
+}
+
+/// `aud` is either one client id or several
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    pub fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+/// Claims of the provider's ID token that serve-replica reads
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct ExternalClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: Audience,
+    /// authorized party, which must be us when there are several audiences
+    pub azp: Option<String>,
+    pub exp: i64,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub preferred_username: Option<String>,
+}
+
+fn login_failed(reason: impl std::fmt::Display) -> ServeReplicaError {
+    ServeReplicaError::Unauthorised(format!("federated login failed: {}", reason))
+}
+
+fn upstream(err: impl std::fmt::Display) -> ServeReplicaError {
+    ServeReplicaError::Upstream(err.to_string())
+}
+
+/// Check the signature and claims of `id_token` against `keys`, the provider's JWKS `keys`
+pub fn verify_id_token(
+    id_token: &str,
+    keys: &[serde_json::Value],
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+    now: i64,
+) -> Result<ExternalClaims, ServeReplicaError> {
+    use base64::Engine;
+    use rsa::signature::Verifier;
+
+    let decode = |part: &str| {
+        base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|_| login_failed("malformed id_token"))
+    };
+    let (signing_input, signature) = id_token
+        .rsplit_once('.')
+        .ok_or_else(|| login_failed("malformed id_token"))?;
+    let (header, claims) = signing_input
+        .split_once('.')
+        .ok_or_else(|| login_failed("malformed id_token"))?;
+    let header: serde_json::Value =
+        serde_json::from_slice(&decode(header)?).map_err(login_failed)?;
+    if header["alg"] != crate::auth::oidc::ID_TOKEN_ALG {
+        return Err(login_failed("id_token must be signed with RS256"));
+    }
+    let key = keys
+        .iter()
+        .filter(|key| key["kty"] == "RSA")
+        .find(|key| header["kid"].is_null() || key["kid"] == header["kid"])
+        .ok_or_else(|| login_failed("id_token signed with an unknown key"))?;
+    let component = |name: &str| {
+        key[name]
+            .as_str()
+            .ok_or_else(|| login_failed("malformed JWKS"))
+            .and_then(decode)
+            .map(|bytes| rsa::BigUint::from_bytes_be(&bytes))
+    };
+    let public_key =
+        rsa::RsaPublicKey::new(component("n")?, component("e")?).map_err(login_failed)?;
+    let signature =
+        rsa::pkcs1v15::Signature::try_from(decode(signature)?.as_slice()).map_err(login_failed)?;
+    rsa::pkcs1v15::VerifyingKey::<sha2::Sha256>::new(public_key)
+        .verify(signing_input.as_bytes(), &signature)
+        .map_err(|_| login_failed("invalid id_token signature"))?;
+
+    let claims: ExternalClaims = serde_json::from_slice(&decode(claims)?).map_err(login_failed)?;
+    if claims.iss != issuer {
+        return Err(login_failed("id_token from another issuer"));
+    }
+    if !claims.aud.contains(client_id) || claims.azp.as_deref().is_some_and(|azp| azp != client_id)
+    {
+        return Err(login_failed("id_token for another client"));
+    }
+    if claims.exp + CLOCK_SKEW_SECS <= now {
+        return Err(login_failed("id_token expired"));
+    }
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(login_failed("id_token nonce mismatch"));
+    }
+    Ok(claims)
+}
+
+/// Client of the external provider; discovery and keys are fetched once and cached
+#[derive(Debug)]
+pub struct FederatedProvider {
+    pub config: FederationConfig,
+    metadata: std::sync::Mutex<Option<IdpMetadata>>,
+    keys: std::sync::Mutex<Vec<serde_json::Value>>,
+}
+
+impl FederatedProvider {
+    pub fn new(config: FederationConfig) -> Self {
+        Self {
+            config,
+            metadata: Default::default(),
+            keys: Default::default(),
+        }
+    }
+
+    pub async fn metadata(&self) -> Result<IdpMetadata, ServeReplicaError> {
+        if let Some(metadata) = self.metadata.lock().unwrap().clone() {
+            return Ok(metadata);
+        }
+        let metadata: IdpMetadata = awc::Client::default()
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                self.config.issuer
+            ))
+            .send()
+            .await
+            .map_err(upstream)?
+            .json()
+            .await
+            .map_err(upstream)?;
+        if metadata.issuer.trim_end_matches('/') != self.config.issuer {
+            return Err(upstream(format!(
+                "provider claims to be {}, not {}",
+                metadata.issuer, self.config.issuer
+            )));
+        }
+        *self.metadata.lock().unwrap() = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// The provider's keys; fetched again when `kid` is new to us, as providers rotate keys
+    async fn keys(&self, kid: Option<&str>) -> Result<Vec<serde_json::Value>, ServeReplicaError> {
+        let cached = self.keys.lock().unwrap().clone();
+        let known = |keys: &[serde_json::Value]| {
+            !keys.is_empty() && kid.is_none_or(|kid| keys.iter().any(|key| key["kid"] == kid))
+        };
+        if known(&cached) {
+            return Ok(cached);
+        }
+        let jwks: serde_json::Value = awc::Client::default()
+            .get(self.metadata().await?.jwks_uri)
+            .send()
+            .await
+            .map_err(upstream)?
+            .json()
+            .await
+            .map_err(upstream)?;
+        let keys = jwks["keys"].as_array().cloned().unwrap_or_default();
+        *self.keys.lock().unwrap() = keys.clone();
+        Ok(keys)
+    }
+
+    /// Where to send the user to sign in
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<String, ServeReplicaError> {
+        let endpoint = self.metadata().await?.authorization_endpoint;
+        let query = serde_urlencoded::to_string([
+            ("response_type", "code"),
+            ("client_id", self.config.client_id.as_str()),
+            ("redirect_uri", self.config.redirect_uri.as_str()),
+            ("scope", self.config.scope.as_str()),
+            ("state", state),
+            ("nonce", nonce),
+            ("code_challenge", pkce_challenge(code_verifier).as_str()),
+            ("code_challenge_method", "S256"),
+        ])
+        .map_err(upstream)?;
+        let separator = if endpoint.contains('?') { '&' } else { '?' };
+        Ok(format!("{}{}{}", endpoint, separator, query))
+    }
+
+    /// Exchange the callback's `code` for the provider's verified ID token claims
+    pub async fn exchange(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<ExternalClaims, ServeReplicaError> {
+        let metadata = self.metadata().await?;
+        let mut response = awc::Client::default()
+            .post(&metadata.token_endpoint)
+            .basic_auth(&self.config.client_id, &self.config.client_secret)
+            .send_form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.config.redirect_uri.as_str()),
+                ("code_verifier", code_verifier),
+            ])
+            .await
+            .map_err(upstream)?;
+        if !response.status().is_success() {
+            let body = response.body().await.unwrap_or_default();
+            return Err(login_failed(format!(
+                "provider responded with {}: {}",
+                response.status(),
+                String::from_utf8_lossy(&body)
+            )));
+        }
+        let token: IdpTokenResponse = response.json().await.map_err(upstream)?;
+        let id_token = token
+            .id_token
+            .ok_or_else(|| login_failed("provider returned no id_token"))?;
+        let kid = id_token
+            .split('.')
+            .next()
+            .and_then(|header| {
+                use base64::Engine;
+                base64::engine::general_purpose::URL_SAFE_NO_PAD
+                    .decode(header)
+                    .ok()
+            })
+            .and_then(|header| serde_json::from_slice::<serde_json::Value>(&header).ok())
+            .and_then(|header| header["kid"].as_str().map(str::to_owned));
+        verify_id_token(
+            &id_token,
+            &self.keys(kid.as_deref()).await?,
+            &metadata.issuer,
+            &self.config.client_id,
+            nonce,
+            chrono::Utc::now().timestamp(),
+        )
+    }
+}
+
+/// A valid local username derived from the provider's `preferred_username` or email
+pub fn candidate_username(claims: &ExternalClaims) -> String {
+    let wanted = claims
+        .preferred_username
+        .as_deref()
+        .or_else(|| {
+            claims
+                .email
+                .as_deref()
+                .and_then(|email| email.split('@').next())
+        })
+        .unwrap_or_default();
+    let username: String = wanted
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        .take(MAX_USERNAME_LEN - 8)
+        .collect();
+    if username.is_empty() {
+        String::from("user")
+    } else {
+        username
+    }
+}
+
+/// The local user of the provider's `claims`: the one already linked; else the one who
+/// registered with the same email, if both sides verified it; else a new user.
+pub fn link_or_create(
+    conn: &mut diesel::PgConnection,
+    issuer: &str,
+    claims: &ExternalClaims,
+) -> Result<String, ServeReplicaError> {
+    use diesel::Connection;
+
+    let email = claims.email.as_deref().map(str::to_lowercase);
+    conn.transaction(|conn| {
+        if let Some(identity) =
+            FederatedIdentity::record_login(conn, issuer, &claims.sub, email.as_deref())?
+        {
+            return Ok(identity.username);
+        }
+        let registered = match (&email, claims.email_verified) {
+            (Some(email), Some(true)) => UserCredential::find_by_email(conn, email)?
+                .filter(|credential| credential.email_verified_at.is_some()),
+            _ => None,
+        };
+        let username = match registered {
+            Some(credential) => {
+                log::info!(
+                    "linking {} of {} to {}",
+                    claims.sub,
+                    issuer,
+                    credential.username
+                );
+                credential.username
+            }
+            None => {
+                let base = candidate_username(claims);
+                let mut username = base.clone();
+                let mut suffix = 1;
+                while username_taken(conn, &username)? {
+                    suffix += 1;
+                    username = format!("{}-{}", base, suffix);
+                }
+                UserAccount::touch(conn, &username)?;
+                log::info!("created {} for {} of {}", username, claims.sub, issuer);
+                username
+            }
+        };
+        FederatedIdentity::insert(
+            conn,
+            &NewFederatedIdentity {
+                issuer,
+                subject: &claims.sub,
+                username: &username,
+                email: email.as_deref(),
+            },
+        )?;
+        Ok::<_, ServeReplicaError>(username)
+    })
+}
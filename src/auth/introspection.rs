@@ -0,0 +1,175 @@
+use crate::errors::ServeReplicaError;
+use crate::models::access_token::AccessToken;
+use crate::models::api_key::{ApiKey, API_KEY_PREFIX};
+use crate::models::confidential_client::ConfidentialClient;
+use crate::models::refresh_token::RefreshToken;
+use crate::models::user_account::UserAccount;
+
+/// Body of an RFC 7662 introspection or RFC 7009 revocation request
+#[derive(Debug, Default, Clone, PartialEq, serde::Deserialize, utoipa::ToSchema)]
+pub struct TokenLookupForm {
+    pub token: String,
+    /// `access_token` or `refresh_token`; only a hint, every kind of token is looked up
+    pub token_type_hint: Option<String>,
+    /// unless sent with HTTP Basic authentication
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// RFC 7662 introspection response; all but `active` are omitted for inactive tokens
+#[derive(
+    Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// set for tokens issued through the client_credentials grant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    /// expiry, in seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    /// issue time, in seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+}
+
+impl IntrospectionResponse {
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+
+    fn active(
+        username: &str,
+        scope: &str,
+        token_type: Option<&str>,
+        exp: Option<chrono::NaiveDateTime>,
+        iat: chrono::NaiveDateTime,
+    ) -> Self {
+        Self {
+            active: true,
+            scope: Some(scope.to_owned()),
+            client_id: None,
+            username: Some(username.to_owned()),
+            token_type: token_type.map(str::to_owned),
+            exp: exp.map(|exp| exp.and_utc().timestamp()),
+            iat: Some(iat.and_utc().timestamp()),
+            sub: Some(username.to_owned()),
+        }
+    }
+}
+
+/// Describe `token`, be it an access token, refresh token or API key.
+///
+/// Tokens whose user is disabled, and tokens serve-replica has no record of (e.g., issued by
+/// the scaffold before scopes were recorded), are reported inactive.
+pub fn introspect(
+    conn: &mut diesel::PgConnection,
+    token: &str,
+) -> Result<IntrospectionResponse, ServeReplicaError> {
+    let now = crate::models::db_now(conn)?;
+    let response = if token.starts_with(API_KEY_PREFIX) {
+        ApiKey::find(conn, token)?
+            .filter(|api_key| api_key.is_active(now))
+            .map(|api_key| {
+                IntrospectionResponse::active(
+                    &api_key.username,
+                    &api_key.scopes,
+                    Some("Bearer"),
+                    api_key.expires_at,
+                    api_key.created_at,
+                )
+            })
+    } else {
+        None
+    };
+    let response = match response {
+        Some(response) => Some(response),
+        None => match AccessToken::find(conn, token)?.filter(AccessToken::is_active) {
+            Some(record) => record.username.as_deref().map(|username| {
+                let mut response = IntrospectionResponse::active(
+                    username,
+                    &record.scopes,
+                    Some("Bearer"),
+                    record.expires_at,
+                    record.created_at,
+                );
+                response.client_id = record
+                    .family_id
+                    .as_deref()
+                    .and_then(ConfidentialClient::of_token_family)
+                    .map(str::to_owned);
+                response
+            }),
+            None => RefreshToken::find(conn, token)?
+                .filter(|record| {
+                    record.revoked_at.is_none()
+                        && record.used_at.is_none()
+                        && record.expires_at > now
+                })
+                .map(|record| {
+                    IntrospectionResponse::active(
+                        &record.username,
+                        &record.scopes,
+                        None,
+                        Some(record.expires_at),
+                        record.created_at,
+                    )
+                }),
+        },
+    };
+    let Some(response) = response else {
+        return Ok(IntrospectionResponse::inactive());
+    };
+    let disabled = match &response.username {
+        Some(username) => UserAccount::is_disabled(conn, username)?,
+        None => true,
+    };
+    Ok(match disabled {
+        true => IntrospectionResponse::inactive(),
+        false => response,
+    })
+}
+
+/// Revoke `token`, be it an access token, refresh token or API key. Revoking a refresh token
+/// also revokes the access tokens issued alongside it. Unknown tokens are ignored, as RFC 7009
+/// requires.
+pub fn revoke(conn: &mut diesel::PgConnection, token: &str) -> diesel::QueryResult<()> {
+    use diesel::Connection;
+
+    conn.transaction(|conn| {
+        if token.starts_with(API_KEY_PREFIX) {
+            if let Some(api_key) = ApiKey::find(conn, token)? {
+                ApiKey::revoke(conn, &api_key.username, api_key.id)?;
+                return Ok(());
+            }
+        }
+        if let Some(record) = AccessToken::find(conn, token)? {
+            AccessToken::revoke(conn, record.id)?;
+            return Ok(());
+        }
+        if let Some(record) = RefreshToken::find(conn, token)? {
+            RefreshToken::revoke_family(conn, &record.family_id)?;
+            AccessToken::revoke_family(conn, &record.family_id)?;
+        }
+        Ok(())
+    })
+}
+
+/// The confidential client authenticating a request, e.g., for introspection or a token grant
+pub fn authenticate_client(
+    conn: &mut diesel::PgConnection,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<ConfidentialClient, ServeReplicaError> {
+    ConfidentialClient::authenticate(conn, client_id, client_secret)?.ok_or_else(|| {
+        ServeReplicaError::Unauthorised(String::from("invalid_client: unknown client or secret"))
+    })
+}
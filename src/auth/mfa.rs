@@ -0,0 +1,122 @@
+use crate::errors::ServeReplicaError;
+use crate::models::totp_credential::TotpCredential;
+
+pub const TOTP_ISSUER: &str = "serve-replica";
+pub const TOTP_DIGITS: usize = 6;
+pub const TOTP_STEP_SECS: u64 = 30;
+/// steps either side of now whose codes are still accepted, for clock drift
+pub const TOTP_SKEW: i64 = 1;
+pub const RECOVERY_CODE_COUNT: usize = 10;
+/// Error of a password grant with a wrong `otp`
+pub const INVALID_OTP: &str = "invalid_grant: invalid otp";
+
+/// 160-bit secret, the size RFC 4226 recommends
+pub fn generate_secret() -> Vec<u8> {
+    use rand::RngCore;
+
+    let mut secret = vec![0u8; 20];
+    rand::rngs::OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+pub fn totp(secret: &[u8], username: &str) -> Result<totp_rs::TOTP, ServeReplicaError> {
+    totp_rs::TOTP::new(
+        totp_rs::Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW as u8,
+        TOTP_STEP_SECS,
+        secret.to_vec(),
+        Some(String::from(TOTP_ISSUER)),
+        username.to_owned(),
+    )
+    .map_err(|err| ServeReplicaError::BadRequest(format!("TOTP: {}", err)))
+}
+
+/// The time step, within the allowed skew of `now`, whose code is `code`
+pub fn matching_step(totp: &totp_rs::TOTP, code: &str, now: u64) -> Option<i64> {
+    let current = (now / TOTP_STEP_SECS) as i64;
+    (current - TOTP_SKEW..=current + TOTP_SKEW)
+        .filter(|step| *step >= 0)
+        .find(|step| totp.generate(*step as u64 * TOTP_STEP_SECS) == code)
+}
+
+/// Codes like `k3xw9-pq7ma`, from an alphabet without easily confused characters
+pub fn generate_recovery_codes() -> Vec<String> {
+    use rand::Rng;
+
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::rngs::OsRng;
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = (0..10)
+                .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes as typed by users: any case, with or without the dash
+pub fn normalise_recovery_code(code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    match code.len() {
+        10 => format!("{}-{}", &code[..5], &code[5..]),
+        _ => code,
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// Accept `code` as the current TOTP code of `credential`, or one of its recovery codes.
+/// Each code works once.
+pub fn verify_code(
+    conn: &mut diesel::PgConnection,
+    credential: &TotpCredential,
+    code: &str,
+    allow_recovery_code: bool,
+) -> Result<bool, ServeReplicaError> {
+    let code = code.trim();
+    let totp = totp(&credential.secret, &credential.username)?;
+    if let Some(step) = matching_step(&totp, code, unix_now()) {
+        return Ok(TotpCredential::use_step(conn, &credential.username, step)?);
+    }
+    if allow_recovery_code && credential.confirmed_at.is_some() {
+        return Ok(TotpCredential::use_recovery_code(
+            conn,
+            &credential.username,
+            &normalise_recovery_code(code),
+        )?);
+    }
+    Ok(false)
+}
+
+/// Second factor check of the password grant: passes users without confirmed TOTP, and
+/// others only with a valid `otp`, which may be a recovery code
+pub fn verify_second_factor(
+    conn: &mut diesel::PgConnection,
+    username: &str,
+    otp: Option<&str>,
+) -> Result<(), ServeReplicaError> {
+    let Some(credential) = TotpCredential::find(conn, username)?
+        .filter(|credential| credential.confirmed_at.is_some())
+    else {
+        return Ok(());
+    };
+    let Some(otp) = otp.filter(|otp| !otp.trim().is_empty()) else {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "mfa_required: send the code from your authenticator app as otp",
+        )));
+    };
+    match verify_code(conn, &credential, otp, true)? {
+        true => Ok(()),
+        false => Err(ServeReplicaError::BadRequest(String::from(INVALID_OTP))),
+    }
+}
@@ -0,0 +1,62 @@
+use crate::errors::ServeReplicaError;
+
+pub(crate) mod account;
+pub(crate) mod authorise;
+pub(crate) mod bearer;
+pub(crate) mod federation;
+pub(crate) mod introspection;
+pub(crate) mod mfa;
+pub(crate) mod oidc;
+pub(crate) mod roles;
+pub(crate) mod scopes;
+pub(crate) mod sessions;
+pub(crate) mod throttle;
+pub(crate) mod token;
+
+/// What the caller of a bearer-protected route presented
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Credential {
+    /// a token issued to the user themself
+    UserToken,
+    /// a `client_credentials` token of a confidential client, acting as its owner
+    ClientToken,
+    /// a personal API key
+    ApiKey,
+}
+
+/// Caller of a bearer-protected route, as resolved by [`bearer::validator`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthenticatedUser {
+    pub username: String,
+    /// scopes granted to the bearer token used
+    pub scopes: Vec<scopes::Scope>,
+    pub role: roles::Role,
+    pub credential: Credential,
+}
+
+impl AuthenticatedUser {
+    pub fn has_scope(&self, scope: scopes::Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+impl actix_web::FromRequest for AuthenticatedUser {
+    type Error = ServeReplicaError;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        _payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        use actix_web::HttpMessage;
+
+        std::future::ready(
+            req.extensions()
+                .get::<AuthenticatedUser>()
+                .cloned()
+                .ok_or_else(|| {
+                    ServeReplicaError::Unauthorised(String::from("no authenticated user"))
+                }),
+        )
+    }
+}
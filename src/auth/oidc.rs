@@ -0,0 +1,263 @@
+use crate::auth::scopes::Scope;
+use crate::errors::ServeReplicaError;
+
+/// The only ID token signing algorithm offered; OpenID Connect requires providers support it
+pub const ID_TOKEN_ALG: &str = "RS256";
+pub const JWKS_PATH: &str = "/.well-known/jwks.json";
+pub const USERINFO_PATH: &str = "/userinfo";
+
+/// RSA key ID tokens are signed with, published at [`JWKS_PATH`]
+#[derive(Clone)]
+pub struct SigningKey {
+    kid: String,
+    key: rsa::RsaPrivateKey,
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("kid", &self.kid)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SigningKey {
+    /// Key id is derived from the public key, so it changes exactly when the key does
+    pub fn new(key: rsa::RsaPrivateKey) -> Self {
+        use base64::Engine;
+        use rsa::traits::PublicKeyParts;
+        use sha2::Digest;
+
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(key.n().to_bytes_be());
+        hasher.update(key.e().to_bytes_be());
+        let kid = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(hasher.finalize())[..16]
+            .to_owned();
+        Self { kid, key }
+    }
+
+    /// PKCS#8 (`BEGIN PRIVATE KEY`) or PKCS#1 (`BEGIN RSA PRIVATE KEY`) PEM
+    pub fn from_pem(pem: &str) -> Result<Self, String> {
+        use rsa::pkcs1::DecodeRsaPrivateKey;
+        use rsa::pkcs8::DecodePrivateKey;
+
+        rsa::RsaPrivateKey::from_pkcs8_pem(pem)
+            .or_else(|_| rsa::RsaPrivateKey::from_pkcs1_pem(pem))
+            .map(Self::new)
+            .map_err(|err| format!("not an RSA private key: {}", err))
+    }
+
+    pub fn generate(bits: usize) -> Result<Self, String> {
+        rsa::RsaPrivateKey::new(&mut rand::rngs::OsRng, bits)
+            .map(Self::new)
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn jwk(&self) -> Jwk {
+        use base64::Engine;
+        use rsa::traits::PublicKeyParts;
+
+        let encode =
+            |bytes: Vec<u8>| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        Jwk {
+            kty: String::from("RSA"),
+            use_: String::from("sig"),
+            alg: String::from(ID_TOKEN_ALG),
+            kid: self.kid.clone(),
+            n: encode(self.key.n().to_bytes_be()),
+            e: encode(self.key.e().to_bytes_be()),
+        }
+    }
+
+    /// Compact JWS of `claims`, signed with RS256
+    pub fn sign(&self, claims: &impl serde::Serialize) -> Result<String, ServeReplicaError> {
+        use base64::Engine;
+        use rsa::signature::{SignatureEncoding, Signer};
+
+        let encode = |bytes: &[u8]| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        let header = serde_json::json!({ "alg": ID_TOKEN_ALG, "typ": "JWT", "kid": self.kid });
+        let header = serde_json::to_vec(&header)
+            .map_err(|err| ServeReplicaError::Database(err.to_string()))?;
+        let claims = serde_json::to_vec(claims)
+            .map_err(|err| ServeReplicaError::Database(err.to_string()))?;
+        let signing_input = format!("{}.{}", encode(&header), encode(&claims));
+        let signature = rsa::pkcs1v15::SigningKey::<sha2::Sha256>::new(self.key.clone())
+            .sign(signing_input.as_bytes())
+            .to_bytes();
+        Ok(format!("{}.{}", signing_input, encode(&signature)))
+    }
+}
+
+/// RFC 7517 JSON Web Key of an RSA public key
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(rename = "use")]
+    pub use_: String,
+    pub alg: String,
+    pub kid: String,
+    /// modulus, base64url encoded
+    pub n: String,
+    /// public exponent, base64url encoded
+    pub e: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+/// Claims of the ID tokens `/api/token` issues
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub preferred_username: String,
+    /// the `nonce` of the authorization request, if it sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+}
+
+/// OpenID Connect provider: who serve-replica says it is, and the key it vouches with
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    /// base URL serve-replica is reached at, e.g., `https://api.example.com`; no trailing slash
+    pub issuer: String,
+    pub id_token_ttl: std::time::Duration,
+    pub key: SigningKey,
+}
+
+impl OidcProvider {
+    pub fn new(issuer: &str, key: SigningKey) -> Self {
+        Self {
+            issuer: issuer.trim_end_matches('/').to_owned(),
+            id_token_ttl: std::time::Duration::from_secs(60 * 60),
+            key,
+        }
+    }
+
+    /// ID token for `username`, addressed to `client_id`, which must have authenticated, or to
+    /// the issuer itself for grants made without one
+    pub fn id_token(
+        &self,
+        username: &str,
+        client_id: Option<&str>,
+        nonce: Option<&str>,
+    ) -> Result<String, ServeReplicaError> {
+        let iat = chrono::Utc::now().timestamp();
+        self.key.sign(&IdTokenClaims {
+            iss: self.issuer.clone(),
+            sub: username.to_owned(),
+            aud: client_id.unwrap_or(&self.issuer).to_owned(),
+            exp: iat + self.id_token_ttl.as_secs() as i64,
+            iat,
+            preferred_username: username.to_owned(),
+            nonce: nonce.map(str::to_owned),
+        })
+    }
+
+    pub fn metadata(&self) -> ProviderMetadata {
+        let url = |path: &str| format!("{}{}", self.issuer, path);
+        ProviderMetadata {
+            issuer: self.issuer.clone(),
+            authorization_endpoint: url(crate::auth::authorise::AUTHORISE_PATH),
+            token_endpoint: url(crate::auth::token::TOKEN_PATH),
+            userinfo_endpoint: url(USERINFO_PATH),
+            jwks_uri: url(JWKS_PATH),
+            introspection_endpoint: url("/api/token/introspect"),
+            revocation_endpoint: url("/api/token/revoke"),
+            scopes_supported: Scope::ALL
+                .iter()
+                .map(|(scope, _)| scope.as_str().to_owned())
+                .collect(),
+            response_types_supported: vec![String::from("code")],
+            grant_types_supported: [
+                "authorization_code",
+                "password",
+                "refresh_token",
+                "client_credentials",
+            ]
+            .map(String::from)
+            .to_vec(),
+            subject_types_supported: vec![String::from("public")],
+            id_token_signing_alg_values_supported: vec![String::from(ID_TOKEN_ALG)],
+            token_endpoint_auth_methods_supported: [
+                "client_secret_basic",
+                "client_secret_post",
+                "none",
+            ]
+            .map(String::from)
+            .to_vec(),
+            code_challenge_methods_supported: vec![String::from(
+                crate::auth::authorise::CODE_CHALLENGE_METHOD,
+            )],
+            claims_supported: [
+                "iss",
+                "sub",
+                "aud",
+                "exp",
+                "iat",
+                "nonce",
+                "preferred_username",
+                "email",
+                "email_verified",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+/// [`OidcProvider::id_token`] if `scopes` asked for one and serve-replica is a provider
+pub fn id_token_for(
+    provider: Option<&OidcProvider>,
+    scopes: &[Scope],
+    username: &str,
+    client_id: Option<&str>,
+    nonce: Option<&str>,
+) -> Result<Option<String>, ServeReplicaError> {
+    match provider {
+        Some(provider) if scopes.contains(&Scope::OpenId) => {
+            provider.id_token(username, client_id, nonce).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// OpenID Connect Discovery 1.0 provider metadata
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    pub introspection_endpoint: String,
+    pub revocation_endpoint: String,
+    pub scopes_supported: Vec<String>,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+}
+
+/// OpenID Connect `/userinfo` claims, with the user's `Profile`
+#[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
+pub struct UserInfo {
+    pub sub: String,
+    pub preferred_username: String,
+    /// for users who registered through `/api/register`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    /// the user's `Profile`, if they created one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replica_profile: Option<replica_backend::models::profile::Profile>,
+}
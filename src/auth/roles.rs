@@ -0,0 +1,88 @@
+use actix_web::HttpMessage;
+
+use crate::auth::AuthenticatedUser;
+use crate::errors::ServeReplicaError;
+
+/// A user's role; each role has every right of those before it
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    /// may view any user's data
+    Moderator,
+    /// may also manage users
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::User, Role::Moderator, Role::Admin];
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = ServeReplicaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| ServeReplicaError::BadRequest(format!("invalid role: {}", s)))
+    }
+}
+
+/// Reject `user` unless their role is at least `role`
+pub fn require_role(user: &AuthenticatedUser, role: Role) -> Result<(), ServeReplicaError> {
+    if user.role >= role {
+        Ok(())
+    } else {
+        Err(ServeReplicaError::Forbidden(format!(
+            "requires role {}",
+            role
+        )))
+    }
+}
+
+/// Middleware rejecting callers below [`Role::Moderator`].
+///
+/// Must run after the bearer validator, i.e., be `.wrap`ped before it.
+pub async fn require_moderator(
+    req: actix_web::dev::ServiceRequest,
+    next: actix_web::middleware::Next<impl actix_web::body::MessageBody>,
+) -> Result<actix_web::dev::ServiceResponse<impl actix_web::body::MessageBody>, actix_web::Error> {
+    match req.extensions().get::<AuthenticatedUser>() {
+        Some(user) => require_role(user, Role::Moderator)?,
+        None => {
+            return Err(
+                ServeReplicaError::Unauthorised(String::from("no authenticated user")).into(),
+            )
+        }
+    }
+    next.call(req).await
+}
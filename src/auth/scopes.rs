@@ -0,0 +1,204 @@
+use actix_web::HttpMessage;
+
+use crate::auth::AuthenticatedUser;
+use crate::errors::ServeReplicaError;
+
+/// OAuth2 scopes a bearer token can be granted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Scope {
+    ModelsRead,
+    ModelsWrite,
+    ProfileRead,
+    ProfileWrite,
+    Crawl,
+    Swap,
+    OpenId,
+    Admin,
+}
+
+impl Scope {
+    /// Every scope, with the description shown by Swagger UI when requesting it
+    pub const ALL: [(Scope, &'static str); 8] = [
+        (Scope::ModelsRead, "Read your `PersonModel`s"),
+        (Scope::ModelsWrite, "Create and update your `PersonModel`s"),
+        (Scope::ProfileRead, "Read your `Profile`"),
+        (Scope::ProfileWrite, "Update your `Profile`"),
+        (Scope::Crawl, "Crawl web pages"),
+        (
+            Scope::Swap,
+            "Faceswap, and view or delete your swap history",
+        ),
+        (
+            Scope::OpenId,
+            "Sign in with OpenID Connect: an `id_token`, and `/userinfo`",
+        ),
+        (
+            Scope::Admin,
+            "Manage users, audit events and models under `/api/admin`, if you are a moderator",
+        ),
+    ];
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ModelsRead => "models:read",
+            Scope::ModelsWrite => "models:write",
+            Scope::ProfileRead => "profile:read",
+            Scope::ProfileWrite => "profile:write",
+            Scope::Crawl => "crawl",
+            Scope::Swap => "swap",
+            Scope::OpenId => "openid",
+            Scope::Admin => "admin",
+        }
+    }
+
+    /// Scopes granted to a user's token when none are requested; ID tokens and admin access
+    /// only on request
+    pub fn user_defaults() -> Vec<Scope> {
+        Scope::ALL
+            .iter()
+            .map(|(scope, _)| *scope)
+            .filter(|scope| !matches!(scope, Scope::OpenId | Scope::Admin))
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = ServeReplicaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .iter()
+            .map(|(scope, _)| *scope)
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| ServeReplicaError::BadRequest(format!("invalid_scope: {}", s)))
+    }
+}
+
+/// Parse a space-separated OAuth2 `scope` parameter
+pub fn parse_scopes(scopes: &str) -> Result<Vec<Scope>, ServeReplicaError> {
+    let mut parsed = scopes
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<Scope>, ServeReplicaError>>()?;
+    parsed.sort();
+    parsed.dedup();
+    Ok(parsed)
+}
+
+pub fn format_scopes(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(Scope::as_str)
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Scope needed to call `method` `path`, or `None` if any valid bearer token will do.
+///
+/// Routes from `replica_backend` cannot be wrapped one by one, so the mapping is by path
+/// prefix; it is also what [`add_security_requirements`] documents in the OpenAPI spec.
+pub fn required_scope(method: &actix_web::http::Method, path: &str) -> Option<Scope> {
+    let read = method == actix_web::http::Method::GET || method == actix_web::http::Method::HEAD;
+    if path.starts_with("/api/v0/model") {
+        Some(if read {
+            Scope::ModelsRead
+        } else {
+            Scope::ModelsWrite
+        })
+    } else if path.starts_with("/api/v0/profile") {
+        Some(if read {
+            Scope::ProfileRead
+        } else {
+            Scope::ProfileWrite
+        })
+    } else if path.starts_with("/api/v0/swaps") || path.starts_with("/v1/swap") {
+        Some(Scope::Swap)
+    } else if path.starts_with("/v1/crawl") {
+        Some(Scope::Crawl)
+    } else if path.starts_with(crate::auth::oidc::USERINFO_PATH) {
+        Some(Scope::OpenId)
+    } else if path.starts_with("/api/admin") {
+        Some(Scope::Admin)
+    } else {
+        None
+    }
+}
+
+/// Path prefixes of the scopes `main.rs` wraps with the bearer validator
+pub const BEARER_PREFIXES: [&str; 5] = ["/api/v0", "/api/admin", "/userinfo", "/v1", "/secured"];
+
+/// Whether `path` needs a bearer token, whether or not it also needs a [`required_scope`]
+pub fn requires_bearer(path: &str) -> bool {
+    BEARER_PREFIXES.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Middleware rejecting requests whose bearer token lacks [`required_scope`].
+///
+/// Must run after the bearer validator, i.e., be `.wrap`ped before it.
+pub async fn require_scope(
+    req: actix_web::dev::ServiceRequest,
+    next: actix_web::middleware::Next<impl actix_web::body::MessageBody>,
+) -> Result<actix_web::dev::ServiceResponse<impl actix_web::body::MessageBody>, actix_web::Error> {
+    if let Some(scope) = required_scope(req.method(), req.path()) {
+        let granted = req
+            .extensions()
+            .get::<AuthenticatedUser>()
+            .is_some_and(|user| user.has_scope(scope));
+        if !granted {
+            return Err(ServeReplicaError::Forbidden(format!(
+                "insufficient_scope: requires {}",
+                scope
+            ))
+            .into());
+        }
+    }
+    next.call(req).await
+}
+
+/// Scopes for the OAuth2 flows in the OpenAPI security scheme
+pub fn openapi_scopes() -> utoipa::openapi::security::Scopes {
+    Scope::ALL
+        .iter()
+        .map(|(scope, description)| (scope.as_str(), *description))
+        .collect()
+}
+
+/// Set each bearer-protected operation's security requirement to its [`required_scope`], or to
+/// a token of any scope where it needs none
+pub fn add_security_requirements(
+    mut openapi: utoipa::openapi::OpenApi,
+) -> utoipa::openapi::OpenApi {
+    use actix_web::http::Method;
+
+    for (path, item) in openapi.paths.paths.iter_mut() {
+        for (method, operation) in [
+            (Method::GET, &mut item.get),
+            (Method::PUT, &mut item.put),
+            (Method::POST, &mut item.post),
+            (Method::DELETE, &mut item.delete),
+            (Method::PATCH, &mut item.patch),
+        ] {
+            let Some(operation) = operation else {
+                continue;
+            };
+            let scopes = match required_scope(&method, path) {
+                Some(scope) => vec![scope.as_str()],
+                None if requires_bearer(path) => Vec::new(),
+                None => continue,
+            };
+            operation.security = Some(vec![utoipa::openapi::security::SecurityRequirement::new(
+                "password", scopes,
+            )]);
+        }
+    }
+    openapi
+}
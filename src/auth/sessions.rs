@@ -0,0 +1,193 @@
+//! A user's sessions: each login, with its refreshed tokens, as seen under `/secured/sessions`
+
+use diesel::prelude::*;
+
+use crate::models::access_token::AccessToken;
+use crate::models::refresh_token::RefreshToken;
+use crate::schema::{access_tokens, refresh_tokens};
+
+/// One signed-in client of the user's, i.e., an unrevoked token family that can still be used
+/// or refreshed
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct Session {
+    /// ID to revoke the session by, that of its newest access token
+    pub id: i32,
+    /// e.g., `Firefox on Linux`, as far as the user agent tells
+    pub device: Option<String>,
+    pub user_agent: Option<String>,
+    /// address the session was last used from
+    pub ip: Option<String>,
+    /// space-separated scopes of its newest access token
+    pub scopes: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    /// whether this is the session of the token making the request
+    pub current: bool,
+}
+
+/// Short description of the browser and operating system a `user_agent` claims, if recognised
+pub fn describe_device(user_agent: &str) -> Option<String> {
+    // earlier entries win, as e.g., Edge also claims to be Chrome, and Android to be Linux
+    const BROWSERS: [(&str, &str); 6] = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+        ("curl/", "curl"),
+    ];
+    const SYSTEMS: [(&str, &str); 7] = [
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("CrOS", "ChromeOS"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ];
+    let find = |names: &[(&str, &'static str)]| {
+        names
+            .iter()
+            .find(|(needle, _)| user_agent.contains(needle))
+            .map(|(_, name)| *name)
+    };
+    match (find(&BROWSERS), find(&SYSTEMS)) {
+        (Some(browser), Some(system)) => Some(format!("{} on {}", browser, system)),
+        (Some(name), None) | (None, Some(name)) => Some(name.to_owned()),
+        (None, None) => None,
+    }
+}
+
+fn same_session(token: &AccessToken, current: Option<&AccessToken>) -> bool {
+    current.is_some_and(|current| {
+        current.id == token.id
+            || (current.family_id.is_some() && current.family_id == token.family_id)
+    })
+}
+
+/// `username`'s sessions, newest first; `current` is the access token making the request, if
+/// it is one
+pub fn list(
+    conn: &mut diesel::PgConnection,
+    username: &str,
+    current: Option<&AccessToken>,
+) -> QueryResult<Vec<Session>> {
+    let now = chrono::Utc::now().naive_utc();
+    let refreshable: Vec<String> = refresh_tokens::table
+        .filter(refresh_tokens::username.eq(username))
+        .filter(refresh_tokens::used_at.is_null())
+        .filter(refresh_tokens::revoked_at.is_null())
+        .filter(refresh_tokens::expires_at.gt(now))
+        .select(refresh_tokens::family_id)
+        .distinct()
+        .load(conn)?;
+    let tokens = access_tokens::table
+        .filter(access_tokens::username.eq(username))
+        .filter(access_tokens::revoked_at.is_null())
+        .filter(
+            access_tokens::expires_at
+                .is_null()
+                .or(access_tokens::expires_at.gt(now))
+                .or(access_tokens::family_id.eq_any(&refreshable)),
+        )
+        .order(access_tokens::id.desc())
+        .select(AccessToken::as_select())
+        .load(conn)?;
+
+    let mut sessions: Vec<Session> = Vec::new();
+    let mut families = std::collections::HashMap::<String, usize>::new();
+    for token in tokens {
+        let known = token
+            .family_id
+            .as_ref()
+            .and_then(|family_id| families.get(family_id));
+        match known {
+            // tokens are newest first, so this one only adds to the session's history
+            Some(&index) => {
+                let session = &mut sessions[index];
+                session.created_at = token.created_at;
+                session.last_used_at = session.last_used_at.max(token.last_used_at);
+            }
+            None => {
+                if let Some(family_id) = &token.family_id {
+                    families.insert(family_id.clone(), sessions.len());
+                }
+                sessions.push(Session {
+                    id: token.id,
+                    device: token.user_agent.as_deref().and_then(describe_device),
+                    current: same_session(&token, current),
+                    user_agent: token.user_agent,
+                    ip: token.ip,
+                    scopes: token.scopes,
+                    created_at: token.created_at,
+                    last_used_at: token.last_used_at,
+                });
+            }
+        }
+    }
+    Ok(sessions)
+}
+
+/// Sign `username`'s session `id` out, revoking its access and refresh tokens; `false` if
+/// they have no such unrevoked session
+pub fn revoke(conn: &mut diesel::PgConnection, username: &str, id: i32) -> QueryResult<bool> {
+    conn.transaction(|conn| {
+        let token = access_tokens::table
+            .find(id)
+            .filter(access_tokens::username.eq(username))
+            .filter(access_tokens::revoked_at.is_null())
+            .select(AccessToken::as_select())
+            .first(conn)
+            .optional()?;
+        match token.and_then(|token| token.family_id) {
+            Some(family_id) => {
+                RefreshToken::revoke_family(conn, &family_id)?;
+                AccessToken::revoke_family(conn, &family_id).map(|revoked| revoked > 0)
+            }
+            None => AccessToken::revoke(conn, id).map(|revoked| revoked > 0),
+        }
+    })
+}
+
+/// Sign `username` out everywhere but the session of `current`, returning how many tokens
+/// were revoked. Without a `current` access token, e.g., when called with an API key, every
+/// session is.
+pub fn revoke_others(
+    conn: &mut diesel::PgConnection,
+    username: &str,
+    current: Option<&AccessToken>,
+) -> QueryResult<usize> {
+    let keep_id = current.map(|current| current.id);
+    let keep_family = current.and_then(|current| current.family_id.clone());
+    conn.transaction(|conn| {
+        let mut access = access_tokens::table
+            .filter(access_tokens::username.eq(username))
+            .filter(access_tokens::revoked_at.is_null())
+            .select(access_tokens::id)
+            .into_boxed();
+        let mut refresh = refresh_tokens::table
+            .filter(refresh_tokens::username.eq(username))
+            .filter(refresh_tokens::revoked_at.is_null())
+            .select(refresh_tokens::id)
+            .into_boxed();
+        if let Some(keep_id) = keep_id {
+            access = access.filter(access_tokens::id.ne(keep_id));
+        }
+        if let Some(keep_family) = &keep_family {
+            access = access.filter(
+                access_tokens::family_id
+                    .is_null()
+                    .or(access_tokens::family_id.ne(keep_family)),
+            );
+            refresh = refresh.filter(refresh_tokens::family_id.ne(keep_family));
+        }
+        let access: Vec<i32> = access.load(conn)?;
+        let refresh: Vec<i32> = refresh.load(conn)?;
+        diesel::update(refresh_tokens::table.filter(refresh_tokens::id.eq_any(&refresh)))
+            .set(refresh_tokens::revoked_at.eq(diesel::dsl::now))
+            .execute(conn)?;
+        diesel::update(access_tokens::table.filter(access_tokens::id.eq_any(&access)))
+            .set(access_tokens::revoked_at.eq(diesel::dsl::now))
+            .execute(conn)
+    })
+}
@@ -0,0 +1,531 @@
+use crate::auth::authorise::{AuthoriseForm, AUTHORISE_PATH};
+use crate::auth::mfa::INVALID_OTP;
+use crate::auth::token::{bytes_to_payload, TokenForm, INVALID_CREDENTIALS, TOKEN_PATH};
+use crate::errors::ServeReplicaError;
+
+/// Emails one address may be sent from registration, verification resends and password resets
+/// within the window, before further requests for it are refused
+pub const MAX_MAIL_REQUESTS: u32 = 3;
+
+/// Limits on failed password logins
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThrottleConfig {
+    /// failures for one username before it is locked out
+    pub max_user_failures: u32,
+    /// failures from one IP before it is locked out; higher, as many users may share an IP
+    pub max_ip_failures: u32,
+    /// failures are forgotten once none has happened for this long
+    pub window: std::time::Duration,
+    pub lockout: std::time::Duration,
+    /// delay before answering after the first failure, doubling with each further one
+    pub base_delay: std::time::Duration,
+    pub max_delay: std::time::Duration,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            max_user_failures: 5,
+            max_ip_failures: 20,
+            window: std::time::Duration::from_secs(15 * 60),
+            lockout: std::time::Duration::from_secs(15 * 60),
+            base_delay: std::time::Duration::from_millis(250),
+            max_delay: std::time::Duration::from_secs(4),
+        }
+    }
+}
+
+impl ThrottleConfig {
+    /// How long to hold back a login attempt after `failures` recent failures
+    pub fn delay(&self, failures: u32) -> std::time::Duration {
+        match failures {
+            0 => std::time::Duration::ZERO,
+            n => self
+                .base_delay
+                .saturating_mul(1u32 << (n - 1).min(16))
+                .min(self.max_delay),
+        }
+    }
+}
+
+/// Recent failures of one key, and how much longer it is locked out for
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AttemptStatus {
+    pub failures: u32,
+    pub locked_for: Option<std::time::Duration>,
+}
+
+/// Where failed attempts are counted; shared between instances when backed by Redis.
+///
+/// Calls block, so make them from `actix_web::web::block`.
+pub trait AttemptStore: Send + Sync {
+    fn status(&self, key: &str) -> Result<AttemptStatus, String>;
+    /// Count an attempt before its outcome is known, as a failure until it is released; the
+    /// count includes it, so concurrent attempts each see those before them
+    fn record_attempt(
+        &self,
+        key: &str,
+        window: std::time::Duration,
+    ) -> Result<AttemptStatus, String>;
+    /// Forget one attempt that turned out not to be a failure
+    fn release(&self, key: &str) -> Result<(), String>;
+    fn lock(&self, key: &str, lockout: std::time::Duration) -> Result<(), String>;
+    fn reset(&self, key: &str) -> Result<(), String>;
+
+    /// Count a failure, locking `key` out for `lockout` once it reaches `max_failures`
+    fn record_failure(
+        &self,
+        key: &str,
+        max_failures: u32,
+        window: std::time::Duration,
+        lockout: std::time::Duration,
+    ) -> Result<AttemptStatus, String> {
+        let status = self.record_attempt(key, window)?;
+        if status.failures < max_failures {
+            return Ok(status);
+        }
+        self.lock(key, lockout)?;
+        Ok(AttemptStatus {
+            locked_for: Some(lockout),
+            ..status
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MemoryEntry {
+    failures: u32,
+    last_failure: std::time::Instant,
+    window: std::time::Duration,
+    locked_until: Option<std::time::Instant>,
+}
+
+impl MemoryEntry {
+    fn status(&self, now: std::time::Instant) -> AttemptStatus {
+        let locked_for = self
+            .locked_until
+            .and_then(|locked_until| locked_until.checked_duration_since(now))
+            .filter(|locked_for| !locked_for.is_zero());
+        let failures = match now.duration_since(self.last_failure) < self.window {
+            true => self.failures,
+            false => 0,
+        };
+        AttemptStatus {
+            failures,
+            locked_for,
+        }
+    }
+}
+
+/// Per-process store, for when `REDIS_URL` is not set
+#[derive(Debug, Default)]
+pub struct MemoryAttemptStore {
+    entries: std::sync::Mutex<std::collections::HashMap<String, MemoryEntry>>,
+}
+
+impl MemoryAttemptStore {
+    /// Entries kept before stale ones are swept
+    const SWEEP_AFTER: usize = 10_000;
+}
+
+impl AttemptStore for MemoryAttemptStore {
+    fn status(&self, key: &str) -> Result<AttemptStatus, String> {
+        let entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        Ok(entries
+            .get(key)
+            .map(|entry| entry.status(std::time::Instant::now()))
+            .unwrap_or_default())
+    }
+
+    fn record_attempt(
+        &self,
+        key: &str,
+        window: std::time::Duration,
+    ) -> Result<AttemptStatus, String> {
+        let now = std::time::Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        if entries.len() >= Self::SWEEP_AFTER {
+            entries.retain(|_, entry| {
+                let status = entry.status(now);
+                status.failures > 0 || status.locked_for.is_some()
+            });
+        }
+        let failures = entries
+            .get(key)
+            .map_or(0, |entry| entry.status(now).failures)
+            + 1;
+        let entry = entries.entry(key.to_owned()).or_insert(MemoryEntry {
+            failures: 0,
+            last_failure: now,
+            window,
+            locked_until: None,
+        });
+        entry.failures = failures;
+        entry.last_failure = now;
+        entry.window = window;
+        Ok(entry.status(now))
+    }
+
+    fn release(&self, key: &str) -> Result<(), String> {
+        let now = std::time::Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(entry) = entries.get_mut(key) {
+            entry.failures = entry.status(now).failures.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    fn lock(&self, key: &str, lockout: std::time::Duration) -> Result<(), String> {
+        let now = std::time::Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries
+            .entry(key.to_owned())
+            .or_insert(MemoryEntry {
+                failures: 0,
+                last_failure: now,
+                window: std::time::Duration::ZERO,
+                locked_until: None,
+            })
+            .locked_until = Some(now + lockout);
+        Ok(())
+    }
+
+    fn reset(&self, key: &str) -> Result<(), String> {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        entries.remove(key);
+        Ok(())
+    }
+}
+
+/// Store shared through Redis, so every instance sees the same failures and lockouts
+pub struct RedisAttemptStore {
+    pool: diesel::r2d2::Pool<redis::Client>,
+}
+
+impl RedisAttemptStore {
+    const PREFIX: &'static str = "serve-replica:login";
+    /// Decrement a count, but never below zero nor into a key without its expiry
+    const RELEASE_SCRIPT: &'static str = r"
+        if tonumber(redis.call('GET', KEYS[1]) or '0') > 0 then
+            return redis.call('DECR', KEYS[1])
+        end
+        return 0";
+
+    pub fn new(url: &str) -> Result<Self, String> {
+        let client = redis::Client::open(url).map_err(|err| err.to_string())?;
+        let pool = diesel::r2d2::Pool::builder()
+            .build(client)
+            .map_err(|err| err.to_string())?;
+        Ok(Self { pool })
+    }
+
+    fn keys(key: &str) -> (String, String) {
+        (
+            format!("{}:failures:{}", Self::PREFIX, key),
+            format!("{}:locked:{}", Self::PREFIX, key),
+        )
+    }
+
+    fn read_status(
+        conn: &mut redis::Connection,
+        failures_key: &str,
+        locked_key: &str,
+    ) -> redis::RedisResult<AttemptStatus> {
+        use redis::Commands;
+
+        let failures: Option<u32> = conn.get(failures_key)?;
+        // -2 if there is no lock, -1 if it never expires, which it always does
+        let locked_ttl: i64 = conn.pttl(locked_key)?;
+        Ok(AttemptStatus {
+            failures: failures.unwrap_or(0),
+            locked_for: u64::try_from(locked_ttl)
+                .ok()
+                .filter(|ttl| *ttl > 0)
+                .map(std::time::Duration::from_millis),
+        })
+    }
+}
+
+impl AttemptStore for RedisAttemptStore {
+    fn status(&self, key: &str) -> Result<AttemptStatus, String> {
+        let mut conn = self.pool.get().map_err(|err| err.to_string())?;
+        let (failures_key, locked_key) = Self::keys(key);
+        Self::read_status(&mut conn, &failures_key, &locked_key).map_err(|err| err.to_string())
+    }
+
+    fn record_attempt(
+        &self,
+        key: &str,
+        window: std::time::Duration,
+    ) -> Result<AttemptStatus, String> {
+        let mut conn = self.pool.get().map_err(|err| err.to_string())?;
+        let (failures_key, locked_key) = Self::keys(key);
+        let (failures,): (u32,) = redis::pipe()
+            .atomic()
+            .incr(&failures_key, 1)
+            .pexpire(&failures_key, window.as_millis() as i64)
+            .ignore()
+            .query(&mut *conn)
+            .map_err(|err| err.to_string())?;
+        // this attempt's own count, not one raised since by those after it
+        Self::read_status(&mut conn, &failures_key, &locked_key)
+            .map(|status| AttemptStatus { failures, ..status })
+            .map_err(|err| err.to_string())
+    }
+
+    fn release(&self, key: &str) -> Result<(), String> {
+        let mut conn = self.pool.get().map_err(|err| err.to_string())?;
+        let (failures_key, _) = Self::keys(key);
+        redis::Script::new(Self::RELEASE_SCRIPT)
+            .key(&failures_key)
+            .invoke::<i64>(&mut *conn)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn lock(&self, key: &str, lockout: std::time::Duration) -> Result<(), String> {
+        use redis::Commands;
+
+        let mut conn = self.pool.get().map_err(|err| err.to_string())?;
+        let (_, locked_key) = Self::keys(key);
+        conn.pset_ex(&locked_key, 1, lockout.as_millis() as u64)
+            .map_err(|err| err.to_string())
+    }
+
+    fn reset(&self, key: &str) -> Result<(), String> {
+        use redis::Commands;
+
+        let mut conn = self.pool.get().map_err(|err| err.to_string())?;
+        let (failures_key, locked_key) = Self::keys(key);
+        conn.del(&[failures_key, locked_key])
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Failed-login tracking for `/api/token`
+#[derive(Clone)]
+pub struct LoginThrottle {
+    pub config: ThrottleConfig,
+    pub store: std::sync::Arc<dyn AttemptStore>,
+}
+
+impl LoginThrottle {
+    /// Keys an attempt counts against, each with its failure limit
+    fn keys(&self, username: &str, ip: &str) -> [(String, u32); 2] {
+        [
+            (format!("user:{}", username), self.config.max_user_failures),
+            (format!("ip:{}", ip), self.config.max_ip_failures),
+        ]
+    }
+
+    /// Count a request that mails `email`, from `ip`, refusing it with `429` once the address
+    /// has had [`MAX_MAIL_REQUESTS`] within the window, or the IP `max_ip_failures`. The
+    /// request is counted before it is checked, so concurrent ones cannot all slip under the
+    /// limit. Should the store be unreachable, the request is let through.
+    ///
+    /// Blocks, so call it from `actix_web::web::block`.
+    pub fn count_mail_request(&self, email: &str, ip: &str) -> Result<(), ServeReplicaError> {
+        let keys = [
+            (format!("mail:{}", email), MAX_MAIL_REQUESTS),
+            (format!("mail-ip:{}", ip), self.config.max_ip_failures),
+        ];
+        for (key, max_requests) in keys {
+            // the first request over the limit starts the lockout
+            match self.store.record_failure(
+                &key,
+                max_requests + 1,
+                self.config.window,
+                self.config.lockout,
+            ) {
+                Ok(status) if status.failures > max_requests => {
+                    return Err(ServeReplicaError::TooManyRequests(
+                        String::from("too many emails requested; try again later"),
+                        status
+                            .locked_for
+                            .unwrap_or(self.config.lockout)
+                            .as_secs()
+                            .max(1),
+                    ));
+                }
+                Ok(_) => {}
+                Err(err) => log::error!("counting mail requests: {}", err),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `err` is a wrong password or one-time code, which is all that counts against a
+/// login; not `mfa_required`, a disabled account or a malformed request
+pub fn is_login_failure(err: &ServeReplicaError) -> bool {
+    matches!(err, ServeReplicaError::BadRequest(msg) if msg == INVALID_CREDENTIALS || msg == INVALID_OTP)
+}
+
+/// Middleware throttling `grant_type=password` at `/api/token`, and sign-ins at
+/// `/api/authorise`, per username and per IP.
+///
+/// Each attempt counts as a failure from before it runs until it is known not to be one, so
+/// concurrent attempts cannot all slip under the limit. Locked out callers, and those past the
+/// limit while earlier attempts are still running, get `429` with `Retry-After`; others are held
+/// back longer after each recent failure. Should the store be unreachable, logins are let
+/// through rather than refused.
+/// The IP is the peer's, or from `Forwarded`/`X-Forwarded-For` if the peer is a trusted proxy.
+pub async fn login_throttle(
+    mut req: actix_web::dev::ServiceRequest,
+    next: actix_web::middleware::Next<impl actix_web::body::MessageBody + 'static>,
+) -> Result<actix_web::dev::ServiceResponse, actix_web::Error> {
+    let throttle = req
+        .app_data::<actix_web::web::Data<LoginThrottle>>()
+        .cloned();
+    let authorise = req.path() == AUTHORISE_PATH;
+    let Some(throttle) = throttle.filter(|_| {
+        req.method() == actix_web::http::Method::POST && (req.path() == TOKEN_PATH || authorise)
+    }) else {
+        return next.call(req).await.map(|res| res.map_into_boxed_body());
+    };
+    let body = req.extract::<actix_web::web::Bytes>().await?;
+    let username = if authorise {
+        serde_urlencoded::from_bytes::<AuthoriseForm>(&body)
+            .ok()
+            .map(|form| form.username)
+    } else {
+        serde_urlencoded::from_bytes::<TokenForm>(&body)
+            .ok()
+            .filter(|form| form.grant_type == "password")
+            .and_then(|form| form.username)
+    };
+    req.set_payload(bytes_to_payload(body));
+    let Some(username) = username else {
+        return next.call(req).await.map(|res| res.map_into_boxed_body());
+    };
+    let ip = crate::proxy::client_ip(req.request()).unwrap_or_else(|| String::from("unknown"));
+    let keys = throttle.keys(&username, &ip);
+
+    // each attempt is counted before it runs, so concurrent ones cannot all pass a check made
+    // before any of them failed; it is released again unless it fails
+    let (reserve_throttle, reserve_keys) = (throttle.clone(), keys.clone());
+    let reserved = actix_web::web::block(move || {
+        reserve_keys
+            .iter()
+            .map(|(key, _)| {
+                reserve_throttle
+                    .store
+                    .record_attempt(key, reserve_throttle.config.window)
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .await
+    .map_err(|err| err.to_string())
+    .and_then(|statuses| statuses);
+    let statuses = match reserved {
+        Ok(statuses) => statuses,
+        Err(err) => {
+            log::error!("counting login attempt: {}", err);
+            return next.call(req).await.map(|res| res.map_into_boxed_body());
+        }
+    };
+    let locked_for = statuses.iter().filter_map(|status| status.locked_for).max();
+    // past the limit without a lockout only while the attempts before it are still running
+    let over_limit = statuses
+        .iter()
+        .zip(&keys)
+        .any(|(status, (_, max_failures))| status.failures > *max_failures);
+    if locked_for.is_some() || over_limit {
+        release(
+            throttle.clone(),
+            keys.iter().map(|(key, _)| key.clone()).collect(),
+        )
+        .await;
+        return Err(ServeReplicaError::TooManyRequests(
+            String::from("too many failed logins; try again later"),
+            locked_for
+                .unwrap_or(throttle.config.max_delay)
+                .as_secs()
+                .max(1),
+        )
+        .into());
+    }
+    let failures = statuses
+        .iter()
+        .map(|status| status.failures.saturating_sub(1))
+        .max();
+    let delay = throttle.config.delay(failures.unwrap_or(0));
+    if !delay.is_zero() {
+        actix_web::rt::time::sleep(delay).await;
+    }
+
+    let res = next.call(req).await;
+    let (succeeded, failed) = match &res {
+        // `/api/authorise` redirects with a code, or shows its page again with `401` for bad
+        // credentials
+        Ok(res) if authorise => (
+            res.status() == actix_web::http::StatusCode::SEE_OTHER,
+            res.status() == actix_web::http::StatusCode::UNAUTHORIZED,
+        ),
+        Ok(res) => (
+            res.status().is_success(),
+            // the scaffold's own password grant fails only on bad credentials
+            matches!(
+                res.status(),
+                actix_web::http::StatusCode::BAD_REQUEST
+                    | actix_web::http::StatusCode::UNAUTHORIZED
+            ),
+        ),
+        Err(err) => (
+            false,
+            err.as_error::<ServeReplicaError>()
+                .is_some_and(is_login_failure),
+        ),
+    };
+    if failed {
+        let recorded = actix_web::web::block(move || {
+            for ((key, max_failures), status) in keys.iter().zip(&statuses) {
+                if status.failures >= *max_failures {
+                    throttle.store.lock(key, throttle.config.lockout)?;
+                    log::warn!(
+                        "locked out {} for {}s after {} failed logins",
+                        key,
+                        throttle.config.lockout.as_secs(),
+                        status.failures
+                    );
+                }
+            }
+            Ok::<_, String>(())
+        })
+        .await;
+        match recorded {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => log::error!("recording login attempt: {}", err),
+            Err(err) => log::error!("recording login attempt: {}", err),
+        }
+    } else if succeeded {
+        // the username's failures are forgotten, but an IP's must not be cleared by its own
+        // valid login
+        let [(user_key, _), (ip_key, _)] = keys;
+        let reset = actix_web::web::block(move || {
+            throttle.store.reset(&user_key)?;
+            throttle.store.release(&ip_key)
+        })
+        .await;
+        match reset {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => log::error!("recording login attempt: {}", err),
+            Err(err) => log::error!("recording login attempt: {}", err),
+        }
+    } else {
+        release(throttle, keys.into_iter().map(|(key, _)| key).collect()).await;
+    }
+    res.map(|res| res.map_into_boxed_body())
+}
+
+/// Release attempts counted against `keys` that did not fail
+async fn release(throttle: actix_web::web::Data<LoginThrottle>, keys: Vec<String>) {
+    let released =
+        actix_web::web::block(move || keys.iter().try_for_each(|key| throttle.store.release(key)))
+            .await;
+    match released {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => log::error!("releasing login attempt: {}", err),
+        Err(err) => log::error!("releasing login attempt: {}", err),
+    }
+}
@@ -0,0 +1,686 @@
+use crate::auth::authorise::verify_pkce;
+use crate::auth::introspection::authenticate_client;
+use crate::auth::mfa::verify_second_factor;
+use crate::auth::oidc::{id_token_for, OidcProvider};
+use crate::auth::scopes::{format_scopes, parse_scopes, Scope};
+use crate::errors::ServeReplicaError;
+use crate::models::access_token::{hash_token, AccessToken, NewAccessToken, TokenClient};
+use crate::models::authorization_code::AuthorizationCode;
+use crate::models::confidential_client::ConfidentialClient;
+use crate::models::refresh_token::{NewRefreshToken, RefreshToken};
+use crate::models::user_account::UserAccount;
+use crate::models::user_credential::UserCredential;
+use crate::DbPool;
+
+pub const TOKEN_PATH: &str = "/api/token";
+/// Error of a password grant with a wrong username or password
+pub const INVALID_CREDENTIALS: &str = "invalid_grant: invalid username or password";
+
+/// Lifetimes of the tokens `/api/token` issues
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenConfig {
+    /// upper bound on access token lifetime, also for tokens the scaffold issues
+    pub access_ttl: std::time::Duration,
+    pub refresh_ttl: std::time::Duration,
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        Self {
+            access_ttl: std::time::Duration::from_secs(60 * 60),
+            refresh_ttl: std::time::Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl TokenConfig {
+    fn access_expires_at(&self) -> chrono::NaiveDateTime {
+        chrono::Utc::now().naive_utc()
+            + chrono::Duration::from_std(self.access_ttl).unwrap_or_default()
+    }
+
+    fn refresh_expires_at(&self) -> chrono::NaiveDateTime {
+        chrono::Utc::now().naive_utc()
+            + chrono::Duration::from_std(self.refresh_ttl).unwrap_or_default()
+    }
+}
+
+/// The parts of an `/api/token` request serve-replica acts on; the rest is the scaffold's
+#[derive(Debug, Default, Clone, PartialEq, serde::Deserialize)]
+pub struct TokenForm {
+    pub grant_type: String,
+    pub username: Option<String>,
+    /// space-separated scopes requested; defaults to all user scopes, or for
+    /// `grant_type=refresh_token` to all scopes of the refresh token
+    pub scope: Option<String>,
+    pub refresh_token: Option<String>,
+    /// for `grant_type=password`
+    pub password: Option<String>,
+    /// for `grant_type=password` by users with TOTP enabled: the current code, or a recovery code
+    pub otp: Option<String>,
+    /// for `grant_type=client_credentials`, unless sent with HTTP Basic authentication; for
+    /// other grants, a client sending its secret is authenticated, and ID tokens are addressed
+    /// to it
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// for `grant_type=authorization_code`: the code `/api/authorise` sent to `redirect_uri`
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    /// PKCE verifier of the request's `code_challenge`
+    pub code_verifier: Option<String>,
+}
+
+/// Token response for the grants serve-replica issues itself
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    /// seconds until `access_token` expires
+    pub expires_in: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+    /// OpenID Connect ID token, when the `openid` scope was granted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+/// 256 bits of randomness, base64url encoded
+pub fn generate_token() -> String {
+    use base64::Engine;
+    use rand::RngCore;
+
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub(crate) fn bytes_to_payload(buf: actix_web::web::Bytes) -> actix_web::dev::Payload {
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(buf);
+    actix_web::dev::Payload::from(payload)
+}
+
+pub(crate) fn ensure_enabled(
+    conn: &mut diesel::PgConnection,
+    username: &str,
+) -> Result<(), ServeReplicaError> {
+    if UserAccount::is_disabled(conn, username)? {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "invalid_grant: account disabled",
+        )));
+    }
+    Ok(())
+}
+
+fn issue_refresh_token(
+    conn: &mut diesel::PgConnection,
+    config: &TokenConfig,
+    family_id: &str,
+    username: &str,
+    scopes: &str,
+) -> diesel::QueryResult<String> {
+    let refresh_token = generate_token();
+    RefreshToken::insert(
+        conn,
+        &NewRefreshToken {
+            token_hash: &hash_token(&refresh_token),
+            family_id,
+            username,
+            scopes,
+            expires_at: config.refresh_expires_at(),
+        },
+    )?;
+    Ok(refresh_token)
+}
+
+/// `grant_type=refresh_token`: rotate the refresh token and mint a new access token.
+///
+/// Presenting an already-rotated refresh token means it leaked, so its whole family,
+/// access tokens included, is revoked.
+pub fn refresh_token_grant(
+    conn: &mut diesel::PgConnection,
+    config: &TokenConfig,
+    oidc: Option<&OidcProvider>,
+    form: &TokenForm,
+    audience: Option<&str>,
+) -> Result<TokenResponse, ServeReplicaError> {
+    use diesel::Connection;
+
+    let invalid_grant =
+        || ServeReplicaError::BadRequest(String::from("invalid_grant: invalid refresh_token"));
+    let presented = form.refresh_token.as_deref().ok_or_else(|| {
+        ServeReplicaError::BadRequest(String::from("invalid_request: refresh_token is required"))
+    })?;
+    let record = RefreshToken::find(conn, presented)?.ok_or_else(invalid_grant)?;
+    if record.revoked_at.is_some() || record.expires_at <= chrono::Utc::now().naive_utc() {
+        return Err(invalid_grant());
+    }
+    ensure_enabled(conn, &record.username)?;
+    let granted = parse_scopes(&record.scopes)?;
+    let scopes = match &form.scope {
+        Some(requested) => {
+            let requested = parse_scopes(requested)?;
+            if !requested.iter().all(|scope| granted.contains(scope)) {
+                return Err(ServeReplicaError::BadRequest(String::from(
+                    "invalid_scope: exceeds the scopes originally granted",
+                )));
+            }
+            requested
+        }
+        None => granted,
+    };
+    let scope = format_scopes(&scopes);
+
+    let rotated = conn.transaction(|conn| {
+        if !RefreshToken::mark_used(conn, record.id)? {
+            return Ok(None);
+        }
+        let access_token = generate_token();
+        AccessToken::record(
+            conn,
+            &NewAccessToken {
+                token_hash: &hash_token(&access_token),
+                username: Some(&record.username),
+                scopes: &scope,
+                expires_at: Some(config.access_expires_at()),
+                family_id: Some(&record.family_id),
+                local: true,
+            },
+        )?;
+        let refresh_token =
+            issue_refresh_token(conn, config, &record.family_id, &record.username, &scope)?;
+        Ok::<_, diesel::result::Error>(Some(TokenResponse {
+            access_token,
+            token_type: String::from("Bearer"),
+            expires_in: config.access_ttl.as_secs() as i64,
+            refresh_token: Some(refresh_token),
+            scope: scope.clone(),
+            id_token: None,
+        }))
+    })?;
+    match rotated {
+        Some(mut token) => {
+            token.id_token = id_token_for(oidc, &scopes, &record.username, audience, None)?;
+            Ok(token)
+        }
+        None => {
+            log::warn!(
+                "refresh token reuse for {}; revoking token family {}",
+                record.username,
+                record.family_id
+            );
+            conn.transaction(|conn| {
+                RefreshToken::revoke_family(conn, &record.family_id)?;
+                AccessToken::revoke_family(conn, &record.family_id)
+            })?;
+            Err(invalid_grant())
+        }
+    }
+}
+
+/// Check the password, and second factor, of `username`, who registered through
+/// `/api/register`, and that their account is enabled.
+///
+/// Returns `Ok(false)` if serve-replica holds no credentials for `username`.
+pub fn verify_login(
+    conn: &mut diesel::PgConnection,
+    username: &str,
+    password: &str,
+    otp: Option<&str>,
+) -> Result<bool, ServeReplicaError> {
+    let Some(credential) = UserCredential::find(conn, username)? else {
+        return Ok(false);
+    };
+    if !credential.verify_password(password) {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            INVALID_CREDENTIALS,
+        )));
+    }
+    if credential.email_verified_at.is_none() {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "invalid_grant: email not verified",
+        )));
+    }
+    verify_second_factor(conn, username, otp)?;
+    // only now, so that a wrong password does not reveal whether the account is disabled
+    ensure_enabled(conn, username)?;
+    Ok(true)
+}
+
+/// `grant_type=password` for users who registered through `/api/register`, whose credentials
+/// serve-replica holds; users unknown to it are left to the scaffold.
+///
+/// Returns `Ok(None)` if `username` did not register here.
+pub fn password_grant(
+    conn: &mut diesel::PgConnection,
+    config: &TokenConfig,
+    oidc: Option<&OidcProvider>,
+    form: &TokenForm,
+    scopes: &[Scope],
+    audience: Option<&str>,
+) -> Result<Option<TokenResponse>, ServeReplicaError> {
+    let Some(username) = form.username.as_deref() else {
+        return Ok(None);
+    };
+    let password = form.password.as_deref().unwrap_or_default();
+    if !verify_login(conn, username, password, form.otp.as_deref())? {
+        return Ok(None);
+    }
+    issue_user_token(conn, config, oidc, username, scopes, audience, None).map(Some)
+}
+
+/// `grant_type=authorization_code`: exchange a code from `/api/authorise` for the scopes the
+/// user approved there, with a refresh token, and an ID token addressed to the client the code
+/// was issued to.
+///
+/// `client_id` must be that client; the code is bound to it by its registered redirect URI and
+/// the PKCE `code_verifier`, and by its secret if it sent one. Each code works once.
+pub fn authorization_code_grant(
+    conn: &mut diesel::PgConnection,
+    config: &TokenConfig,
+    oidc: Option<&OidcProvider>,
+    form: &TokenForm,
+    client_id: Option<&str>,
+) -> Result<TokenResponse, ServeReplicaError> {
+    let invalid_grant = || {
+        ServeReplicaError::BadRequest(String::from(
+            "invalid_grant: invalid, expired or already used code",
+        ))
+    };
+    let (Some(code), Some(code_verifier)) = (form.code.as_deref(), form.code_verifier.as_deref())
+    else {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "invalid_request: code and code_verifier are required",
+        )));
+    };
+    let record = AuthorizationCode::consume(conn, code)?.ok_or_else(invalid_grant)?;
+    if client_id != Some(record.client_id.as_str())
+        || form.redirect_uri.as_deref() != Some(record.redirect_uri.as_str())
+        || !verify_pkce(code_verifier, &record.code_challenge)
+    {
+        return Err(invalid_grant());
+    }
+    ensure_enabled(conn, &record.username)?;
+    issue_user_token(
+        conn,
+        config,
+        oidc,
+        &record.username,
+        &parse_scopes(&record.scopes)?,
+        Some(&record.client_id),
+        record.nonce.as_deref(),
+    )
+}
+
+/// Access and refresh token for `username`, who has just logged in, in a new token family; the
+/// ID token, if `openid` is granted, is addressed to `audience`
+pub fn issue_user_token(
+    conn: &mut diesel::PgConnection,
+    config: &TokenConfig,
+    oidc: Option<&OidcProvider>,
+    username: &str,
+    scopes: &[Scope],
+    audience: Option<&str>,
+    nonce: Option<&str>,
+) -> Result<TokenResponse, ServeReplicaError> {
+    use diesel::Connection;
+
+    let scope = format_scopes(scopes);
+    let id_token = id_token_for(oidc, scopes, username, audience, nonce)?;
+    let token = conn.transaction(|conn| {
+        let family_id = generate_token();
+        let access_token = generate_token();
+        AccessToken::record(
+            conn,
+            &NewAccessToken {
+                token_hash: &hash_token(&access_token),
+                username: Some(username),
+                scopes: &scope,
+                expires_at: Some(config.access_expires_at()),
+                family_id: Some(&family_id),
+                local: true,
+            },
+        )?;
+        let refresh_token = issue_refresh_token(conn, config, &family_id, username, &scope)?;
+        Ok::<_, diesel::result::Error>(TokenResponse {
+            access_token,
+            token_type: String::from("Bearer"),
+            expires_in: config.access_ttl.as_secs() as i64,
+            refresh_token: Some(refresh_token),
+            scope: scope.clone(),
+            id_token,
+        })
+    })?;
+    Ok(token)
+}
+
+/// Client id and secret from HTTP Basic authentication, or failing that from the form body
+pub fn client_credentials(
+    authorization: Option<&str>,
+    client_id: Option<&str>,
+    client_secret: Option<&str>,
+) -> Option<(String, String)> {
+    use base64::Engine;
+
+    let from_header = authorization
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| {
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded.trim())
+                .ok()
+        })
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|decoded| {
+            decoded
+                .split_once(':')
+                .map(|(id, secret)| (id.to_owned(), secret.to_owned()))
+        });
+    from_header.or_else(|| {
+        client_id
+            .zip(client_secret)
+            .map(|(id, secret)| (id.to_owned(), secret.to_owned()))
+    })
+}
+
+/// `grant_type=client_credentials`: a token acting as the client's owner, limited to the
+/// client's scopes. No refresh token is issued; the client simply asks again.
+pub fn client_credentials_grant(
+    conn: &mut diesel::PgConnection,
+    config: &TokenConfig,
+    client_id: &str,
+    client_secret: &str,
+    scope: Option<&str>,
+) -> Result<TokenResponse, ServeReplicaError> {
+    let client =
+        ConfidentialClient::authenticate(conn, client_id, client_secret)?.ok_or_else(|| {
+            ServeReplicaError::Unauthorised(String::from(
+                "invalid_client: unknown client or secret",
+            ))
+        })?;
+    ensure_enabled(conn, &client.owner)?;
+    let granted = parse_scopes(&client.scopes)?;
+    let scopes = match scope {
+        Some(requested) => {
+            let requested = parse_scopes(requested)?;
+            if !requested.iter().all(|scope| granted.contains(scope)) {
+                return Err(ServeReplicaError::BadRequest(String::from(
+                    "invalid_scope: exceeds the scopes registered for the client",
+                )));
+            }
+            requested
+        }
+        None => granted,
+    };
+    let scope = format_scopes(&scopes);
+
+    let access_token = generate_token();
+    AccessToken::record(
+        conn,
+        &NewAccessToken {
+            token_hash: &hash_token(&access_token),
+            username: Some(&client.owner),
+            scopes: &scope,
+            expires_at: Some(config.access_expires_at()),
+            family_id: Some(&ConfidentialClient::token_family(&client.client_id)),
+            local: true,
+        },
+    )?;
+    Ok(TokenResponse {
+        access_token,
+        token_type: String::from("Bearer"),
+        expires_in: config.access_ttl.as_secs() as i64,
+        refresh_token: None,
+        scope,
+        id_token: None,
+    })
+}
+
+/// Scopes a user's token gets for the requested `scope`: those, or by default all user scopes
+pub fn granted_scopes(scope: Option<&str>) -> Result<Vec<Scope>, ServeReplicaError> {
+    match scope {
+        Some(scope) => parse_scopes(scope),
+        None => Ok(Scope::user_defaults()),
+    }
+}
+
+/// Middleware around `rust_actix_diesel_auth_scaffold::routes::token::token`.
+///
+/// Handles `grant_type=refresh_token`, `grant_type=client_credentials`,
+/// `grant_type=authorization_code`, and the password grant of users who registered through
+/// `/api/register`, itself. For the scaffold's own users' password grant it validates the
+/// requested `scope`, lets the scaffold issue the token, then records the granted scopes against
+/// it in `access_tokens`, caps its lifetime, and adds a `refresh_token`. Grants of the `openid`
+/// scope also get an `id_token`, addressed to the client if it authenticated, else the issuer.
+pub async fn token_endpoint(
+    mut req: actix_web::dev::ServiceRequest,
+    next: actix_web::middleware::Next<impl actix_web::body::MessageBody + 'static>,
+) -> Result<actix_web::dev::ServiceResponse, actix_web::Error> {
+    if req.method() != actix_web::http::Method::POST || req.path() != TOKEN_PATH {
+        return next.call(req).await.map(|res| res.map_into_boxed_body());
+    }
+    let body = req.extract::<actix_web::web::Bytes>().await?;
+    let form: TokenForm = serde_urlencoded::from_bytes(&body)
+        .map_err(|err| ServeReplicaError::BadRequest(format!("invalid_request: {}", err)))?;
+    let pool = req
+        .app_data::<actix_web::web::Data<DbPool>>()
+        .cloned()
+        .ok_or_else(|| ServeReplicaError::Database(String::from("no database pool")))?;
+    let config = req
+        .app_data::<actix_web::web::Data<TokenConfig>>()
+        .cloned()
+        .unwrap_or_default();
+    let oidc = req
+        .app_data::<actix_web::web::Data<OidcProvider>>()
+        .cloned();
+    let client = TokenClient::of(req.request());
+    let credentials = client_credentials(
+        req.headers()
+            .get(actix_web::http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok()),
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    );
+
+    if form.grant_type == "client_credentials" {
+        let (client_id, client_secret) = credentials.ok_or_else(|| {
+            ServeReplicaError::Unauthorised(String::from(
+                "invalid_client: client authentication is required",
+            ))
+        })?;
+        let token = actix_web::web::block(move || {
+            let mut conn = pool.get()?;
+            let token = client_credentials_grant(
+                &mut conn,
+                &config,
+                &client_id,
+                &client_secret,
+                form.scope.as_deref(),
+            )?;
+            AccessToken::note_client(&mut conn, &token.access_token, &client)?;
+            Ok::<_, ServeReplicaError>(token)
+        })
+        .await??;
+        return Ok(req.into_response(actix_web::HttpResponse::Ok().json(token)));
+    }
+    // a client's own say-so is not enough to have ID tokens addressed to it
+    let audience = match credentials {
+        Some((client_id, client_secret)) => {
+            let pool = pool.clone();
+            Some(
+                actix_web::web::block(move || {
+                    let mut conn = pool.get()?;
+                    authenticate_client(&mut conn, &client_id, &client_secret)
+                        .map(|client| client.client_id)
+                })
+                .await??,
+            )
+        }
+        None => None,
+    };
+
+    if form.grant_type == "refresh_token" || form.grant_type == "authorization_code" {
+        let token = actix_web::web::block(move || {
+            let mut conn = pool.get()?;
+            let token = match form.grant_type.as_str() {
+                "refresh_token" => refresh_token_grant(
+                    &mut conn,
+                    &config,
+                    oidc.as_deref(),
+                    &form,
+                    audience.as_deref(),
+                )?,
+                _ => authorization_code_grant(
+                    &mut conn,
+                    &config,
+                    oidc.as_deref(),
+                    &form,
+                    audience.as_deref().or(form.client_id.as_deref()),
+                )?,
+            };
+            AccessToken::note_client(&mut conn, &token.access_token, &client)?;
+            Ok::<_, ServeReplicaError>(token)
+        })
+        .await??;
+        return Ok(req.into_response(actix_web::HttpResponse::Ok().json(token)));
+    }
+
+    let scopes = granted_scopes(form.scope.as_deref())?;
+    if form.username.is_some() {
+        let (pool, config, oidc, form, scopes, client, audience) = (
+            pool.clone(),
+            config.clone(),
+            oidc.clone(),
+            form.clone(),
+            scopes.clone(),
+            client.clone(),
+            audience.clone(),
+        );
+        let token = actix_web::web::block(move || {
+            let mut conn = pool.get()?;
+            let token = match form.grant_type.as_str() {
+                "password" => password_grant(
+                    &mut conn,
+                    &config,
+                    oidc.as_deref(),
+                    &form,
+                    &scopes,
+                    audience.as_deref(),
+                )?,
+                _ => None,
+            };
+            if let Some(token) = &token {
+                AccessToken::note_client(&mut conn, &token.access_token, &client)?;
+            }
+            Ok::<_, ServeReplicaError>(token)
+        })
+        .await??;
+        if let Some(token) = token {
+            return Ok(req.into_response(actix_web::HttpResponse::Ok().json(token)));
+        }
+    }
+    req.set_payload(bytes_to_payload(body));
+
+    let res = next.call(req).await?;
+    if !res.status().is_success() {
+        return Ok(res.map_into_boxed_body());
+    }
+    let (http_req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let body = actix_web::body::to_bytes(body)
+        .await
+        .map_err(|_| ServeReplicaError::Upstream(String::from("unreadable token response")))?;
+    let mut token: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&body)
+        .map_err(|err| ServeReplicaError::Upstream(err.to_string()))?;
+    let access_token = token
+        .get("access_token")
+        .and_then(serde_json::Value::as_str)
+        .ok_or_else(|| {
+            ServeReplicaError::Upstream(String::from("token response has no access_token"))
+        })?
+        .to_owned();
+    let expires_in = token
+        .get("expires_in")
+        .and_then(serde_json::Value::as_i64)
+        .map_or(config.access_ttl.as_secs() as i64, |expires_in| {
+            expires_in.min(config.access_ttl.as_secs() as i64)
+        });
+
+    let scope = format_scopes(&scopes);
+    let granted = scope.clone();
+    let id_token = match (form.grant_type.as_str(), &form.username) {
+        ("password", Some(username)) => id_token_for(
+            oidc.as_deref(),
+            &scopes,
+            username,
+            audience.as_deref(),
+            None,
+        )?,
+        _ => None,
+    };
+    let refresh_token = actix_web::web::block(move || {
+        use diesel::Connection;
+
+        let mut conn = pool.get()?;
+        conn.transaction(|conn| {
+            let family_id = generate_token();
+            let record = AccessToken::record(
+                conn,
+                &NewAccessToken {
+                    token_hash: &hash_token(&access_token),
+                    username: form.username.as_deref(),
+                    scopes: &granted,
+                    expires_at: Some(
+                        chrono::Utc::now().naive_utc() + chrono::Duration::seconds(expires_in),
+                    ),
+                    family_id: Some(&family_id),
+                    local: false,
+                },
+            )?;
+            AccessToken::note_client(conn, &access_token, &client)?;
+            match (form.grant_type.as_str(), &form.username) {
+                ("password", Some(username)) => {
+                    // the scaffold knows nothing of second factors or disabled accounts, so its
+                    // token is kept but revoked, lest the bearer validator take it for one issued
+                    // before scopes
+                    let verified = verify_second_factor(conn, username, form.otp.as_deref())
+                        .and_then(|()| ensure_enabled(conn, username));
+                    if let Err(err) = verified {
+                        AccessToken::revoke(conn, record.id)?;
+                        return Ok(Err(err));
+                    }
+                    issue_refresh_token(conn, &config, &family_id, username, &granted)
+                        .map(|refresh_token| Ok(Some(refresh_token)))
+                }
+                _ => Ok(Ok(None)),
+            }
+        })
+        .map_err(ServeReplicaError::from)
+    })
+    .await???;
+
+    token.insert(String::from("scope"), serde_json::Value::String(scope));
+    token.insert(
+        String::from("expires_in"),
+        serde_json::Value::from(expires_in),
+    );
+    match refresh_token {
+        Some(refresh_token) => token.insert(
+            String::from("refresh_token"),
+            serde_json::Value::String(refresh_token),
+        ),
+        None => token.remove("refresh_token"),
+    };
+    if let Some(id_token) = id_token {
+        token.insert(
+            String::from("id_token"),
+            serde_json::Value::String(id_token),
+        );
+    }
+    let body =
+        serde_json::to_vec(&token).map_err(|err| ServeReplicaError::Upstream(err.to_string()))?;
+    Ok(actix_web::dev::ServiceResponse::new(
+        http_req,
+        res.set_body(body).map_into_boxed_body(),
+    ))
+}
@@ -0,0 +1,440 @@
+//! Subcommands besides `serve`: migrations, the OpenAPI spec, user accounts and health checks
+
+use diesel::Connection;
+use diesel_migrations::MigrationHarness;
+
+use crate::auth::account::{username_taken, validate_email, validate_password, validate_username};
+use crate::auth::roles::Role;
+use crate::errors::ServeReplicaError;
+use crate::listen::Listen;
+use crate::models::access_token::AccessToken;
+use crate::models::refresh_token::RefreshToken;
+use crate::models::user_account::{UserAccount, UserAccountQuery};
+use crate::models::user_credential::{hash_password, NewUserCredential, UserCredential};
+use crate::{exit_with, MIGRATIONS};
+
+/// Report a failure to do as asked and exit, unlike [`exit_with`] for bad configuration
+fn fail(err: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", err);
+    std::process::exit(1)
+}
+
+fn connect(env: &indexmap::IndexMap<String, String>) -> diesel::PgConnection {
+    let database_url = env
+        .get("DATABASE_URL")
+        .unwrap_or_else(|| exit_with("DATABASE_URL must be set"));
+    diesel::PgConnection::establish(database_url)
+        .unwrap_or_else(|err| fail(format!("connecting to DATABASE_URL: {}", err)))
+}
+
+#[derive(Clone, clap::Subcommand)]
+pub enum MigrateCommand {
+    /// Run the pending migrations of serve-replica and the libraries it builds on (the default)
+    Run,
+    /// Roll back serve-replica's latest migrations; the libraries' are left as they are
+    Revert {
+        /// How many migrations to roll back
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+    /// List serve-replica's migrations, and whether each is applied
+    List,
+}
+
+pub fn migrate(env: &indexmap::IndexMap<String, String>, command: MigrateCommand) {
+    let mut conn = connect(env);
+    match command {
+        MigrateCommand::Run => {
+            let applied = crate::run_migrations(&mut conn)
+                .unwrap_or_else(|err| fail(format!("running migrations: {}", err)));
+            for version in &applied {
+                println!("applied {}", version);
+            }
+            if applied.is_empty() {
+                println!("no pending migrations");
+            }
+        }
+        MigrateCommand::Revert { steps } => {
+            for _ in 0..steps {
+                let version = conn
+                    .revert_last_migration(MIGRATIONS)
+                    .unwrap_or_else(|err| fail(format!("reverting migration: {}", err)));
+                println!("reverted {}", version);
+            }
+        }
+        MigrateCommand::List => {
+            let applied = conn
+                .applied_migrations()
+                .unwrap_or_else(|err| fail(format!("reading applied migrations: {}", err)));
+            let migrations =
+                diesel_migrations::MigrationSource::<diesel::pg::Pg>::migrations(&MIGRATIONS)
+                    .unwrap_or_else(|err| fail(err));
+            for migration in migrations {
+                let name = migration.name();
+                let mark = if applied.iter().any(|version| *version == name.version()) {
+                    'x'
+                } else {
+                    ' '
+                };
+                println!("[{}] {}", mark, name);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum SpecFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+#[derive(Clone, clap::Args)]
+pub struct OpenapiArgs {
+    /// File to write the spec to, instead of stdout
+    #[arg(short, long)]
+    output: Option<std::path::PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = SpecFormat::Json)]
+    format: SpecFormat,
+}
+
+/// Write the OpenAPI spec, as served at `/api-docs/openapi.json`, without a database
+pub fn openapi(env: &indexmap::IndexMap<String, String>, args: OpenapiArgs) -> std::io::Result<()> {
+    let spec = crate::openapi_spec(env.get("CRAWL_URL").map(String::as_str));
+    let mut spec = match args.format {
+        SpecFormat::Json => serde_json::to_string_pretty(&spec).map_err(std::io::Error::other)?,
+        SpecFormat::Yaml => serde_yaml::to_string(&spec).map_err(std::io::Error::other)?,
+    };
+    if !spec.ends_with('\n') {
+        spec.push('\n');
+    }
+    match args.output {
+        Some(path) => std::fs::write(path, spec),
+        None => std::io::Write::write_all(&mut std::io::stdout(), spec.as_bytes()),
+    }
+}
+
+#[derive(Clone, clap::Subcommand)]
+pub enum UserCommand {
+    /// List users who have used serve-replica, as `GET /api/admin/users` does
+    List {
+        /// Only users with this role: user, moderator or admin
+        #[arg(long)]
+        role: Option<String>,
+        /// Only disabled users
+        #[arg(long, conflicts_with = "enabled")]
+        disabled: bool,
+        /// Only enabled users
+        #[arg(long)]
+        enabled: bool,
+    },
+    /// Create a user with a verified email; their password is read from stdin, e.g.,
+    /// `printf %s "$PASSWORD" | serve-replica user create alice alice@example.com`
+    Create {
+        username: String,
+        email: String,
+        /// user, moderator or admin
+        #[arg(long, default_value_t = String::from(Role::User.as_str()))]
+        role: String,
+    },
+    /// Change a user's role to user, moderator or admin
+    SetRole { username: String, role: String },
+    /// Set a user's password, read from stdin, signing them out everywhere
+    SetPassword { username: String },
+    /// Disable a user; none of their tokens, API keys or clients work until re-enabled
+    Disable { username: String },
+    /// Re-enable a disabled user
+    Enable { username: String },
+}
+
+fn parse_role(role: &str) -> Role {
+    role.parse().unwrap_or_else(|err| exit_with(err))
+}
+
+/// First line of stdin, without its line ending
+fn read_password() -> String {
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .unwrap_or_else(|err| fail(format!("reading password from stdin: {}", err)));
+    password.trim_end_matches(['\r', '\n']).to_owned()
+}
+
+fn no_such_user(username: &str) -> ServeReplicaError {
+    ServeReplicaError::NotFound(format!("user {} not found", username))
+}
+
+fn print_account(account: &UserAccount) {
+    println!(
+        "{}\t{}\t{}\t{}\t{}",
+        account.username,
+        account.role,
+        account
+            .disabled_at
+            .map_or(String::from("-"), |disabled_at| disabled_at.to_string()),
+        account.credits_used,
+        account.last_seen_at
+    );
+}
+
+pub fn user(env: &indexmap::IndexMap<String, String>, command: UserCommand) {
+    let mut conn = connect(env);
+    let result: Result<(), ServeReplicaError> = match command {
+        UserCommand::List {
+            role,
+            disabled,
+            enabled,
+        } => {
+            let mut query = UserAccountQuery {
+                page: Some(0),
+                per_page: Some(UserAccountQuery::MAX_PER_PAGE),
+                role: role.as_deref().map(|role| parse_role(role).to_string()),
+                disabled: (disabled || enabled).then_some(disabled),
+            };
+            println!("username\trole\tdisabled_at\tcredits_used\tlast_seen_at");
+            loop {
+                let page = UserAccount::list(&mut conn, &query)
+                    .unwrap_or_else(|err| fail(format!("listing users: {}", err)));
+                page.users.iter().for_each(print_account);
+                if (page.page + 1) * page.per_page >= page.total {
+                    break Ok(());
+                }
+                query.page = Some(page.page + 1);
+            }
+        }
+        UserCommand::Create {
+            username,
+            email,
+            role,
+        } => {
+            let role = parse_role(&role);
+            let email = email.trim().to_lowercase();
+            let password = read_password();
+            validate_username(&username)
+                .and_then(|()| validate_email(&email))
+                .and_then(|()| validate_password(&password))
+                .unwrap_or_else(|err| exit_with(err));
+            let password_hash =
+                hash_password(&password).unwrap_or_else(|err| fail(err.to_string()));
+            conn.transaction(|conn| {
+                if username_taken(conn, &username)? {
+                    return Err(ServeReplicaError::BadRequest(String::from(
+                        "username already taken",
+                    )));
+                }
+                if UserCredential::find_by_email(conn, &email)?.is_some() {
+                    return Err(ServeReplicaError::BadRequest(String::from(
+                        "email already registered",
+                    )));
+                }
+                UserCredential::insert(
+                    conn,
+                    &NewUserCredential {
+                        username: &username,
+                        email: &email,
+                        password_hash: &password_hash,
+                    },
+                )?;
+                // whoever runs this vouches for the address
+                UserCredential::mark_verified(conn, &username)?;
+                UserAccount::touch(conn, &username)?;
+                let account = UserAccount::set_role(conn, &username, role.as_str())?
+                    .ok_or_else(|| no_such_user(&username))?;
+                print_account(&account);
+                Ok(())
+            })
+        }
+        UserCommand::SetRole { username, role } => {
+            let role = parse_role(&role);
+            UserAccount::set_role(&mut conn, &username, role.as_str())
+                .map_err(ServeReplicaError::from)
+                .and_then(|account| account.ok_or_else(|| no_such_user(&username)))
+                .map(|account| print_account(&account))
+        }
+        UserCommand::SetPassword { username } => {
+            let password = read_password();
+            validate_password(&password).unwrap_or_else(|err| exit_with(err));
+            let password_hash =
+                hash_password(&password).unwrap_or_else(|err| fail(err.to_string()));
+            conn.transaction(|conn| {
+                if UserCredential::set_password_hash(conn, &username, &password_hash)? == 0 {
+                    return Err(ServeReplicaError::NotFound(format!(
+                        "user {} has no password login",
+                        username
+                    )));
+                }
+                RefreshToken::revoke_user(conn, &username)?;
+                AccessToken::revoke_user(conn, &username)?;
+                Ok(())
+            })
+        }
+        UserCommand::Disable { username } => UserAccount::set_disabled(&mut conn, &username, true)
+            .map_err(ServeReplicaError::from)
+            .and_then(|account| account.ok_or_else(|| no_such_user(&username)))
+            .map(|account| {
+                log::warn!("disabled account {} from the command line", username);
+                print_account(&account)
+            }),
+        UserCommand::Enable { username } => UserAccount::set_disabled(&mut conn, &username, false)
+            .map_err(ServeReplicaError::from)
+            .and_then(|account| account.ok_or_else(|| no_such_user(&username)))
+            .map(|account| print_account(&account)),
+    };
+    result.unwrap_or_else(|err| fail(err));
+}
+
+#[derive(Clone, clap::Args)]
+pub struct HealthcheckArgs {
+    /// Address of the server, as for `--listen` [default: the first of LISTEN, else
+    /// SADAS_HOSTNAME:SADAS_PORT, with unspecified addresses like 0.0.0.0 probed on loopback]
+    #[arg(long, env = "HEALTHCHECK_ADDRESS")]
+    address: Option<String>,
+
+    /// Path to GET, healthy if it answers 2xx
+    #[arg(long, default_value = "/api")]
+    path: String,
+
+    /// Seconds to wait for the connection, and again for the response
+    #[arg(long, default_value_t = 5)]
+    timeout: u64,
+}
+
+/// Where to reach a server listening on `listen`: an unspecified address is reached on loopback
+pub fn probe_address(listen: Listen) -> Listen {
+    match listen {
+        Listen::Tcp(host, port) if host == "0.0.0.0" => {
+            Listen::Tcp(String::from("127.0.0.1"), port)
+        }
+        Listen::Tcp(host, port) if host == "::" => Listen::Tcp(String::from("::1"), port),
+        listen => listen,
+    }
+}
+
+/// Status code of an HTTP/1.x `response`, from its status line
+pub fn response_status(response: &[u8]) -> Option<u16> {
+    let status_line = response.split(|byte| *byte == b'\n').next()?;
+    let mut parts = std::str::from_utf8(status_line).ok()?.split_whitespace();
+    parts
+        .next()
+        .filter(|version| version.starts_with("HTTP/1."))?;
+    parts.next()?.parse().ok()
+}
+
+fn exchange(
+    mut stream: impl std::io::Read + std::io::Write,
+    request: &str,
+) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    stream.write_all(request.as_bytes())?;
+    let mut response = Vec::new();
+    // the status line is all that is needed
+    match stream.take(1024).read_to_end(&mut response) {
+        Ok(_) => Ok(response),
+        // a TLS server may hang up without `close_notify` once it has answered
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof && !response.is_empty() => {
+            Ok(response)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// GET `path` from the server at `address` over HTTP/1.1, returning the response's status.
+///
+/// With `tls`, TCP addresses are spoken to over HTTPS, without verifying the certificate, so
+/// only on loopback; Unix domain sockets are always plain HTTP.
+pub fn probe(
+    address: &Listen,
+    path: &str,
+    timeout: std::time::Duration,
+    tls: bool,
+) -> std::io::Result<u16> {
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nUser-Agent: {}-healthcheck\r\nConnection: close\r\n\r\n",
+        path,
+        crate::CARGO_PKG_NAME
+    );
+    let response = match address {
+        Listen::Tcp(host, port) => {
+            let socket_address = std::net::ToSocketAddrs::to_socket_addrs(&(host.as_str(), *port))?
+                .next()
+                .ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::NotFound, "no such address")
+                })?;
+            if tls && !socket_address.ip().is_loopback() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "HTTPS is only probed on loopback, where the certificate is not verified",
+                ));
+            }
+            let stream = std::net::TcpStream::connect_timeout(&socket_address, timeout)?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            if tls {
+                let config = crate::tls::loopback_client_config()
+                    .map_err(|err| std::io::Error::other(err))?;
+                let connection = rustls::ClientConnection::new(
+                    std::sync::Arc::new(config),
+                    rustls::pki_types::ServerName::IpAddress(socket_address.ip().into()),
+                )
+                .map_err(|err| std::io::Error::other(err))?;
+                exchange(rustls::StreamOwned::new(connection, stream), &request)?
+            } else {
+                exchange(stream, &request)?
+            }
+        }
+        Listen::Unix(path) => {
+            let stream = std::os::unix::net::UnixStream::connect(path)?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            exchange(stream, &request)?
+        }
+    };
+    response_status(&response)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "not an HTTP response"))
+}
+
+fn unhealthy(reason: impl std::fmt::Display) -> ! {
+    eprintln!("unhealthy: {}", reason);
+    std::process::exit(1)
+}
+
+/// Probe the server, exiting with 0 if healthy and 1 if not, as Docker's HEALTHCHECK expects
+pub fn healthcheck(
+    env: &indexmap::IndexMap<String, String>,
+    args: HealthcheckArgs,
+) -> std::io::Result<()> {
+    let address = match args.address.as_deref() {
+        Some(address) => address.to_owned(),
+        None => match env.get("LISTEN") {
+            Some(listen) => listen
+                .split(',')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_owned(),
+            None => format!(
+                "{}:{}",
+                env.get("SADAS_HOSTNAME")
+                    .map_or("localhost", String::as_str),
+                env.get("SADAS_PORT").map_or("3000", String::as_str)
+            ),
+        },
+    };
+    let address = probe_address(
+        address
+            .parse()
+            .unwrap_or_else(|err| unhealthy(format!("address {:?}: {}", address, err))),
+    );
+    match probe(
+        &address,
+        &args.path,
+        std::time::Duration::from_secs(args.timeout),
+        env.contains_key("TLS_CERT"),
+    ) {
+        Ok(status) if (200..300).contains(&status) => Ok(()),
+        Ok(status) => unhealthy(format!("{}{} answered {}", address, args.path, status)),
+        Err(err) => unhealthy(format!("{}{}: {}", address, args.path, err)),
+    }
+}
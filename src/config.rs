@@ -0,0 +1,586 @@
+//! Configuration: the optional TOML or YAML file, and the [`AppConfig`] built at startup.
+//!
+//! Each key of the file stands for the environment variable named alongside it, and is
+//! overridden by that variable, so precedence is: defaults < file < environment (host, then env
+//! file) < flags. [`AppConfig::from_vars`] then validates the merged variables once.
+
+use crate::auth::account::AccountConfig;
+use crate::auth::federation::FederationConfig;
+use crate::auth::throttle::ThrottleConfig;
+use crate::auth::token::TokenConfig;
+use crate::listen::Listen;
+use crate::proxy::TrustedProxies;
+use crate::swap::preprocess::Preprocessor;
+use crate::swap::worker::WorkerConfig;
+use crate::tls::TlsConfig;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    Read(String, String),
+    /// neither `.toml`, `.yaml` nor `.yml`
+    UnknownFormat(String),
+    Invalid(String, String),
+    /// keys, e.g., `swap.worker`, that the file has but no section does
+    UnknownKeys(String, Vec<String>),
+    /// one message per missing or malformed variable
+    InvalidVars(Vec<String>),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "reading config file {}: {}", path, err),
+            ConfigError::UnknownFormat(path) => {
+                write!(f, "config file {} must end in .toml, .yaml or .yml", path)
+            }
+            ConfigError::Invalid(path, err) => write!(f, "config file {}: {}", path, err),
+            ConfigError::UnknownKeys(path, keys) => write!(
+                f,
+                "config file {} has unknown keys: {}",
+                path,
+                keys.join(", ")
+            ),
+            ConfigError::InvalidVars(errors) => {
+                write!(f, "invalid configuration:")?;
+                errors
+                    .iter()
+                    .try_for_each(|error| write!(f, "\n  {}", error))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn of(path: &std::path::Path) -> Option<ConfigFormat> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct ServerSection {
+    /// `SADAS_HOSTNAME`
+    pub hostname: Option<String>,
+    /// `SADAS_PORT`
+    pub port: Option<u16>,
+    /// `LISTEN`, instead of `hostname` and `port`
+    pub listen: Option<Vec<String>>,
+    /// `SOCKET_MODE`, octal, e.g., `"660"`
+    pub socket_mode: Option<String>,
+    /// `PUBLIC_URL`
+    pub public_url: Option<String>,
+    /// `SWAGGER_UI_CLIENT_ID`
+    pub swagger_ui_client_id: Option<String>,
+    /// `TLS_CERT`
+    pub tls_cert: Option<String>,
+    /// `TLS_KEY`
+    pub tls_key: Option<String>,
+    /// `HTTP_REDIRECT_PORT`
+    pub http_redirect_port: Option<u16>,
+    /// `TRUSTED_PROXIES`
+    pub trusted_proxies: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct DatabaseSection {
+    /// `DATABASE_URL`
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct RedisSection {
+    /// `REDIS_URL`
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct CrawlSection {
+    /// `CRAWL_URL`
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct SwapSection {
+    /// `SWAP_URL`
+    pub url: Option<String>,
+    /// `SWAP_WORKERS`
+    pub workers: Option<usize>,
+    /// `SWAP_QUEUE_CAPACITY`
+    pub queue_capacity: Option<i64>,
+    /// `SWAP_JOB_TIMEOUT_SECS`
+    pub job_timeout_secs: Option<u64>,
+    /// `SWAP_MAX_ATTEMPTS`
+    pub max_attempts: Option<i32>,
+    /// `SWAP_MAX_IMG_DIMENSION`
+    pub max_img_dimension: Option<u32>,
+    /// `SWAP_IMG_FORMAT`
+    pub img_format: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct MailSection {
+    /// `MAILER`
+    pub mailer: Option<String>,
+    /// `MAIL_FILE`
+    pub file: Option<String>,
+    /// `SMTP_URL`
+    pub smtp_url: Option<String>,
+    /// `MAIL_FROM`
+    pub from: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct FederationSection {
+    /// `FEDERATION_ISSUER`
+    pub issuer: Option<String>,
+    /// `FEDERATION_CLIENT_ID`
+    pub client_id: Option<String>,
+    /// `FEDERATION_CLIENT_SECRET`
+    pub client_secret: Option<String>,
+    /// `FEDERATION_SCOPE`
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct AuthSection {
+    /// `ACCESS_TOKEN_TTL_SECS`
+    pub access_token_ttl_secs: Option<u64>,
+    /// `REFRESH_TOKEN_TTL_SECS`
+    pub refresh_token_ttl_secs: Option<u64>,
+    /// `LOGIN_MAX_FAILURES`
+    pub login_max_failures: Option<u32>,
+    /// `LOGIN_MAX_IP_FAILURES`
+    pub login_max_ip_failures: Option<u32>,
+    /// `LOGIN_LOCKOUT_SECS`
+    pub login_lockout_secs: Option<u64>,
+    /// `OIDC_ISSUER`
+    pub oidc_issuer: Option<String>,
+    /// `OIDC_SIGNING_KEY_FILE`
+    pub oidc_signing_key_file: Option<String>,
+    pub mail: MailSection,
+    pub federation: FederationSection,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct LogSection {
+    /// `RUST_LOG`
+    pub level: Option<String>,
+    /// `LOG_FORMAT`
+    pub format: Option<String>,
+}
+
+/// Contents of a configuration file; every key is optional
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct ConfigFile {
+    pub server: ServerSection,
+    pub database: DatabaseSection,
+    pub redis: RedisSection,
+    pub crawl: CrawlSection,
+    pub swap: SwapSection,
+    pub auth: AuthSection,
+    pub log: LogSection,
+}
+
+impl ConfigFile {
+    pub fn load(path: &std::path::Path) -> Result<ConfigFile, ConfigError> {
+        let name = path.display().to_string();
+        let format =
+            ConfigFormat::of(path).ok_or_else(|| ConfigError::UnknownFormat(name.clone()))?;
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| ConfigError::Read(name.clone(), err.to_string()))?;
+        ConfigFile::parse(&name, &contents, format)
+    }
+
+    /// Parse `contents` of the file `name`, rejecting every key no section has rather than
+    /// ignoring typos
+    pub fn parse(
+        name: &str,
+        contents: &str,
+        format: ConfigFormat,
+    ) -> Result<ConfigFile, ConfigError> {
+        let mut unknown = Vec::new();
+        let on_unknown = |path: serde_ignored::Path| unknown.push(path.to_string());
+        let file = match format {
+            ConfigFormat::Toml => {
+                serde_ignored::deserialize(toml::Deserializer::new(contents), on_unknown)
+                    .map_err(|err| err.to_string())
+            }
+            ConfigFormat::Yaml => {
+                serde_ignored::deserialize(serde_yaml::Deserializer::from_str(contents), on_unknown)
+                    .map_err(|err| err.to_string())
+            }
+        }
+        .map_err(|err| ConfigError::Invalid(name.to_owned(), err))?;
+        if unknown.is_empty() {
+            Ok(file)
+        } else {
+            Err(ConfigError::UnknownKeys(name.to_owned(), unknown))
+        }
+    }
+
+    /// The environment variables the file sets, by name
+    pub fn to_vars(&self) -> Vec<(&'static str, String)> {
+        fn var<T: ToString>(
+            name: &'static str,
+            value: &Option<T>,
+        ) -> Option<(&'static str, String)> {
+            value.as_ref().map(|value| (name, value.to_string()))
+        }
+
+        let ConfigFile {
+            server,
+            database,
+            redis,
+            crawl,
+            swap,
+            auth,
+            log,
+        } = self;
+        [
+            var("SADAS_HOSTNAME", &server.hostname),
+            var("SADAS_PORT", &server.port),
+            var(
+                "LISTEN",
+                &server.listen.as_ref().map(|listen| listen.join(",")),
+            ),
+            var("SOCKET_MODE", &server.socket_mode),
+            var("PUBLIC_URL", &server.public_url),
+            var("SWAGGER_UI_CLIENT_ID", &server.swagger_ui_client_id),
+            var("TLS_CERT", &server.tls_cert),
+            var("TLS_KEY", &server.tls_key),
+            var("HTTP_REDIRECT_PORT", &server.http_redirect_port),
+            var(
+                "TRUSTED_PROXIES",
+                &server
+                    .trusted_proxies
+                    .as_ref()
+                    .map(|proxies| proxies.join(",")),
+            ),
+            var("DATABASE_URL", &database.url),
+            var("REDIS_URL", &redis.url),
+            var("CRAWL_URL", &crawl.url),
+            var("SWAP_URL", &swap.url),
+            var("SWAP_WORKERS", &swap.workers),
+            var("SWAP_QUEUE_CAPACITY", &swap.queue_capacity),
+            var("SWAP_JOB_TIMEOUT_SECS", &swap.job_timeout_secs),
+            var("SWAP_MAX_ATTEMPTS", &swap.max_attempts),
+            var("SWAP_MAX_IMG_DIMENSION", &swap.max_img_dimension),
+            var("SWAP_IMG_FORMAT", &swap.img_format),
+            var("ACCESS_TOKEN_TTL_SECS", &auth.access_token_ttl_secs),
+            var("REFRESH_TOKEN_TTL_SECS", &auth.refresh_token_ttl_secs),
+            var("LOGIN_MAX_FAILURES", &auth.login_max_failures),
+            var("LOGIN_MAX_IP_FAILURES", &auth.login_max_ip_failures),
+            var("LOGIN_LOCKOUT_SECS", &auth.login_lockout_secs),
+            var("OIDC_ISSUER", &auth.oidc_issuer),
+            var("OIDC_SIGNING_KEY_FILE", &auth.oidc_signing_key_file),
+            var("MAILER", &auth.mail.mailer),
+            var("MAIL_FILE", &auth.mail.file),
+            var("SMTP_URL", &auth.mail.smtp_url),
+            var("MAIL_FROM", &auth.mail.from),
+            var("FEDERATION_ISSUER", &auth.federation.issuer),
+            var("FEDERATION_CLIENT_ID", &auth.federation.client_id),
+            var("FEDERATION_CLIENT_SECRET", &auth.federation.client_secret),
+            var("FEDERATION_SCOPE", &auth.federation.scope),
+            var("RUST_LOG", &log.level),
+            var("LOG_FORMAT", &log.format),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// Where account emails go, per `MAILER`
+#[derive(Debug, Clone, PartialEq)]
+pub enum MailerConfig {
+    Log,
+    File(String),
+    Smtp { url: String, from: String },
+}
+
+/// Every setting of the server, validated once at startup and shared as `web::Data`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppConfig {
+    pub hostname: String,
+    pub port: u16,
+    /// `hostname` and `port` unless `LISTEN` is set
+    pub listen: Vec<Listen>,
+    /// file mode of the Unix domain sockets in `listen`
+    pub socket_mode: u32,
+    /// HTTPS on the TCP addresses of `listen` when set, else plain HTTP
+    pub tls: Option<TlsConfig>,
+    /// peers whose `Forwarded`/`X-Forwarded-For` give the client's IP
+    pub trusted_proxies: TrustedProxies,
+    pub database_url: String,
+    /// login throttling is shared through Redis when set, else kept in memory
+    pub redis_url: Option<String>,
+    /// crawler documented as the server of `/v1/crawl`, if not proxied under this origin
+    pub crawl_url: Option<String>,
+    pub swagger_ui_client_id: Option<String>,
+    /// faceswap service `/v1/swap` forwards to
+    pub swap_url: String,
+    pub preprocessor: Preprocessor,
+    pub worker: WorkerConfig,
+    pub token: TokenConfig,
+    pub account: AccountConfig,
+    pub throttle: ThrottleConfig,
+    pub mailer: MailerConfig,
+    pub oidc_issuer: String,
+    /// PEM file of the ID token signing key; without one a key is generated at startup
+    pub oidc_signing_key_file: Option<String>,
+    pub federation: Option<FederationConfig>,
+}
+
+/// The merged variables, collecting what is wrong with them rather than stopping at the first
+struct Vars<'a> {
+    vars: &'a indexmap::IndexMap<String, String>,
+    errors: Vec<String>,
+}
+
+impl<'a> Vars<'a> {
+    fn get(&self, name: &str) -> Option<&'a str> {
+        self.vars.get(name).map(String::as_str)
+    }
+
+    fn string(&self, name: &str) -> Option<String> {
+        self.get(name).map(str::to_owned)
+    }
+
+    fn required(&mut self, name: &str, when: &str) -> String {
+        self.string(name).unwrap_or_else(|| {
+            self.errors.push(format!("{} must be set{}", name, when));
+            String::new()
+        })
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self, name: &str, expected: &str) -> Option<T> {
+        let value = self.get(name)?;
+        match value.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                self.errors
+                    .push(format!("{} must be {}, not {:?}", name, expected, value));
+                None
+            }
+        }
+    }
+
+    fn secs(&mut self, name: &str) -> Option<std::time::Duration> {
+        self.parse(name, "a non-negative integer")
+            .map(std::time::Duration::from_secs)
+    }
+}
+
+impl AppConfig {
+    /// Build from the merged variables (file, environment and flags), starting from defaults
+    pub fn from_vars(vars: &indexmap::IndexMap<String, String>) -> Result<AppConfig, ConfigError> {
+        let mut vars = Vars {
+            vars,
+            errors: Vec::new(),
+        };
+        let hostname = vars
+            .string("SADAS_HOSTNAME")
+            .unwrap_or(String::from("localhost"));
+        let port = vars.parse("SADAS_PORT", "a port number").unwrap_or(3000u16);
+        let listen = match vars.get("LISTEN") {
+            Some(addresses) => {
+                let addresses: Vec<&str> = addresses
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|address| !address.is_empty())
+                    .collect();
+                if addresses.is_empty() {
+                    vars.errors
+                        .push(String::from("LISTEN must have at least one address"));
+                }
+                addresses
+                    .into_iter()
+                    .filter_map(|address| match address.parse() {
+                        Ok(listen) => Some(listen),
+                        Err(err) => {
+                            vars.errors
+                                .push(format!("LISTEN has {:?}: {}", address, err));
+                            None
+                        }
+                    })
+                    .collect()
+            }
+            None => vec![Listen::Tcp(hostname.clone(), port)],
+        };
+        let socket_mode = match vars.get("SOCKET_MODE") {
+            Some(mode) => crate::listen::parse_mode(mode).unwrap_or_else(|| {
+                vars.errors.push(format!(
+                    "SOCKET_MODE must be an octal file mode, not {:?}",
+                    mode
+                ));
+                crate::listen::DEFAULT_SOCKET_MODE
+            }),
+            None => crate::listen::DEFAULT_SOCKET_MODE,
+        };
+        let redirect_port = vars.parse("HTTP_REDIRECT_PORT", "a port number");
+        let tls = match (vars.string("TLS_CERT"), vars.string("TLS_KEY")) {
+            (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+                cert_path: cert_path.into(),
+                key_path: key_path.into(),
+                redirect_port,
+            }),
+            (None, None) => {
+                if redirect_port.is_some() {
+                    vars.errors.push(String::from(
+                        "HTTP_REDIRECT_PORT needs TLS_CERT and TLS_KEY set",
+                    ));
+                }
+                None
+            }
+            (Some(_), None) => {
+                vars.errors
+                    .push(String::from("TLS_KEY must be set with TLS_CERT"));
+                None
+            }
+            (None, Some(_)) => {
+                vars.errors
+                    .push(String::from("TLS_CERT must be set with TLS_KEY"));
+                None
+            }
+        };
+        let trusted_proxies = match vars.get("TRUSTED_PROXIES") {
+            Some(proxies) => proxies.parse().unwrap_or_else(|err| {
+                vars.errors
+                    .push(format!("TRUSTED_PROXIES is invalid: {}", err));
+                TrustedProxies::default()
+            }),
+            None => TrustedProxies::default(),
+        };
+        let database_url = vars.required("DATABASE_URL", "");
+
+        let mut preprocessor = Preprocessor::default();
+        if let Some(max_dimension) = vars.parse("SWAP_MAX_IMG_DIMENSION", "a positive integer") {
+            preprocessor.max_dimension = max_dimension;
+        }
+        if let Some(format) = vars.parse("SWAP_IMG_FORMAT", "jpeg or png") {
+            preprocessor.format = format;
+        }
+        let mut worker = WorkerConfig::default();
+        if let Some(concurrency) = vars.parse("SWAP_WORKERS", "a non-negative integer") {
+            worker.concurrency = concurrency;
+        }
+        if let Some(queue_capacity) = vars.parse("SWAP_QUEUE_CAPACITY", "an integer") {
+            worker.queue_capacity = queue_capacity;
+        }
+        if let Some(job_timeout) = vars.secs("SWAP_JOB_TIMEOUT_SECS") {
+            worker.job_timeout = job_timeout;
+        }
+        if let Some(max_attempts) = vars.parse("SWAP_MAX_ATTEMPTS", "an integer") {
+            worker.max_attempts = max_attempts;
+        }
+
+        let mut token = TokenConfig::default();
+        if let Some(access_ttl) = vars.secs("ACCESS_TOKEN_TTL_SECS") {
+            token.access_ttl = access_ttl;
+        }
+        if let Some(refresh_ttl) = vars.secs("REFRESH_TOKEN_TTL_SECS") {
+            token.refresh_ttl = refresh_ttl;
+        }
+        let mut account = AccountConfig::default();
+        if let Some(public_url) = vars.string("PUBLIC_URL") {
+            account.public_url = public_url;
+        }
+        let mut throttle = ThrottleConfig::default();
+        if let Some(max_failures) =
+            vars.parse::<std::num::NonZeroU32>("LOGIN_MAX_FAILURES", "a positive integer")
+        {
+            throttle.max_user_failures = max_failures.get();
+        }
+        if let Some(max_failures) =
+            vars.parse::<std::num::NonZeroU32>("LOGIN_MAX_IP_FAILURES", "a positive integer")
+        {
+            throttle.max_ip_failures = max_failures.get();
+        }
+        if let Some(lockout) = vars.secs("LOGIN_LOCKOUT_SECS") {
+            throttle.lockout = lockout;
+        }
+        let mailer = match vars.get("MAILER").unwrap_or("log") {
+            "log" => MailerConfig::Log,
+            "file" => {
+                MailerConfig::File(vars.string("MAIL_FILE").unwrap_or(String::from("mail.log")))
+            }
+            "smtp" => MailerConfig::Smtp {
+                url: vars.required("SMTP_URL", " when MAILER=smtp"),
+                from: vars.required("MAIL_FROM", " when MAILER=smtp"),
+            },
+            other => {
+                vars.errors
+                    .push(format!("MAILER must be log, file or smtp, not {:?}", other));
+                MailerConfig::Log
+            }
+        };
+
+        let oidc_issuer = vars.string("OIDC_ISSUER").unwrap_or_else(|| {
+            let scheme = if tls.is_some() { "https" } else { "http" };
+            format!("{}://{}:{}", scheme, hostname, port)
+        });
+        let federation = vars.string("FEDERATION_ISSUER").map(|issuer| {
+            let mut federation = FederationConfig::new(
+                &issuer,
+                &vars.required("FEDERATION_CLIENT_ID", " with FEDERATION_ISSUER"),
+                &vars.required("FEDERATION_CLIENT_SECRET", " with FEDERATION_ISSUER"),
+                &oidc_issuer,
+            );
+            if let Some(scope) = vars.string("FEDERATION_SCOPE") {
+                federation.scope = scope;
+            }
+            federation
+        });
+
+        let config = AppConfig {
+            hostname,
+            port,
+            listen,
+            socket_mode,
+            tls,
+            trusted_proxies,
+            database_url,
+            redis_url: vars.string("REDIS_URL"),
+            crawl_url: vars.string("CRAWL_URL"),
+            swagger_ui_client_id: vars.string("SWAGGER_UI_CLIENT_ID"),
+            swap_url: vars
+                .string("SWAP_URL")
+                .unwrap_or(String::from("http://localhost:3003")),
+            preprocessor,
+            worker,
+            token,
+            account,
+            throttle,
+            mailer,
+            oidc_issuer,
+            oidc_signing_key_file: vars.string("OIDC_SIGNING_KEY_FILE"),
+            federation,
+        };
+        if vars.errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError::InvalidVars(vars.errors))
+        }
+    }
+}
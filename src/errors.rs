@@ -0,0 +1,81 @@
+/// Errors from serve-replica's own routes; rendered like the `JsonConfig` handler: `{"error": "..."}`
+#[derive(Debug)]
+pub enum ServeReplicaError {
+    BadRequest(String),
+    Unauthorised(String),
+    Forbidden(String),
+    NotFound(String),
+    Upstream(String),
+    Database(String),
+    /// the request may succeed if retried after this many seconds
+    ServiceUnavailable(String, u64),
+    /// rate limited; retry after this many seconds
+    TooManyRequests(String, u64),
+}
+
+impl std::fmt::Display for ServeReplicaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServeReplicaError::BadRequest(msg)
+            | ServeReplicaError::Unauthorised(msg)
+            | ServeReplicaError::Forbidden(msg)
+            | ServeReplicaError::NotFound(msg)
+            | ServeReplicaError::Upstream(msg)
+            | ServeReplicaError::Database(msg)
+            | ServeReplicaError::ServiceUnavailable(msg, _)
+            | ServeReplicaError::TooManyRequests(msg, _) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for ServeReplicaError {}
+
+impl actix_web::ResponseError for ServeReplicaError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            ServeReplicaError::BadRequest(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            ServeReplicaError::Unauthorised(_) => actix_web::http::StatusCode::UNAUTHORIZED,
+            ServeReplicaError::Forbidden(_) => actix_web::http::StatusCode::FORBIDDEN,
+            ServeReplicaError::NotFound(_) => actix_web::http::StatusCode::NOT_FOUND,
+            ServeReplicaError::Upstream(_) => actix_web::http::StatusCode::BAD_GATEWAY,
+            ServeReplicaError::Database(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ServeReplicaError::ServiceUnavailable(_, _) => {
+                actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+            }
+            ServeReplicaError::TooManyRequests(_, _) => {
+                actix_web::http::StatusCode::TOO_MANY_REQUESTS
+            }
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse {
+        let mut response = actix_web::HttpResponse::build(self.status_code());
+        if let ServeReplicaError::ServiceUnavailable(_, retry_after)
+        | ServeReplicaError::TooManyRequests(_, retry_after) = self
+        {
+            response.insert_header((actix_web::http::header::RETRY_AFTER, *retry_after));
+        }
+        response.json(serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+impl From<diesel::result::Error> for ServeReplicaError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            diesel::result::Error::NotFound => ServeReplicaError::NotFound(err.to_string()),
+            _ => ServeReplicaError::Database(err.to_string()),
+        }
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for ServeReplicaError {
+    fn from(err: diesel::r2d2::PoolError) -> Self {
+        ServeReplicaError::Database(err.to_string())
+    }
+}
+
+impl From<actix_web::error::BlockingError> for ServeReplicaError {
+    fn from(err: actix_web::error::BlockingError) -> Self {
+        ServeReplicaError::Database(err.to_string())
+    }
+}
@@ -106,13 +106,64 @@ lazy_static::lazy_static! {
 )]
 pub struct SwapPostRequest {
     pub user_img_url: String,
-    pub model_img_url: String,
+    /// raw model image; mutually exclusive with `model_id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_img_url: Option<String>,
+    /// id of a stored `PersonModel` to swap against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_id: Option<i32>,
+    /// index into that `PersonModel`'s images, defaults to 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_img_idx: Option<usize>,
 }
 
 #[derive(
     Default, Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
 )]
 pub struct SwapPostResponse {
+    /// id of the swap job, to poll `/v1/swap/{id}` with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
     pub output_url: String,
+    /// one of "queued", "running", "completed" or "failed"
     pub status: String,
+    /// why the last attempt failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One model of a batch swap: a stored `PersonModel` or a raw model image URL
+#[derive(
+    Default, Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+pub struct SwapBatchModel {
+    /// raw model image; mutually exclusive with `model_id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_img_url: Option<String>,
+    /// id of a stored `PersonModel` to swap against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_id: Option<i32>,
+    /// index into that `PersonModel`'s images, defaults to 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_img_idx: Option<usize>,
+}
+
+#[derive(
+    Default, Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+pub struct SwapBatchPostRequest {
+    pub user_img_url: String,
+    pub models: Vec<SwapBatchModel>,
+}
+
+#[derive(
+    Default, Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema,
+)]
+pub struct SwapBatchPostResponse {
+    /// id of the parent batch, to poll `/v1/swap/batch/{id}` with
+    pub id: i32,
+    /// one of "queued", "running", "completed", "partial" or "failed"
+    pub status: String,
+    /// one entry per model, in request order
+    pub children: Vec<SwapPostResponse>,
 }
@@ -0,0 +1,121 @@
+//! Addresses the server listens on: TCP, over IPv4 or IPv6, and Unix domain sockets
+
+/// Prefix of a `--listen` address naming a Unix domain socket
+pub const UNIX_PREFIX: &str = "unix:";
+/// File mode Unix domain sockets are created with, unless `SOCKET_MODE` says otherwise
+pub const DEFAULT_SOCKET_MODE: u32 = 0o660;
+
+/// One `--listen` address, e.g., `0.0.0.0:3000`, `[::]:3000` or `unix:/run/serve-replica.sock`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Listen {
+    /// host without brackets, as `ToSocketAddrs` wants it, and port
+    Tcp(String, u16),
+    Unix(std::path::PathBuf),
+}
+
+impl std::str::FromStr for Listen {
+    type Err = String;
+
+    fn from_str(address: &str) -> Result<Listen, String> {
+        if let Some(path) = address.strip_prefix(UNIX_PREFIX) {
+            return match path {
+                "" => Err(String::from("a socket path must follow `unix:`")),
+                path => Ok(Listen::Unix(path.into())),
+            };
+        }
+        let (host, port) = address
+            .rsplit_once(':')
+            .ok_or_else(|| String::from("expected host:port or unix:path"))?;
+        let port = port
+            .parse()
+            .map_err(|_| format!("{:?} is not a port number", port))?;
+        let host = match host.strip_prefix('[') {
+            Some(host) => host
+                .strip_suffix(']')
+                .ok_or_else(|| String::from("unclosed `[` around IPv6 address"))?,
+            // an unbracketed IPv6 address is ambiguous, as its last group could be the port
+            None if host.contains(':') => {
+                return Err(String::from(
+                    "IPv6 addresses must be in brackets, e.g., [::]",
+                ))
+            }
+            None => host,
+        };
+        if host.is_empty() {
+            return Err(String::from("missing host"));
+        }
+        Ok(Listen::Tcp(host.to_owned(), port))
+    }
+}
+
+impl std::fmt::Display for Listen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Listen::Tcp(host, port) if host.contains(':') => write!(f, "[{}]:{}", host, port),
+            Listen::Tcp(host, port) => write!(f, "{}:{}", host, port),
+            Listen::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// Hosts of the TCP addresses in `listen`, each once, in order
+pub fn tcp_hosts(listen: &[Listen]) -> Vec<&str> {
+    let mut hosts = Vec::new();
+    for address in listen {
+        if let Listen::Tcp(host, _) = address {
+            if !hosts.contains(&host.as_str()) {
+                hosts.push(host.as_str());
+            }
+        }
+    }
+    hosts
+}
+
+/// `SOCKET_MODE`-style octal file mode, e.g., `660` or `0o660`
+pub fn parse_mode(mode: &str) -> Option<u32> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+}
+
+/// Listen on the Unix domain socket at `path`, replacing a stale one left by an earlier run,
+/// with its file `mode` set before any connection is accepted.
+///
+/// A socket is only stale if connecting to it is refused; one that still accepts
+/// connections belongs to a running server, so binding fails instead.
+#[cfg(unix)]
+pub fn bind_unix(
+    path: &std::path::Path,
+    mode: u32,
+) -> std::io::Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            match std::os::unix::net::UnixStream::connect(path) {
+                Ok(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AddrInUse,
+                        format!("{} is in use by another server", path.display()),
+                    ))
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    std::fs::remove_file(path)?
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
@@ -0,0 +1,230 @@
+//! Log output, as text or JSON lines, and the access log middleware
+
+use actix_web::HttpMessage;
+
+use crate::audit::REQUEST_ID_HEADER;
+use crate::auth::AuthenticatedUser;
+
+/// Target of access log records, e.g., to filter them with `--log-level`
+pub const ACCESS_TARGET: &str = "serve_replica::access";
+/// What secrets are replaced with
+pub const REDACTED: &str = "[redacted]";
+/// Headers never logged as they are
+const SECRET_HEADERS: [&str; 5] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+/// Query parameters never logged as they are, e.g., the `code` of an OAuth2 redirect
+const SECRET_PARAMS: [&str; 9] = [
+    "access_token",
+    "refresh_token",
+    "id_token",
+    "token",
+    "code",
+    "code_verifier",
+    "client_secret",
+    "password",
+    "otp",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum LogFormat {
+    /// one line per record, fields appended as `key=value`
+    #[default]
+    Text,
+    /// one JSON object per record, fields as its keys
+    Json,
+}
+
+/// Collects a record's key-values
+struct Fields(Vec<(String, serde_json::Value)>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for Fields {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let value = match (value.to_u64(), value.to_f64()) {
+            (Some(number), _) => serde_json::Value::from(number),
+            (None, Some(number)) => serde_json::Value::from(number),
+            (None, None) => serde_json::Value::String(value.to_string()),
+        };
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+fn fields(record: &log::Record) -> Vec<(String, serde_json::Value)> {
+    let mut fields = Fields(Vec::new());
+    // visiting only fails if the visitor does
+    let _ = record.key_values().visit(&mut fields);
+    fields.0
+}
+
+fn format_text(buf: &mut env_logger::fmt::Formatter, record: &log::Record) -> std::io::Result<()> {
+    use std::io::Write;
+
+    write!(
+        buf,
+        "[{} {:<5} {}] {}",
+        buf.timestamp(),
+        record.level(),
+        record.target(),
+        record.args()
+    )?;
+    for (key, value) in fields(record) {
+        match value {
+            // unquoted, unlike in JSON
+            serde_json::Value::String(value) => write!(buf, " {}={}", key, value)?,
+            value => write!(buf, " {}={}", key, value)?,
+        }
+    }
+    writeln!(buf)
+}
+
+fn format_json(buf: &mut env_logger::fmt::Formatter, record: &log::Record) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut line = serde_json::Map::new();
+    line.insert(
+        String::from("timestamp"),
+        serde_json::Value::String(buf.timestamp().to_string()),
+    );
+    line.insert(
+        String::from("level"),
+        serde_json::Value::String(record.level().to_string()),
+    );
+    line.insert(
+        String::from("target"),
+        serde_json::Value::String(record.target().to_owned()),
+    );
+    line.insert(
+        String::from("message"),
+        serde_json::Value::String(record.args().to_string()),
+    );
+    line.extend(fields(record));
+    writeln!(buf, "{}", serde_json::Value::Object(line))
+}
+
+/// Start logging at `filter`, in `env_logger` syntax (e.g., `info` or
+/// `serve_replica=debug,actix_web=info`), defaulting to `info`
+/// The filter and format to log with: the flags', else `RUST_LOG` and `LOG_FORMAT` of the
+/// merged variables, so the config file's `[log]` counts and `--no-host-env` applies
+pub fn settings(
+    filter: Option<&str>,
+    format: Option<LogFormat>,
+    vars: &indexmap::IndexMap<String, String>,
+) -> Result<(String, LogFormat), String> {
+    let filter = filter
+        .or(vars.get("RUST_LOG").map(String::as_str))
+        .unwrap_or("info");
+    let format = match (format, vars.get("LOG_FORMAT")) {
+        (Some(format), _) => format,
+        (None, Some(format)) => clap::ValueEnum::from_str(format, true)
+            .map_err(|_| format!("LOG_FORMAT must be text or json, not {:?}", format))?,
+        (None, None) => LogFormat::default(),
+    };
+    Ok((filter.to_owned(), format))
+}
+
+pub fn init(filter: &str, format: LogFormat) {
+    let mut builder = env_logger::Builder::new();
+    builder.parse_filters(filter);
+    match format {
+        LogFormat::Text => builder.format(format_text),
+        LogFormat::Json => builder.format(format_json),
+    };
+    builder.init();
+}
+
+/// `headers` as logged, with credentials and cookies redacted
+pub fn redact_headers(headers: &actix_web::http::header::HeaderMap) -> Vec<(String, String)> {
+    let mut redacted: Vec<(String, String)> = headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if SECRET_HEADERS.contains(&name.as_str()) {
+                String::from(REDACTED)
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect();
+    redacted.sort();
+    redacted
+}
+
+/// `query` as logged, with the values of tokens, codes and passwords redacted
+pub fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if SECRET_PARAMS.contains(&name) => format!("{}={}", name, REDACTED),
+            _ => pair.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Middleware logging every request under [`ACCESS_TARGET`]: method, path with its query
+/// redacted, status, route pattern, latency, request id and user. At debug level the request
+/// headers are logged too, redacted.
+pub async fn access_log(
+    req: actix_web::dev::ServiceRequest,
+    next: actix_web::middleware::Next<impl actix_web::body::MessageBody + 'static>,
+) -> Result<actix_web::dev::ServiceResponse<impl actix_web::body::MessageBody>, actix_web::Error> {
+    let started = std::time::Instant::now();
+    let method = req.method().to_string();
+    let target = match req.query_string() {
+        "" => req.path().to_owned(),
+        query => format!("{}?{}", req.path(), redact_query(query)),
+    };
+    let ip = crate::proxy::client_ip(req.request()).unwrap_or_else(|| String::from("-"));
+    if log::log_enabled!(target: ACCESS_TARGET, log::Level::Debug) {
+        let headers = redact_headers(req.headers());
+        log::debug!(target: ACCESS_TARGET, "{} {} headers {:?}", method, target, headers);
+    }
+
+    let res = next.call(req).await;
+    // to the microsecond
+    let latency_ms = (started.elapsed().as_secs_f64() * 1_000_000.0).round() / 1000.0;
+    let (status, route, user, request_id) = match &res {
+        Ok(res) => (
+            res.status(),
+            res.request().match_pattern(),
+            res.request()
+                .extensions()
+                .get::<AuthenticatedUser>()
+                .map(|user| user.username.clone()),
+            res.headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+        ),
+        Err(err) => (err.as_response_error().status_code(), None, None, None),
+    };
+    let level = if status.is_server_error() {
+        log::Level::Warn
+    } else {
+        log::Level::Info
+    };
+    log::log!(
+        target: ACCESS_TARGET,
+        level,
+        status = status.as_u16(),
+        route = route.as_deref().unwrap_or("-"),
+        latency_ms = latency_ms,
+        request_id = request_id.as_deref().unwrap_or("-"),
+        user = user.as_deref().unwrap_or("-"),
+        ip = ip.as_str();
+        "{} {} {}",
+        method,
+        target,
+        status.as_u16()
+    );
+    res
+}
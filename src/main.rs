@@ -6,42 +6,155 @@ use utoipa_redoc::Servable;
 use utoipa_scalar::Servable as ScalarServable;
 
 use crate::extra_schemas::{
-    CrawledResult, ScraperPostBody, ScraperPostBodyResponse, SwapPostRequest, SwapPostResponse,
-    EXAMPLE_SCRAPED_RESULT,
+    CrawledResult, ScraperPostBody, ScraperPostBodyResponse, EXAMPLE_SCRAPED_RESULT,
 };
 
+mod audit;
+mod auth;
+mod commands;
+mod config;
+mod errors;
 mod extra_schemas;
+mod listen;
+mod logging;
+mod mail;
+mod models;
+mod proxy;
+mod routes;
+mod schema;
+mod swap;
 #[cfg(test)]
 mod tests;
+mod tls;
 
 pub const CARGO_PKG_DESCRIPTION: &'static str = env!("CARGO_PKG_DESCRIPTION");
 pub const CARGO_PKG_NAME: &'static str = env!("CARGO_PKG_NAME");
 pub const CARGO_PKG_VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+pub type DbPool = diesel::r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>;
+
+pub const MIGRATIONS: diesel_migrations::EmbeddedMigrations =
+    diesel_migrations::embed_migrations!();
+
+/// The libraries' embedded migrations, then serve-replica's, in the order they are run
+pub const ALL_MIGRATIONS: [diesel_migrations::EmbeddedMigrations; 3] = [
+    rust_actix_diesel_auth_scaffold::MIGRATIONS,
+    replica_backend::MIGRATIONS,
+    MIGRATIONS,
+];
+
+/// Run the pending migrations of [`ALL_MIGRATIONS`], returning the versions applied
+pub fn run_migrations(
+    conn: &mut diesel::PgConnection,
+) -> diesel::migration::Result<Vec<diesel::migration::MigrationVersion<'static>>> {
+    use diesel_migrations::MigrationHarness;
+
+    let mut applied = Vec::new();
+    for migrations in ALL_MIGRATIONS {
+        applied.extend(conn.run_pending_migrations(migrations)?);
+    }
+    Ok(applied)
+}
+
 #[derive(clap::Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// Hostname
-    #[arg(long, default_value = "localhost", env = "SADAS_HOSTNAME")]
-    hostname: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Port
-    #[arg(short, long, default_value_t = 3000u16, env = "SADAS_PORT")]
-    port: u16,
+    // `serve` runs when no subcommand is given, so its options are taken without one too
+    #[command(flatten)]
+    serve: ServeArgs,
+
+    /// Config file, TOML or YAML by extension; overridden by env vars and flags
+    #[arg(short, long, env = "SERVE_REPLICA_CONFIG", global = true)]
+    config: Option<std::path::PathBuf>,
+
+    /// Log filter, e.g., "info" or "serve_replica=debug,actix_web=info"; overrides RUST_LOG
+    /// [default: info]
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    /// Log output format; overrides LOG_FORMAT [default: text]
+    #[arg(long, value_enum, global = true)]
+    log_format: Option<logging::LogFormat>,
 
     /// Avoid inheriting host environment variables
-    #[arg(long, default_value_t = false)]
+    #[arg(long, default_value_t = false, global = true)]
     no_host_env: bool,
 
     /// Env file, defaults to ".env"
-    #[arg(long)]
+    #[arg(long, global = true)]
     env_file: Option<String>,
 
     /// Env var (can be specified multiple times, like `-eFOO=5 -eBAR=can`)
-    #[arg(short, long, action(clap::ArgAction::Append))]
+    #[arg(short, long, action(clap::ArgAction::Append), global = true)]
     env: Option<Vec<String>>,
 }
 
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Run the server (the default)
+    Serve(ServeArgs),
+    /// Run pending migrations, or roll serve-replica's back
+    Migrate {
+        #[command(subcommand)]
+        command: Option<commands::MigrateCommand>,
+    },
+    /// Print the OpenAPI spec
+    Openapi(commands::OpenapiArgs),
+    /// Manage user accounts
+    User {
+        #[command(subcommand)]
+        command: commands::UserCommand,
+    },
+    /// Probe a running server, exiting with 0 if it is healthy and 1 if not, e.g., for Docker's
+    /// HEALTHCHECK
+    Healthcheck(commands::HealthcheckArgs),
+}
+
+#[derive(clap::Args)]
+struct ServeArgs {
+    /// Hostname [default: localhost]
+    #[arg(long, env = "SADAS_HOSTNAME")]
+    hostname: Option<String>,
+
+    /// Port [default: 3000]
+    #[arg(short, long, env = "SADAS_PORT")]
+    port: Option<u16>,
+
+    /// Address to listen on instead of hostname and port, e.g., `[::]:3000` or
+    /// `unix:/run/serve-replica.sock` (can be specified multiple times)
+    #[arg(long, env = "LISTEN", value_delimiter = ',')]
+    listen: Vec<String>,
+
+    /// Octal file mode of `unix:` sockets [default: 660]
+    #[arg(long, env = "SOCKET_MODE")]
+    socket_mode: Option<String>,
+
+    /// PEM certificate chain; with `--tls-key`, serve HTTPS, reloading both on change or SIGHUP
+    #[arg(long, env = "TLS_CERT")]
+    tls_cert: Option<String>,
+
+    /// PEM private key of `--tls-cert`
+    #[arg(long, env = "TLS_KEY")]
+    tls_key: Option<String>,
+
+    /// Port to redirect plain HTTP from to HTTPS, with `--tls-cert`
+    #[arg(long, env = "HTTP_REDIRECT_PORT")]
+    http_redirect_port: Option<u16>,
+
+    /// Skip running pending migrations at startup, e.g., when `migrate` is a separate deploy step
+    #[arg(long, default_value_t = false)]
+    no_migrate: bool,
+}
+
+/// Report a configuration error and exit, as clap does for bad arguments
+fn exit_with(err: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", err);
+    std::process::exit(2)
+}
+
 const GET_CARGO_PKG_VERSION: fn() -> &'static str = || CARGO_PKG_VERSION;
 
 const GET_CARGO_PKG_NAME: fn() -> &'static str = || CARGO_PKG_NAME;
@@ -97,17 +210,18 @@ async fn version() -> actix_web::web::Json<Version> {
     actix_web::web::Json(VERSION)
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let args: Cli = clap::Parser::parse();
-
-    std::env::set_var("RUST_LOG", "debug");
-    env_logger::init();
+/// Merge the variables from, in increasing precedence: the config file, the host
+/// environment, the env file, and `-e`
+fn load_env(args: &Cli) -> indexmap::IndexMap<String, String> {
     let mut env = indexmap::IndexMap::<String, String>::new();
+    if let Some(path) = &args.config {
+        let file = config::ConfigFile::load(path).unwrap_or_else(|err| exit_with(err));
+        env.extend(file.to_vars().into_iter().map(|(k, v)| (k.to_string(), v)));
+    }
     if !args.no_host_env {
         env.extend(std::env::vars());
     }
-    let env_file = args.env_file.unwrap_or(String::from(".env"));
+    let env_file = args.env_file.as_deref().unwrap_or(".env");
     if let Ok(file_iter) = dotenvy::from_filename_iter(env_file) {
         for res in file_iter {
             if let Ok((k, v)) = res {
@@ -115,188 +229,396 @@ async fn main() -> std::io::Result<()> {
             }
         }
     }
-    if let Some(env_vec) = args.env {
+    if let Some(env_vec) = &args.env {
         env.extend(env_vec.iter().filter_map(|s| match s.split_once("=") {
             None => None,
             Some((k, v)) => Some((k.to_string(), v.to_string())),
         }));
     };
-    env.iter().for_each(|(k, v)| std::env::set_var(k, v));
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    env
+}
 
-    rust_actix_diesel_auth_scaffold::db_init();
-    replica_backend::db_init();
+#[derive(utoipa::OpenApi)]
+#[openapi(
+    info(license(name="")),
+    tags(
+        (name = CARGO_PKG_NAME, description = CARGO_PKG_DESCRIPTION)
+    ),
+    modifiers(&SecurityAddon, &Scraper)
+)]
+struct ApiDoc;
 
-    let manager = diesel::r2d2::ConnectionManager::<diesel::PgConnection>::new(database_url);
-    let pool = diesel::r2d2::Pool::builder().build(manager).unwrap();
+struct SecurityAddon;
 
-    #[derive(utoipa::OpenApi)]
-    #[openapi(
-        info(license(name="")),
-        tags(
-            (name = CARGO_PKG_NAME, description = CARGO_PKG_DESCRIPTION)
-        ),
-        modifiers(&SecurityAddon, &Scraper)
-    )]
-    struct ApiDoc;
-
-    struct SecurityAddon;
-
-    impl Modify for SecurityAddon {
-        fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
-            let components = openapi.components.as_mut().unwrap(); // we can unwrap safely since there already is components registered.
-            components.add_security_scheme(
-                "password",
-                utoipa::openapi::security::SecurityScheme::OAuth2(
-                    utoipa::openapi::security::OAuth2::new([
-                        utoipa::openapi::security::Flow::Password(
-                            utoipa::openapi::security::Password::new(
-                                "/api/token",
-                                utoipa::openapi::security::Scopes::new(),
-                            ),
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.as_mut().unwrap(); // we can unwrap safely since there already is components registered.
+        components.add_security_scheme(
+            "password",
+            utoipa::openapi::security::SecurityScheme::OAuth2(
+                utoipa::openapi::security::OAuth2::new([
+                    utoipa::openapi::security::Flow::Password(
+                        utoipa::openapi::security::Password::new(
+                            "/api/token",
+                            auth::scopes::openapi_scopes(),
                         ),
-                    ]),
-                ),
-            )
+                    ),
+                    // PKCE (`code_challenge`, S256) is required by `/api/authorise`
+                    utoipa::openapi::security::Flow::AuthorizationCode(
+                        utoipa::openapi::security::AuthorizationCode::new(
+                            "/api/authorise",
+                            "/api/token",
+                            auth::scopes::openapi_scopes(),
+                        ),
+                    ),
+                    // confidential clients registered under `/secured/clients`
+                    utoipa::openapi::security::Flow::ClientCredentials(
+                        utoipa::openapi::security::ClientCredentials::new(
+                            "/api/token",
+                            auth::scopes::openapi_scopes(),
+                        ),
+                    ),
+                ]),
+            ),
+        )
+    }
+}
+
+struct Scraper;
+
+impl Modify for Scraper {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(ref mut schemas) = openapi.components {
+            let actual_schemas = &mut schemas.schemas;
+            actual_schemas.insert(String::from("ScraperPostBody"), ScraperPostBody::schema());
+            actual_schemas.insert(
+                String::from("ScraperPostBodyResponse"),
+                ScraperPostBodyResponse::schema(),
+            );
+            actual_schemas.insert(String::from("CrawledResult"), CrawledResult::schema());
         }
+        openapi.paths.paths.insert(
+            String::from("/v1/crawl"),
+            utoipa::openapi::path::PathItemBuilder::new()
+                .summary(Some(String::from("Web crawler")))
+                .operation(
+                    utoipa::openapi::HttpMethod::Post,
+                    utoipa::openapi::path::OperationBuilder::new()
+                        .summary(Some(String::from("POST url to crawl")))
+                        .request_body(Some(
+                            utoipa::openapi::request_body::RequestBodyBuilder::new()
+                                .description(Some(String::from("URL to crawl")))
+                                .content(
+                                    mime::APPLICATION_JSON.to_string(),
+                                    utoipa::openapi::ContentBuilder::new()
+                                        .schema(Some(utoipa::openapi::Ref::from_schema_name(
+                                            "ScraperPostBody",
+                                        )))
+                                        .example(Some(
+                                            serde_json::json!({"url": "https://example.com"}),
+                                        ))
+                                        .build(),
+                                )
+                                .build(),
+                        ))
+                        .response(
+                            "200",
+                            utoipa::openapi::response::ResponseBuilder::new()
+                                .description("Crawled process update")
+                                .content(
+                                    mime::APPLICATION_JSON.to_string(),
+                                    utoipa::openapi::ContentBuilder::new()
+                                        .schema(Some(utoipa::openapi::Ref::from_schema_name(
+                                            "ScraperPostBodyResponse",
+                                        )))
+                                        .build(),
+                                ),
+                        ),
+                )
+                .build(),
+        );
+        openapi.paths.paths.insert(
+            String::from("/v1/crawl/{id}"),
+            utoipa::openapi::path::PathItemBuilder::new()
+                .summary(Some(String::from("Web crawler")))
+                .operation(
+                    utoipa::openapi::HttpMethod::Get,
+                    utoipa::openapi::path::OperationBuilder::new()
+                        .summary(Some(String::from("GET crawled result")))
+                        .parameter(
+                            utoipa::openapi::path::ParameterBuilder::new()
+                                .description(Some(String::from("ID of crawled result")))
+                                .name("id")
+                                .build(),
+                        )
+                        .response(
+                            "200",
+                            utoipa::openapi::response::ResponseBuilder::new()
+                                .description("Crawled result")
+                                .content(
+                                    mime::APPLICATION_JSON.to_string(),
+                                    utoipa::openapi::ContentBuilder::new()
+                                        .example(Some(EXAMPLE_SCRAPED_RESULT.to_owned()))
+                                        .schema(Some(utoipa::openapi::Ref::from_schema_name(
+                                            "CrawledResult",
+                                        )))
+                                        .build(),
+                                ),
+                        ),
+                )
+                .build(),
+        );
     }
+}
 
-    struct Scraper;
-
-    impl Modify for Scraper {
-        fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
-            if let Some(ref mut schemas) = openapi.components {
-                let actual_schemas = &mut schemas.schemas;
-                actual_schemas.insert(String::from("ScraperPostBody"), ScraperPostBody::schema());
-                actual_schemas.insert(
-                    String::from("ScraperPostBodyResponse"),
-                    ScraperPostBodyResponse::schema(),
-                );
-                actual_schemas.insert(String::from("CrawledResult"), CrawledResult::schema());
-                actual_schemas.insert(String::from("SwapPostRequest"), SwapPostRequest::schema());
-                actual_schemas.insert(String::from("SwapPostResponse"), SwapPostResponse::schema());
+/// The spec without its paths, which are collected from [`routes`]; the crawler's at `crawl_url`
+fn api_doc(crawl_url: Option<&str>) -> utoipa::openapi::OpenApi {
+    let mut openapi = ApiDoc::openapi();
+    if let Some(crawl_url) = crawl_url {
+        // the crawler is a separate service, normally proxied to under this one's origin
+        for path in ["/v1/crawl", "/v1/crawl/{id}"] {
+            if let Some(item) = openapi.paths.paths.get_mut(path) {
+                item.servers = Some(vec![utoipa::openapi::server::Server::new(crawl_url)]);
             }
-            openapi.paths.paths.insert(
-                String::from("/v1/crawl"),
-                utoipa::openapi::path::PathItemBuilder::new()
-                    .summary(Some(String::from("Web crawler")))
-                    .operation(
-                        utoipa::openapi::HttpMethod::Post,
-                        utoipa::openapi::path::OperationBuilder::new()
-                            .summary(Some(String::from("POST url to crawl")))
-                            .request_body(Some(
-                                utoipa::openapi::request_body::RequestBodyBuilder::new()
-                                    .description(Some(String::from("URL to crawl")))
-                                    .content(
-                                        mime::APPLICATION_JSON.to_string(),
-                                        utoipa::openapi::ContentBuilder::new()
-                                            .schema(Some(utoipa::openapi::Ref::from_schema_name(
-                                                "ScraperPostBody",
-                                            )))
-                                            .example(Some(
-                                                serde_json::json!({"url": "https://example.com"}),
-                                            ))
-                                            .build(),
-                                    )
-                                    .build(),
-                            ))
-                            .response(
-                                "200",
-                                utoipa::openapi::response::ResponseBuilder::new()
-                                    .description("Crawled process update")
-                                    .content(
-                                        mime::APPLICATION_JSON.to_string(),
-                                        utoipa::openapi::ContentBuilder::new()
-                                            .schema(Some(utoipa::openapi::Ref::from_schema_name(
-                                                "ScraperPostBodyResponse",
-                                            )))
-                                            .build(),
-                                    ),
-                            ),
-                    )
-                    .build(),
-            );
-            openapi.paths.paths.insert(
-                String::from("/v1/crawl/{id}"),
-                utoipa::openapi::path::PathItemBuilder::new()
-                    .summary(Some(String::from("Web crawler")))
-                    .operation(
-                        utoipa::openapi::HttpMethod::Get,
-                        utoipa::openapi::path::OperationBuilder::new()
-                            .summary(Some(String::from("GET crawled result")))
-                            .parameter(
-                                utoipa::openapi::path::ParameterBuilder::new()
-                                    .description(Some(String::from("ID of crawled result")))
-                                    .name("id")
-                                    .build(),
-                            )
-                            .response(
-                                "200",
-                                utoipa::openapi::response::ResponseBuilder::new()
-                                    .description("Crawled result")
-                                    .content(
-                                        mime::APPLICATION_JSON.to_string(),
-                                        utoipa::openapi::ContentBuilder::new()
-                                            .example(Some(EXAMPLE_SCRAPED_RESULT.to_owned()))
-                                            .schema(Some(utoipa::openapi::Ref::from_schema_name(
-                                                "CrawledResult",
-                                            )))
-                                            .build(),
-                                    ),
-                            ),
-                    )
-                    .build(),
-            );
+        }
+    }
+    openapi
+}
 
-            openapi.paths.paths.insert(
-                String::from("/v1/swap"),
-                utoipa::openapi::path::PathItemBuilder::new()
-                    .summary(Some(String::from("Faceswapper")))
-                    .operation(
-                        utoipa::openapi::HttpMethod::Post,
-                        utoipa::openapi::path::OperationBuilder::new()
-                            .summary(Some(String::from("POST to faceswap")))
-                            .request_body(Some(
-                                utoipa::openapi::request_body::RequestBodyBuilder::new()
-                                    .description(Some(String::from("Body to faceswap")))
-                                    .content(
-                                        mime::APPLICATION_JSON.to_string(),
-                                        utoipa::openapi::content::ContentBuilder::new()
-                                            .schema(Some(utoipa::openapi::Ref::from_schema_name(
-                                                "SwapPostRequest",
-                                            )))
-                                            .example(Some(serde_json::json!({
-                                                "user_img_url": "data:image/jpg;base64,fa",
-                                                "model_img_url": "https://a.com/a.jpg"
-                                            })))
-                                            .build(),
-                                    )
-                                    .build(),
-                            ))
-                            .response(
-                                "200",
-                                utoipa::openapi::response::ResponseBuilder::new()
-                                    .description("Faceswap response")
-                                    .content(
-                                        mime::APPLICATION_JSON.to_string(),
-                                        utoipa::openapi::ContentBuilder::new()
-                                            .schema(Some(utoipa::openapi::Ref::from_schema_name(
-                                                "SwapPostResponse",
-                                            )))
-                                            .build(),
-                                    ),
-                            ),
-                    )
-                    .build(),
+/// Every route, under its scope and middleware; also where the OpenAPI paths come from
+fn routes(cfg: &mut utoipa_actix_web::service_config::ServiceConfig) {
+    cfg.service(
+        utoipa_actix_web::scope("/api/v0")
+            .wrap(actix_web::middleware::from_fn(auth::scopes::require_scope))
+            .wrap(actix_web::middleware::Compat::new(
+                actix_web_httpauth::middleware::HttpAuthentication::bearer(auth::bearer::validator),
+            ))
+            .service(replica_backend::routes::model::read)
+            .service(replica_backend::routes::model::read_many)
+            .service(replica_backend::routes::model::upsert)
+            .service(replica_backend::routes::profile::read)
+            .service(replica_backend::routes::profile::upsert)
+            .service(routes::swaps::read_many)
+            .service(routes::swaps::read)
+            .service(routes::swaps::remove),
+    )
+    .service(
+        utoipa_actix_web::scope("/api/admin")
+            .wrap(actix_web::middleware::from_fn(
+                auth::roles::require_moderator,
+            ))
+            .wrap(actix_web::middleware::from_fn(auth::scopes::require_scope))
+            .wrap(actix_web::middleware::Compat::new(
+                actix_web_httpauth::middleware::HttpAuthentication::bearer(auth::bearer::validator),
+            ))
+            .service(routes::admin::read_users)
+            .service(routes::admin::read_user)
+            .service(routes::admin::update_role)
+            .service(routes::admin::disable)
+            .service(routes::admin::enable)
+            .service(routes::admin::reset_credits)
+            .service(routes::admin::read_audit_events)
+            .service(routes::admin::read_models)
+            .service(routes::admin::read_model)
+            .service(routes::admin::read_profile),
+    )
+    .service(
+        utoipa_actix_web::scope("/api")
+            .wrap(actix_web::middleware::from_fn(auth::token::token_endpoint))
+            .wrap(actix_web::middleware::from_fn(
+                auth::throttle::login_throttle,
+            ))
+            .service(rust_actix_diesel_auth_scaffold::routes::token::token)
+            .service(routes::authorise::authorise)
+            .service(routes::authorise::approve)
+            .service(routes::token::introspect_token)
+            .service(routes::token::revoke_token)
+            .service(routes::account::register)
+            .service(routes::account::verify_email)
+            .service(routes::account::resend_verification)
+            .service(routes::account::forgot_password)
+            .service(routes::account::reset_password)
+            .service(routes::federation::login)
+            .service(routes::federation::callback)
+            .service(version),
+    )
+    .service(
+        utoipa_actix_web::scope("/.well-known")
+            .service(routes::oidc::discovery)
+            .service(routes::oidc::jwks),
+    )
+    .service(
+        utoipa_actix_web::scope("/userinfo")
+            .wrap(actix_web::middleware::from_fn(auth::scopes::require_scope))
+            .wrap(actix_web::middleware::Compat::new(
+                actix_web_httpauth::middleware::HttpAuthentication::bearer(auth::bearer::validator),
+            ))
+            .service(routes::oidc::userinfo),
+    )
+    .service(
+        utoipa_actix_web::scope("/v1")
+            .wrap(actix_web::middleware::from_fn(auth::scopes::require_scope))
+            .wrap(actix_web::middleware::Compat::new(
+                actix_web_httpauth::middleware::HttpAuthentication::bearer(auth::bearer::validator),
+            ))
+            .service(routes::swap::batch)
+            .service(routes::swap::read_batch)
+            .service(routes::swap::swap)
+            .service(routes::swap::read),
+    )
+    .service(
+        utoipa_actix_web::scope("/secured")
+            .wrap(actix_web::middleware::Compat::new(
+                actix_web_httpauth::middleware::HttpAuthentication::bearer(auth::bearer::validator),
+            ))
+            .service(rust_actix_diesel_auth_scaffold::routes::secret::secret)
+            .service(rust_actix_diesel_auth_scaffold::routes::logout::logout)
+            .service(routes::scope::check)
+            .service(routes::api_keys::create)
+            .service(routes::api_keys::read_many)
+            .service(routes::api_keys::rename)
+            .service(routes::api_keys::revoke)
+            .service(routes::clients::register)
+            .service(routes::clients::read_many)
+            .service(routes::clients::rotate_secret)
+            .service(routes::clients::revoke)
+            .service(routes::mfa::enrol)
+            .service(routes::mfa::confirm)
+            .service(routes::mfa::disable)
+            .service(routes::sessions::read_many)
+            .service(routes::sessions::revoke)
+            .service(routes::sessions::revoke_others),
+    );
+}
+
+/// The OpenAPI spec as served, e.g., at `/api-docs/openapi.json`
+fn openapi_spec(crawl_url: Option<&str>) -> utoipa::openapi::OpenApi {
+    let (_, openapi) = actix_web::App::new()
+        .into_utoipa_app()
+        .openapi(api_doc(crawl_url))
+        .configure(routes)
+        .split_for_parts();
+    auth::scopes::add_security_requirements(openapi)
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let args: Cli = clap::Parser::parse();
+
+    let env = load_env(&args);
+    let (log_filter, log_format) =
+        logging::settings(args.log_level.as_deref(), args.log_format, &env)
+            .unwrap_or_else(|err| exit_with(err));
+    logging::init(&log_filter, log_format);
+    match args.command.unwrap_or(Command::Serve(args.serve)) {
+        Command::Serve(serve_args) => serve(serve_args, env).await,
+        Command::Migrate { command } => {
+            commands::migrate(&env, command.unwrap_or(commands::MigrateCommand::Run));
+            Ok(())
+        }
+        Command::Openapi(openapi_args) => commands::openapi(&env, openapi_args),
+        Command::User { command } => {
+            commands::user(&env, command);
+            Ok(())
+        }
+        Command::Healthcheck(healthcheck_args) => commands::healthcheck(&env, healthcheck_args),
+    }
+}
+
+async fn serve(
+    args: ServeArgs,
+    mut env: indexmap::IndexMap<String, String>,
+) -> std::io::Result<()> {
+    if let Some(hostname) = args.hostname {
+        env.insert(String::from("SADAS_HOSTNAME"), hostname);
+    }
+    if let Some(port) = args.port {
+        env.insert(String::from("SADAS_PORT"), port.to_string());
+    }
+    if !args.listen.is_empty() {
+        env.insert(String::from("LISTEN"), args.listen.join(","));
+    }
+    if let Some(socket_mode) = args.socket_mode {
+        env.insert(String::from("SOCKET_MODE"), socket_mode);
+    }
+    if let Some(tls_cert) = args.tls_cert {
+        env.insert(String::from("TLS_CERT"), tls_cert);
+    }
+    if let Some(tls_key) = args.tls_key {
+        env.insert(String::from("TLS_KEY"), tls_key);
+    }
+    if let Some(port) = args.http_redirect_port {
+        env.insert(String::from("HTTP_REDIRECT_PORT"), port.to_string());
+    }
+    let app_config = config::AppConfig::from_vars(&env).unwrap_or_else(|err| exit_with(err));
+
+    let swap_backend =
+        swap::SwapBackend::new(app_config.swap_url.clone(), app_config.preprocessor.clone());
+    let mailer: std::sync::Arc<dyn mail::Mailer> = match &app_config.mailer {
+        config::MailerConfig::Log => std::sync::Arc::new(mail::LogMailer),
+        config::MailerConfig::File(path) => std::sync::Arc::new(mail::FileMailer::new(path)),
+        config::MailerConfig::Smtp { url, from } => std::sync::Arc::new(
+            mail::SmtpMailer::new(url, from)
+                .unwrap_or_else(|err| exit_with(format!("SMTP_URL or MAIL_FROM: {}", err))),
+        ),
+    };
+    let login_throttle = auth::throttle::LoginThrottle {
+        config: app_config.throttle.clone(),
+        store: match &app_config.redis_url {
+            Some(redis_url) => std::sync::Arc::new(
+                auth::throttle::RedisAttemptStore::new(redis_url)
+                    .unwrap_or_else(|err| exit_with(format!("REDIS_URL: {}", err))),
+            ),
+            None => std::sync::Arc::new(auth::throttle::MemoryAttemptStore::default()),
+        },
+    };
+
+    let signing_key = match &app_config.oidc_signing_key_file {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|pem| auth::oidc::SigningKey::from_pem(&pem).map_err(|err| err.to_string()))
+            .unwrap_or_else(|err| exit_with(format!("OIDC_SIGNING_KEY_FILE {}: {}", path, err))),
+        None => {
+            log::warn!(
+                "OIDC_SIGNING_KEY_FILE not set; ID tokens are signed with a key generated now, \
+                 invalid after restart and on other replicas"
             );
+            auth::oidc::SigningKey::generate(2048).expect("generating an RSA key")
         }
+    };
+    let oidc_provider = auth::oidc::OidcProvider::new(&app_config.oidc_issuer, signing_key);
+    // one instance shared by all workers, so the provider's metadata and keys are fetched once
+    let federated_provider = app_config.federation.clone().map(|federation| {
+        actix_web::web::Data::new(auth::federation::FederatedProvider::new(federation))
+    });
+
+    let manager =
+        diesel::r2d2::ConnectionManager::<diesel::PgConnection>::new(&app_config.database_url);
+    let pool = diesel::r2d2::Pool::builder()
+        .build(manager)
+        .unwrap_or_else(|err| exit_with(format!("connecting to DATABASE_URL: {}", err)));
+
+    if !args.no_migrate {
+        let mut conn = pool
+            .get()
+            .unwrap_or_else(|err| exit_with(format!("connecting to DATABASE_URL: {}", err)));
+        run_migrations(&mut conn).unwrap_or_else(|err| {
+            log::error!("running migrations: {}", err);
+            std::process::exit(1)
+        });
     }
 
-    actix_web::HttpServer::new(move || {
+    swap::worker::spawn(pool.clone(), swap_backend, app_config.worker.clone());
+
+    let openapi = api_doc(app_config.crawl_url.as_deref());
+    let shared_config = actix_web::web::Data::new(app_config.clone());
+
+    let mut server = actix_web::HttpServer::new(move || {
         actix_web::App::new()
             .into_utoipa_app()
-            .openapi(ApiDoc::openapi())
-            .map(|app| app.wrap(actix_web::middleware::Logger::default()))
+            .openapi(openapi.clone())
+            .map(|app| app.wrap(actix_web::middleware::from_fn(audit::audit)))
+            .map(|app| app.wrap(actix_web::middleware::from_fn(logging::access_log)))
             .app_data(
                 actix_web::web::JsonConfig::default().error_handler(|err, _req| {
                     actix_web::error::InternalError::from_response(
@@ -309,47 +631,92 @@ async fn main() -> std::io::Result<()> {
                 }),
             )
             .app_data(actix_web::web::Data::new(pool.clone()))
-            .service(
-                utoipa_actix_web::scope("/api/v0")
-                    .wrap(actix_web::middleware::Compat::new(
-                        actix_web_httpauth::middleware::HttpAuthentication::bearer(
-                            rust_actix_diesel_auth_scaffold::middleware::bearer::validator,
-                        ),
-                    ))
-                    .service(replica_backend::routes::model::read)
-                    .service(replica_backend::routes::model::read_many)
-                    .service(replica_backend::routes::model::upsert)
-                    .service(replica_backend::routes::profile::read)
-                    .service(replica_backend::routes::profile::upsert),
-            )
-            .service(
-                utoipa_actix_web::scope("/api")
-                    .service(rust_actix_diesel_auth_scaffold::routes::token::token)
-                    //  .service(rust_actix_diesel_auth_scaffold::routes::authorisation::authorise)
-                    .service(version),
-            )
-            .service(
-                utoipa_actix_web::scope("/secured")
-                    .wrap(actix_web::middleware::Compat::new(
-                        actix_web_httpauth::middleware::HttpAuthentication::bearer(
-                            rust_actix_diesel_auth_scaffold::middleware::bearer::validator,
-                        ),
-                    ))
-                    .service(rust_actix_diesel_auth_scaffold::routes::secret::secret)
-                    .service(rust_actix_diesel_auth_scaffold::routes::logout::logout),
-            )
-            .openapi_service(|api| utoipa_redoc::Redoc::with_url("/redoc", api))
+            .app_data(shared_config.clone())
+            .app_data(actix_web::web::Data::new(shared_config.worker.clone()))
+            .app_data(actix_web::web::Data::new(
+                shared_config.preprocessor.clone(),
+            ))
+            .app_data(actix_web::web::Data::new(shared_config.token.clone()))
+            .app_data(actix_web::web::Data::new(shared_config.account.clone()))
+            .app_data(actix_web::web::Data::from(mailer.clone()))
+            .app_data(actix_web::web::Data::new(login_throttle.clone()))
+            .app_data(actix_web::web::Data::new(
+                shared_config.trusted_proxies.clone(),
+            ))
+            .app_data(actix_web::web::Data::new(oidc_provider.clone()))
+            .map(|app| match &federated_provider {
+                Some(federated_provider) => app.app_data(federated_provider.clone()),
+                None => app,
+            })
+            .configure(routes)
+            .openapi_service(|api| {
+                utoipa_redoc::Redoc::with_url(
+                    "/redoc",
+                    auth::scopes::add_security_requirements(api),
+                )
+            })
             .openapi_service(|api| {
+                let mut oauth_config = utoipa_swagger_ui::oauth::Config::new()
+                    .use_pkce_with_authorization_code_grant(true);
+                if let Some(client_id) = &shared_config.swagger_ui_client_id {
+                    oauth_config = oauth_config.client_id(client_id);
+                }
                 utoipa_swagger_ui::SwaggerUi::new("/swagger-ui/{_:.*}")
-                    .url("/api-docs/openapi.json", api)
+                    .url(
+                        "/api-docs/openapi.json",
+                        auth::scopes::add_security_requirements(api),
+                    )
+                    .oauth(oauth_config)
             })
             .map(|app| {
                 app.service(utoipa_rapidoc::RapiDoc::new("/api-docs/openapi.json").path("/rapidoc"))
             })
-            .openapi_service(|api| utoipa_scalar::Scalar::with_url("/scalar", api))
+            .openapi_service(|api| {
+                utoipa_scalar::Scalar::with_url(
+                    "/scalar",
+                    auth::scopes::add_security_requirements(api),
+                )
+            })
             .into_app()
-    })
-    .bind((args.hostname.as_str(), args.port))?
-    .run()
-    .await
+    });
+    let rustls_config = match &app_config.tls {
+        Some(tls_config) => {
+            let resolver = std::sync::Arc::new(
+                tls::CertResolver::load(tls_config.clone())
+                    .unwrap_or_else(|err| exit_with(format!("TLS: {}", err))),
+            );
+            tls::watch(resolver.clone())?;
+            if let Some(redirect_port) = tls_config.redirect_port {
+                let https_port = app_config
+                    .listen
+                    .iter()
+                    .find_map(|listen| match listen {
+                        listen::Listen::Tcp(_, port) => Some(*port),
+                        listen::Listen::Unix(_) => None,
+                    })
+                    .unwrap_or(app_config.port);
+                // plain HTTP is answered wherever HTTPS is, not on `--hostname` regardless
+                let hosts = match listen::tcp_hosts(&app_config.listen) {
+                    hosts if hosts.is_empty() => vec![app_config.hostname.as_str()],
+                    hosts => hosts,
+                };
+                actix_web::rt::spawn(tls::redirect_server(&hosts, redirect_port, https_port)?);
+            }
+            Some(tls::server_config(resolver).unwrap_or_else(|err| exit_with(err)))
+        }
+        None => None,
+    };
+    for address in &app_config.listen {
+        let bound = match (address, &rustls_config) {
+            (listen::Listen::Tcp(host, port), Some(rustls_config)) => {
+                server.bind_rustls_0_23((host.as_str(), *port), rustls_config.clone())
+            }
+            (listen::Listen::Tcp(host, port), None) => server.bind((host.as_str(), *port)),
+            // TLS over a socket is left to the proxy in front of it
+            (listen::Listen::Unix(path), _) => listen::bind_unix(path, app_config.socket_mode)
+                .and_then(|listener| server.listen_uds(listener)),
+        };
+        server = bound.unwrap_or_else(|err| exit_with(format!("listen on {}: {}", address, err)));
+    }
+    server.run().await
 }
@@ -0,0 +1,184 @@
+use diesel::prelude::*;
+
+use crate::schema::access_tokens;
+
+/// serve-replica's record of a bearer token: who it is for and what it may do
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = access_tokens)]
+pub struct AccessToken {
+    pub id: i32,
+    /// SHA-256 of the token, hex encoded; see [`hash_token`]
+    pub token_hash: String,
+    /// `None` until known, e.g., for tokens minted by the authorization code flow
+    pub username: Option<String>,
+    /// space-separated, as in the OAuth2 `scope` parameter
+    pub scopes: String,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    /// refresh token family this token belongs to
+    pub family_id: Option<String>,
+    /// minted by serve-replica, so not known to rust-actix-diesel-auth-scaffold
+    pub local: bool,
+    /// address the token was issued to, then last used from
+    pub ip: Option<String>,
+    /// `User-Agent` of the client the token was issued to
+    pub user_agent: Option<String>,
+    /// to within [`LAST_USE_RESOLUTION_SECS`]; `None` if never used
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = access_tokens)]
+pub struct NewAccessToken<'a> {
+    pub token_hash: &'a str,
+    pub username: Option<&'a str>,
+    pub scopes: &'a str,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub family_id: Option<&'a str>,
+    pub local: bool,
+}
+
+/// How precisely [`AccessToken::touch`] records use; more is not worth a write per request
+pub const LAST_USE_RESOLUTION_SECS: i64 = 60;
+/// Longer `User-Agent`s are cut short before they are stored
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// The client a token was issued to, as far as the request tells
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TokenClient {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl TokenClient {
+    pub fn of(req: &actix_web::HttpRequest) -> TokenClient {
+        TokenClient {
+            ip: crate::proxy::client_ip(req),
+            user_agent: req
+                .headers()
+                .get(actix_web::http::header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LEN).collect()),
+        }
+    }
+}
+
+/// Tokens are looked up by hash so a database leak does not leak usable tokens
+pub fn hash_token(token: &str) -> String {
+    use sha2::Digest;
+
+    format!("{:x}", sha2::Sha256::digest(token.as_bytes()))
+}
+
+impl AccessToken {
+    pub fn record(
+        conn: &mut diesel::PgConnection,
+        new_token: &NewAccessToken,
+    ) -> QueryResult<AccessToken> {
+        diesel::insert_into(access_tokens::table)
+            .values(new_token)
+            .on_conflict(access_tokens::token_hash)
+            .do_update()
+            .set((
+                access_tokens::scopes.eq(new_token.scopes),
+                access_tokens::expires_at.eq(new_token.expires_at),
+            ))
+            .returning(AccessToken::as_returning())
+            .get_result(conn)
+    }
+
+    /// Find a token whether or not it is still active; see [`AccessToken::is_active`]
+    pub fn find(conn: &mut diesel::PgConnection, token: &str) -> QueryResult<Option<AccessToken>> {
+        access_tokens::table
+            .filter(access_tokens::token_hash.eq(hash_token(token)))
+            .select(AccessToken::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none()
+            && self
+                .expires_at
+                .is_none_or(|expires_at| expires_at > chrono::Utc::now().naive_utc())
+    }
+
+    pub fn revoke(conn: &mut diesel::PgConnection, id: i32) -> QueryResult<usize> {
+        diesel::update(
+            access_tokens::table
+                .find(id)
+                .filter(access_tokens::revoked_at.is_null()),
+        )
+        .set(access_tokens::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+    }
+
+    pub fn revoke_family(conn: &mut diesel::PgConnection, family_id: &str) -> QueryResult<usize> {
+        diesel::update(
+            access_tokens::table
+                .filter(access_tokens::family_id.eq(family_id))
+                .filter(access_tokens::revoked_at.is_null()),
+        )
+        .set(access_tokens::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+    }
+
+    pub fn set_username(
+        conn: &mut diesel::PgConnection,
+        id: i32,
+        username: &str,
+    ) -> QueryResult<usize> {
+        diesel::update(access_tokens::table.find(id))
+            .set(access_tokens::username.eq(username))
+            .execute(conn)
+    }
+
+    /// Revoke every token of `username`, e.g., after their password changed
+    pub fn revoke_user(conn: &mut diesel::PgConnection, username: &str) -> QueryResult<usize> {
+        diesel::update(
+            access_tokens::table
+                .filter(access_tokens::username.eq(username))
+                .filter(access_tokens::revoked_at.is_null()),
+        )
+        .set(access_tokens::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+    }
+
+    /// Record which client `token` was just issued to
+    pub fn note_client(
+        conn: &mut diesel::PgConnection,
+        token: &str,
+        client: &TokenClient,
+    ) -> QueryResult<usize> {
+        diesel::update(access_tokens::table.filter(access_tokens::token_hash.eq(hash_token(token))))
+            .set((
+                access_tokens::ip.eq(client.ip.as_deref()),
+                access_tokens::user_agent.eq(client.user_agent.as_deref()),
+            ))
+            .execute(conn)
+    }
+
+    /// Record a use of token `id` from `ip`, unless one was recorded within
+    /// [`LAST_USE_RESOLUTION_SECS`]
+    pub fn touch(conn: &mut diesel::PgConnection, id: i32, ip: Option<&str>) -> QueryResult<usize> {
+        let cutoff =
+            chrono::Utc::now().naive_utc() - chrono::Duration::seconds(LAST_USE_RESOLUTION_SECS);
+        let target = access_tokens::table.find(id).filter(
+            access_tokens::last_used_at
+                .is_null()
+                .or(access_tokens::last_used_at.lt(cutoff)),
+        );
+        match ip {
+            Some(ip) => diesel::update(target)
+                .set((
+                    access_tokens::last_used_at.eq(diesel::dsl::now),
+                    access_tokens::ip.eq(ip),
+                ))
+                .execute(conn),
+            None => diesel::update(target)
+                .set(access_tokens::last_used_at.eq(diesel::dsl::now))
+                .execute(conn),
+        }
+    }
+}
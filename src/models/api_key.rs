@@ -0,0 +1,146 @@
+use diesel::prelude::*;
+
+use crate::models::access_token::hash_token;
+use crate::schema::api_keys;
+
+/// Every API key starts with this, which is how the bearer validator tells them from tokens
+pub const API_KEY_PREFIX: &str = "sr_";
+
+/// A long-lived personal API key; only its hash is stored
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+    Queryable,
+    Selectable,
+)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub id: i32,
+    #[serde(skip)]
+    pub username: String,
+    pub name: String,
+    /// first characters of the key
+    pub prefix: String,
+    #[serde(skip)]
+    pub key_hash: String,
+    /// space-separated scopes
+    pub scopes: String,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct NewApiKey<'a> {
+    pub username: &'a str,
+    pub name: &'a str,
+    pub prefix: &'a str,
+    pub key_hash: &'a str,
+    pub scopes: &'a str,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ApiKeyCreateRequest {
+    pub name: String,
+    /// space-separated scopes; at most those of the token creating the key
+    pub scope: String,
+    /// when the key stops working, by the database's clock; never, if omitted
+    #[serde(default)]
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+/// A newly created API key; `key` is shown this once and cannot be retrieved again
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ApiKeyCreateResponse {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ApiKeyRenameRequest {
+    pub name: String,
+}
+
+impl ApiKey {
+    pub fn insert(conn: &mut diesel::PgConnection, new_key: &NewApiKey) -> QueryResult<ApiKey> {
+        diesel::insert_into(api_keys::table)
+            .values(new_key)
+            .returning(ApiKey::as_returning())
+            .get_result(conn)
+    }
+
+    /// Find an unrevoked, unexpired key, recording that it was used
+    pub fn authenticate(conn: &mut diesel::PgConnection, key: &str) -> QueryResult<Option<ApiKey>> {
+        diesel::update(
+            api_keys::table
+                .filter(api_keys::key_hash.eq(hash_token(key)))
+                .filter(api_keys::revoked_at.is_null())
+                .filter(
+                    api_keys::expires_at
+                        .is_null()
+                        .or(api_keys::expires_at.gt(diesel::dsl::now)),
+                ),
+        )
+        .set(api_keys::last_used_at.eq(diesel::dsl::now))
+        .returning(ApiKey::as_returning())
+        .get_result(conn)
+        .optional()
+    }
+
+    /// Find a key whether or not it is still usable, without recording a use
+    pub fn find(conn: &mut diesel::PgConnection, key: &str) -> QueryResult<Option<ApiKey>> {
+        api_keys::table
+            .filter(api_keys::key_hash.eq(hash_token(key)))
+            .select(ApiKey::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// Whether the key is unrevoked and unexpired at `now`, from [`crate::models::db_now`]
+    pub fn is_active(&self, now: chrono::NaiveDateTime) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    pub fn list(conn: &mut diesel::PgConnection, username: &str) -> QueryResult<Vec<ApiKey>> {
+        api_keys::table
+            .filter(api_keys::username.eq(username))
+            .order(api_keys::created_at.desc())
+            .select(ApiKey::as_select())
+            .load(conn)
+    }
+
+    pub fn rename(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        id: i32,
+        name: &str,
+    ) -> QueryResult<ApiKey> {
+        diesel::update(
+            api_keys::table
+                .filter(api_keys::id.eq(id))
+                .filter(api_keys::username.eq(username)),
+        )
+        .set(api_keys::name.eq(name))
+        .returning(ApiKey::as_returning())
+        .get_result(conn)
+    }
+
+    pub fn revoke(conn: &mut diesel::PgConnection, username: &str, id: i32) -> QueryResult<usize> {
+        diesel::update(
+            api_keys::table
+                .filter(api_keys::id.eq(id))
+                .filter(api_keys::username.eq(username))
+                .filter(api_keys::revoked_at.is_null()),
+        )
+        .set(api_keys::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+    }
+}
@@ -0,0 +1,134 @@
+use diesel::prelude::*;
+
+use crate::schema::audit_events;
+
+/// One entry of the security audit log
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+    Queryable,
+    Selectable,
+)]
+#[diesel(table_name = audit_events)]
+pub struct AuditEvent {
+    pub id: i64,
+    /// e.g., `login`, `login_failed`, `logout`, `token_revoked`, `profile_upserted`
+    pub event: String,
+    /// username, or client id for client authentication, if known
+    pub actor: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: String,
+    pub method: String,
+    pub path: String,
+    /// HTTP status of the response
+    pub status: i32,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = audit_events)]
+pub struct NewAuditEvent<'a> {
+    pub event: &'a str,
+    pub actor: Option<&'a str>,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub request_id: &'a str,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub status: i32,
+}
+
+/// Filters and pagination for the audit log, newest first
+#[derive(Debug, Clone, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditEventQuery {
+    /// 0-indexed page number
+    pub page: Option<i64>,
+    /// results per page, at most 100, defaults to 20
+    pub per_page: Option<i64>,
+    /// only this event
+    pub event: Option<String>,
+    /// only events of this actor
+    pub actor: Option<String>,
+    /// only events at or after this time
+    pub since: Option<chrono::NaiveDateTime>,
+    /// only events before this time
+    pub until: Option<chrono::NaiveDateTime>,
+}
+
+impl AuditEventQuery {
+    pub const DEFAULT_PER_PAGE: i64 = 20;
+    pub const MAX_PER_PAGE: i64 = 100;
+
+    pub fn per_page(&self) -> i64 {
+        self.per_page
+            .unwrap_or(Self::DEFAULT_PER_PAGE)
+            .clamp(1, Self::MAX_PER_PAGE)
+    }
+
+    pub fn offset(&self) -> i64 {
+        crate::models::page_offset(self.page, self.per_page())
+    }
+}
+
+/// One page of the audit log
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct AuditEventPage {
+    pub events: Vec<AuditEvent>,
+    /// total number of events matching the filters, across all pages
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl AuditEvent {
+    pub fn insert(
+        conn: &mut diesel::PgConnection,
+        new_event: &NewAuditEvent,
+    ) -> QueryResult<AuditEvent> {
+        diesel::insert_into(audit_events::table)
+            .values(new_event)
+            .returning(AuditEvent::as_returning())
+            .get_result(conn)
+    }
+
+    pub fn list(
+        conn: &mut diesel::PgConnection,
+        query: &AuditEventQuery,
+    ) -> QueryResult<AuditEventPage> {
+        let filtered = || {
+            let mut statement = audit_events::table.into_boxed();
+            if let Some(event) = &query.event {
+                statement = statement.filter(audit_events::event.eq(event.to_owned()));
+            }
+            if let Some(actor) = &query.actor {
+                statement = statement.filter(audit_events::actor.eq(actor.to_owned()));
+            }
+            if let Some(since) = query.since {
+                statement = statement.filter(audit_events::created_at.ge(since));
+            }
+            if let Some(until) = query.until {
+                statement = statement.filter(audit_events::created_at.lt(until));
+            }
+            statement
+        };
+        let total = filtered().count().get_result(conn)?;
+        let events = filtered()
+            .order(audit_events::id.desc())
+            .limit(query.per_page())
+            .offset(query.offset())
+            .select(AuditEvent::as_select())
+            .load(conn)?;
+        Ok(AuditEventPage {
+            events,
+            total,
+            page: query.page.unwrap_or(0).max(0),
+            per_page: query.per_page(),
+        })
+    }
+}
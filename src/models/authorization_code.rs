@@ -0,0 +1,70 @@
+use diesel::prelude::*;
+
+use crate::models::access_token::hash_token;
+use crate::schema::authorization_codes;
+
+/// A code issued at `/api/authorise`, recording what the user approved until the client
+/// exchanges it at `/api/token`
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = authorization_codes)]
+pub struct AuthorizationCode {
+    pub code_hash: String,
+    pub client_id: String,
+    pub username: String,
+    pub redirect_uri: String,
+    /// space-separated scopes the user approved
+    pub scopes: String,
+    /// PKCE S256 challenge, base64url encoded
+    pub code_challenge: String,
+    pub nonce: Option<String>,
+    pub expires_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = authorization_codes)]
+pub struct NewAuthorizationCode<'a> {
+    pub code_hash: &'a str,
+    pub client_id: &'a str,
+    pub username: &'a str,
+    pub redirect_uri: &'a str,
+    pub scopes: &'a str,
+    pub code_challenge: &'a str,
+    pub nonce: Option<&'a str>,
+}
+
+impl AuthorizationCode {
+    /// Insert, expiring `ttl` from now, and clear out codes that were never exchanged
+    pub fn insert(
+        conn: &mut diesel::PgConnection,
+        new_code: &NewAuthorizationCode,
+        ttl: std::time::Duration,
+    ) -> QueryResult<AuthorizationCode> {
+        diesel::delete(
+            authorization_codes::table.filter(authorization_codes::expires_at.le(diesel::dsl::now)),
+        )
+        .execute(conn)?;
+        diesel::insert_into(authorization_codes::table)
+            .values((
+                new_code,
+                authorization_codes::expires_at.eq(diesel::dsl::now + crate::models::interval(ttl)),
+            ))
+            .returning(AuthorizationCode::as_returning())
+            .get_result(conn)
+    }
+
+    /// Remove and return the unexpired code `code`; each code works once
+    pub fn consume(
+        conn: &mut diesel::PgConnection,
+        code: &str,
+    ) -> QueryResult<Option<AuthorizationCode>> {
+        diesel::delete(
+            authorization_codes::table
+                .filter(authorization_codes::code_hash.eq(hash_token(code)))
+                .filter(authorization_codes::expires_at.gt(diesel::dsl::now)),
+        )
+        .returning(AuthorizationCode::as_returning())
+        .get_result(conn)
+        .optional()
+    }
+}
@@ -0,0 +1,172 @@
+use diesel::prelude::*;
+
+use crate::models::access_token::hash_token;
+use crate::schema::confidential_clients;
+
+/// A registered client: a machine client of the `client_credentials` grant and, given
+/// `redirect_uris`, a client of the authorization code flow. Only its secret's hash is stored
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+    Queryable,
+    Selectable,
+)]
+#[diesel(table_name = confidential_clients)]
+pub struct ConfidentialClient {
+    #[serde(skip)]
+    pub id: i32,
+    pub client_id: String,
+    #[serde(skip)]
+    pub secret_hash: String,
+    pub name: String,
+    /// user the client's tokens act as
+    pub owner: String,
+    /// space-separated scopes the client may request
+    pub scopes: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub secret_rotated_at: chrono::NaiveDateTime,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    /// space-separated URIs `/api/authorise` may send codes to; none means the client cannot
+    /// use the authorization code flow
+    pub redirect_uris: String,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = confidential_clients)]
+pub struct NewConfidentialClient<'a> {
+    pub client_id: &'a str,
+    pub secret_hash: &'a str,
+    pub name: &'a str,
+    pub owner: &'a str,
+    pub scopes: &'a str,
+    pub redirect_uris: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ClientRegistrationRequest {
+    pub name: String,
+    /// space-separated scopes; at most those of the token registering the client
+    pub scope: String,
+    /// space-separated redirect URIs, for the authorization code flow
+    #[serde(default)]
+    pub redirect_uris: String,
+}
+
+/// A client and its secret; `client_secret` is shown this once and cannot be retrieved again
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ClientSecretResponse {
+    pub client_secret: String,
+    #[serde(flatten)]
+    pub client: ConfidentialClient,
+}
+
+impl ConfidentialClient {
+    const TOKEN_FAMILY_PREFIX: &'static str = "client:";
+
+    pub fn insert(
+        conn: &mut diesel::PgConnection,
+        new_client: &NewConfidentialClient,
+    ) -> QueryResult<ConfidentialClient> {
+        diesel::insert_into(confidential_clients::table)
+            .values(new_client)
+            .returning(ConfidentialClient::as_returning())
+            .get_result(conn)
+    }
+
+    /// Find the unrevoked client `client_id`, if `client_secret` is its current secret
+    pub fn authenticate(
+        conn: &mut diesel::PgConnection,
+        client_id: &str,
+        client_secret: &str,
+    ) -> QueryResult<Option<ConfidentialClient>> {
+        confidential_clients::table
+            .filter(confidential_clients::client_id.eq(client_id))
+            .filter(confidential_clients::secret_hash.eq(hash_token(client_secret)))
+            .filter(confidential_clients::revoked_at.is_null())
+            .select(ConfidentialClient::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// Find the unrevoked client `client_id` without its secret, as public clients of the
+    /// authorization code flow have none to keep
+    pub fn find(
+        conn: &mut diesel::PgConnection,
+        client_id: &str,
+    ) -> QueryResult<Option<ConfidentialClient>> {
+        confidential_clients::table
+            .filter(confidential_clients::client_id.eq(client_id))
+            .filter(confidential_clients::revoked_at.is_null())
+            .select(ConfidentialClient::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// Whether `redirect_uri` is exactly one of the client's registered redirect URIs
+    pub fn allows_redirect(&self, redirect_uri: &str) -> bool {
+        self.redirect_uris
+            .split_whitespace()
+            .any(|registered| registered == redirect_uri)
+    }
+
+    pub fn list(
+        conn: &mut diesel::PgConnection,
+        owner: &str,
+    ) -> QueryResult<Vec<ConfidentialClient>> {
+        confidential_clients::table
+            .filter(confidential_clients::owner.eq(owner))
+            .order(confidential_clients::created_at.desc())
+            .select(ConfidentialClient::as_select())
+            .load(conn)
+    }
+
+    /// Replace the secret of an unrevoked client; the old secret stops working at once
+    pub fn rotate_secret(
+        conn: &mut diesel::PgConnection,
+        owner: &str,
+        client_id: &str,
+        secret_hash: &str,
+    ) -> QueryResult<ConfidentialClient> {
+        diesel::update(
+            confidential_clients::table
+                .filter(confidential_clients::client_id.eq(client_id))
+                .filter(confidential_clients::owner.eq(owner))
+                .filter(confidential_clients::revoked_at.is_null()),
+        )
+        .set((
+            confidential_clients::secret_hash.eq(secret_hash),
+            confidential_clients::secret_rotated_at.eq(diesel::dsl::now),
+        ))
+        .returning(ConfidentialClient::as_returning())
+        .get_result(conn)
+    }
+
+    pub fn revoke(
+        conn: &mut diesel::PgConnection,
+        owner: &str,
+        client_id: &str,
+    ) -> QueryResult<usize> {
+        diesel::update(
+            confidential_clients::table
+                .filter(confidential_clients::client_id.eq(client_id))
+                .filter(confidential_clients::owner.eq(owner))
+                .filter(confidential_clients::revoked_at.is_null()),
+        )
+        .set(confidential_clients::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+    }
+
+    /// Family id under which the client's access tokens are recorded, to revoke them together
+    pub fn token_family(client_id: &str) -> String {
+        format!("{}{}", Self::TOKEN_FAMILY_PREFIX, client_id)
+    }
+
+    /// The client id a [`ConfidentialClient::token_family`] belongs to, if it is one
+    pub fn of_token_family(family_id: &str) -> Option<&str> {
+        family_id.strip_prefix(Self::TOKEN_FAMILY_PREFIX)
+    }
+}
@@ -0,0 +1,81 @@
+use diesel::prelude::*;
+
+use crate::models::access_token::hash_token;
+use crate::schema::email_tokens;
+
+pub const PURPOSE_VERIFY_EMAIL: &str = "verify_email";
+pub const PURPOSE_RESET_PASSWORD: &str = "reset_password";
+
+/// A single-use token mailed to a user; only its hash is stored
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = email_tokens)]
+pub struct EmailToken {
+    pub id: i32,
+    pub token_hash: String,
+    pub username: String,
+    /// [`PURPOSE_VERIFY_EMAIL`] or [`PURPOSE_RESET_PASSWORD`]
+    pub purpose: String,
+    pub expires_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = email_tokens)]
+pub struct NewEmailToken<'a> {
+    pub token_hash: &'a str,
+    pub username: &'a str,
+    pub purpose: &'a str,
+}
+
+impl EmailToken {
+    /// Insert, expiring `ttl` from now
+    pub fn insert(
+        conn: &mut diesel::PgConnection,
+        new_token: &NewEmailToken,
+        ttl: std::time::Duration,
+    ) -> QueryResult<EmailToken> {
+        diesel::insert_into(email_tokens::table)
+            .values((
+                new_token,
+                email_tokens::expires_at.eq(diesel::dsl::now + crate::models::interval(ttl)),
+            ))
+            .returning(EmailToken::as_returning())
+            .get_result(conn)
+    }
+
+    /// Use up an unexpired, unused `token` issued for `purpose`, returning it
+    pub fn consume(
+        conn: &mut diesel::PgConnection,
+        token: &str,
+        purpose: &str,
+    ) -> QueryResult<Option<EmailToken>> {
+        diesel::update(
+            email_tokens::table
+                .filter(email_tokens::token_hash.eq(hash_token(token)))
+                .filter(email_tokens::purpose.eq(purpose))
+                .filter(email_tokens::used_at.is_null())
+                .filter(email_tokens::expires_at.gt(diesel::dsl::now)),
+        )
+        .set(email_tokens::used_at.eq(diesel::dsl::now))
+        .returning(EmailToken::as_returning())
+        .get_result(conn)
+        .optional()
+    }
+
+    /// Invalidate `username`'s outstanding tokens for `purpose`, e.g., once one was used
+    pub fn expire_outstanding(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        purpose: &str,
+    ) -> QueryResult<usize> {
+        diesel::update(
+            email_tokens::table
+                .filter(email_tokens::username.eq(username))
+                .filter(email_tokens::purpose.eq(purpose))
+                .filter(email_tokens::used_at.is_null()),
+        )
+        .set(email_tokens::used_at.eq(diesel::dsl::now))
+        .execute(conn)
+    }
+}
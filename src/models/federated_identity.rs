@@ -0,0 +1,54 @@
+use diesel::prelude::*;
+
+use crate::schema::federated_identities;
+
+/// A user's account at an external OpenID Connect provider, linked to their local username
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = federated_identities)]
+pub struct FederatedIdentity {
+    pub issuer: String,
+    /// the provider's `sub` claim
+    pub subject: String,
+    pub username: String,
+    pub email: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_login_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = federated_identities)]
+pub struct NewFederatedIdentity<'a> {
+    pub issuer: &'a str,
+    pub subject: &'a str,
+    pub username: &'a str,
+    pub email: Option<&'a str>,
+}
+
+impl FederatedIdentity {
+    pub fn insert(
+        conn: &mut diesel::PgConnection,
+        new_identity: &NewFederatedIdentity,
+    ) -> QueryResult<FederatedIdentity> {
+        diesel::insert_into(federated_identities::table)
+            .values(new_identity)
+            .returning(FederatedIdentity::as_returning())
+            .get_result(conn)
+    }
+
+    /// Note a login, refreshing the email the provider reports
+    pub fn record_login(
+        conn: &mut diesel::PgConnection,
+        issuer: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> QueryResult<Option<FederatedIdentity>> {
+        diesel::update(federated_identities::table.find((issuer, subject)))
+            .set((
+                federated_identities::email.eq(email),
+                federated_identities::last_login_at.eq(diesel::dsl::now),
+            ))
+            .returning(FederatedIdentity::as_returning())
+            .get_result(conn)
+            .optional()
+    }
+}
@@ -0,0 +1,61 @@
+use diesel::prelude::*;
+
+use crate::models::access_token::hash_token;
+use crate::schema::federated_logins;
+
+/// A login started at `/api/federated/login`, until the provider redirects back with its `state`
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = federated_logins)]
+pub struct FederatedLogin {
+    pub state_hash: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub return_to: Option<String>,
+    pub expires_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = federated_logins)]
+pub struct NewFederatedLogin<'a> {
+    pub state_hash: &'a str,
+    pub nonce: &'a str,
+    pub code_verifier: &'a str,
+    pub return_to: Option<&'a str>,
+}
+
+impl FederatedLogin {
+    /// Insert, expiring `ttl` from now, clearing out logins that were abandoned
+    pub fn insert(
+        conn: &mut diesel::PgConnection,
+        new_login: &NewFederatedLogin,
+        ttl: std::time::Duration,
+    ) -> QueryResult<FederatedLogin> {
+        diesel::delete(
+            federated_logins::table.filter(federated_logins::expires_at.le(diesel::dsl::now)),
+        )
+        .execute(conn)?;
+        diesel::insert_into(federated_logins::table)
+            .values((
+                new_login,
+                federated_logins::expires_at.eq(diesel::dsl::now + crate::models::interval(ttl)),
+            ))
+            .returning(FederatedLogin::as_returning())
+            .get_result(conn)
+    }
+
+    /// Remove and return the unexpired login `state` belongs to; each state works once
+    pub fn consume(
+        conn: &mut diesel::PgConnection,
+        state: &str,
+    ) -> QueryResult<Option<FederatedLogin>> {
+        diesel::delete(
+            federated_logins::table
+                .filter(federated_logins::state_hash.eq(hash_token(state)))
+                .filter(federated_logins::expires_at.gt(diesel::dsl::now)),
+        )
+        .returning(FederatedLogin::as_returning())
+        .get_result(conn)
+        .optional()
+    }
+}
@@ -0,0 +1,36 @@
+pub mod access_token;
+pub mod api_key;
+pub mod audit_event;
+pub mod authorization_code;
+pub mod confidential_client;
+pub mod email_token;
+pub mod federated_identity;
+pub mod federated_login;
+pub mod refresh_token;
+pub mod swap_batch;
+pub mod swap_history;
+pub mod swap_job;
+pub mod totp_credential;
+pub mod user_account;
+pub mod user_credential;
+
+/// Rows skipped to reach zero-based `page` of `per_page` rows; a huge page saturates rather
+/// than overflowing, and simply comes back empty
+pub fn page_offset(page: Option<i64>, per_page: i64) -> i64 {
+    page.unwrap_or(0).max(0).saturating_mul(per_page)
+}
+
+/// The database's clock, which expiry is checked against
+pub fn db_now(conn: &mut diesel::PgConnection) -> diesel::QueryResult<chrono::NaiveDateTime> {
+    use diesel::RunQueryDsl;
+
+    diesel::select(diesel::dsl::now).get_result(conn)
+}
+
+/// `duration` as an SQL interval, to add to `diesel::dsl::now` so that times are set by the
+/// same clock, the database's, that they are checked against
+pub fn interval(duration: std::time::Duration) -> diesel::pg::data_types::PgInterval {
+    diesel::pg::data_types::PgInterval::from_microseconds(
+        i64::try_from(duration.as_micros()).unwrap_or(i64::MAX),
+    )
+}
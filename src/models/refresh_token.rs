@@ -0,0 +1,82 @@
+use diesel::prelude::*;
+
+use crate::models::access_token::hash_token;
+use crate::schema::refresh_tokens;
+
+/// A refresh token; each is single use, and is replaced by a new one in the same family
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = refresh_tokens)]
+pub struct RefreshToken {
+    pub id: i32,
+    pub token_hash: String,
+    pub family_id: String,
+    pub username: String,
+    pub scopes: String,
+    pub expires_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub used_at: Option<chrono::NaiveDateTime>,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = refresh_tokens)]
+pub struct NewRefreshToken<'a> {
+    pub token_hash: &'a str,
+    pub family_id: &'a str,
+    pub username: &'a str,
+    pub scopes: &'a str,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+impl RefreshToken {
+    pub fn insert(
+        conn: &mut diesel::PgConnection,
+        new_token: &NewRefreshToken,
+    ) -> QueryResult<RefreshToken> {
+        diesel::insert_into(refresh_tokens::table)
+            .values(new_token)
+            .returning(RefreshToken::as_returning())
+            .get_result(conn)
+    }
+
+    pub fn find(conn: &mut diesel::PgConnection, token: &str) -> QueryResult<Option<RefreshToken>> {
+        refresh_tokens::table
+            .filter(refresh_tokens::token_hash.eq(hash_token(token)))
+            .select(RefreshToken::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// Mark used; `false` means it already was, i.e., the token is being replayed
+    pub fn mark_used(conn: &mut diesel::PgConnection, id: i32) -> QueryResult<bool> {
+        diesel::update(
+            refresh_tokens::table
+                .filter(refresh_tokens::id.eq(id))
+                .filter(refresh_tokens::used_at.is_null()),
+        )
+        .set(refresh_tokens::used_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .map(|updated| updated == 1)
+    }
+
+    pub fn revoke_family(conn: &mut diesel::PgConnection, family_id: &str) -> QueryResult<usize> {
+        diesel::update(
+            refresh_tokens::table
+                .filter(refresh_tokens::family_id.eq(family_id))
+                .filter(refresh_tokens::revoked_at.is_null()),
+        )
+        .set(refresh_tokens::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+    }
+
+    /// Revoke every token of `username`, e.g., after their password changed
+    pub fn revoke_user(conn: &mut diesel::PgConnection, username: &str) -> QueryResult<usize> {
+        diesel::update(
+            refresh_tokens::table
+                .filter(refresh_tokens::username.eq(username))
+                .filter(refresh_tokens::revoked_at.is_null()),
+        )
+        .set(refresh_tokens::revoked_at.eq(diesel::dsl::now))
+        .execute(conn)
+    }
+}
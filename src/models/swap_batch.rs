@@ -0,0 +1,125 @@
+use diesel::prelude::*;
+
+use crate::models::swap_job::{
+    NewSwapJob, SwapJob, STATUS_COMPLETED, STATUS_FAILED, STATUS_QUEUED, STATUS_RUNNING,
+};
+use crate::schema::{swap_batches, swap_jobs};
+
+/// Parent of the child swap jobs created by one batch swap request
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = swap_batches)]
+pub struct SwapBatch {
+    pub id: i32,
+    pub username: String,
+    /// preprocessed once for all children; cleared once they have all finished
+    pub user_img_url: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+impl SwapBatch {
+    /// Create a batch holding `user_img_url`, already preprocessed, and one child job per
+    /// `(model_id, model_img_url)`, all or nothing.
+    ///
+    /// Returns `Ok(None)` when the children would not fit within `capacity`.
+    pub fn create(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        user_img_url: &str,
+        models: &[(Option<i32>, String)],
+        capacity: i64,
+    ) -> QueryResult<Option<(SwapBatch, Vec<SwapJob>)>> {
+        let created = conn.transaction(|conn| {
+            let batch: SwapBatch = diesel::insert_into(swap_batches::table)
+                .values((
+                    swap_batches::username.eq(username),
+                    swap_batches::user_img_url.eq(user_img_url),
+                ))
+                .returning(SwapBatch::as_returning())
+                .get_result(conn)?;
+            let new_jobs = models
+                .iter()
+                .map(|(model_id, model_img_url)| NewSwapJob {
+                    username,
+                    model_id: *model_id,
+                    user_img_url: None,
+                    model_img_url,
+                    batch_id: Some(batch.id),
+                })
+                .collect::<Vec<NewSwapJob>>();
+            match SwapJob::enqueue_many(conn, &new_jobs, capacity)? {
+                Some(jobs) => Ok((batch, jobs)),
+                None => Err(diesel::result::Error::RollbackTransaction),
+            }
+        });
+        match created {
+            Ok(created) => Ok(Some(created)),
+            Err(diesel::result::Error::RollbackTransaction) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn read(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        id: i32,
+    ) -> QueryResult<(SwapBatch, Vec<SwapJob>)> {
+        let batch = swap_batches::table
+            .filter(swap_batches::id.eq(id))
+            .filter(swap_batches::username.eq(username))
+            .select(SwapBatch::as_select())
+            .first(conn)?;
+        let jobs = SwapJob::read_batch(conn, batch.id)?;
+        Ok((batch, jobs))
+    }
+
+    /// The preprocessed user image a child job of batch `id` is run with
+    pub fn user_img_url(conn: &mut diesel::PgConnection, id: i32) -> QueryResult<String> {
+        swap_batches::table
+            .find(id)
+            .select(swap_batches::user_img_url)
+            .first(conn)
+    }
+
+    /// Clear the user image of every batch whose children have all finished, so uploads are
+    /// not retained
+    pub fn release_images(conn: &mut diesel::PgConnection) -> QueryResult<usize> {
+        let unfinished = swap_jobs::table
+            .filter(swap_jobs::batch_id.eq(swap_batches::id.nullable()))
+            .filter(swap_jobs::status.eq_any([STATUS_QUEUED, STATUS_RUNNING]));
+        diesel::update(
+            swap_batches::table
+                .filter(swap_batches::user_img_url.ne(""))
+                .filter(diesel::dsl::not(diesel::dsl::exists(unfinished))),
+        )
+        .set(swap_batches::user_img_url.eq(""))
+        .execute(conn)
+    }
+}
+
+/// Overall status of a batch from its children's statuses.
+///
+/// "queued" until any child starts, "running" until every child finishes, then
+/// "completed", "failed", or "partial" when only some children failed.
+pub fn aggregate_status<'a>(statuses: impl IntoIterator<Item = &'a str>) -> &'static str {
+    let (mut queued, mut completed, mut failed, mut total) = (0, 0, 0, 0);
+    for status in statuses {
+        total += 1;
+        match status {
+            STATUS_QUEUED => queued += 1,
+            STATUS_COMPLETED => completed += 1,
+            STATUS_FAILED => failed += 1,
+            _ => {}
+        }
+    }
+    if total == 0 || completed == total {
+        STATUS_COMPLETED
+    } else if queued == total {
+        STATUS_QUEUED
+    } else if completed + failed < total {
+        STATUS_RUNNING
+    } else if failed == total {
+        STATUS_FAILED
+    } else {
+        "partial"
+    }
+}
@@ -0,0 +1,145 @@
+use diesel::prelude::*;
+
+use crate::schema::swap_history;
+
+/// A completed swap, tying its output to the user who requested it
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+    Queryable,
+    Selectable,
+)]
+#[diesel(table_name = swap_history)]
+pub struct SwapHistory {
+    pub id: i32,
+    pub username: String,
+    /// `PersonModel` swapped against, when the request referenced one
+    pub model_id: Option<i32>,
+    pub model_img_url: String,
+    pub output_url: String,
+    pub status: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = swap_history)]
+pub struct NewSwapHistory<'a> {
+    pub username: &'a str,
+    pub model_id: Option<i32>,
+    pub model_img_url: &'a str,
+    pub output_url: &'a str,
+    pub status: &'a str,
+}
+
+/// Filters and pagination for listing a user's swap history
+#[derive(Debug, Clone, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SwapHistoryQuery {
+    /// 0-indexed page number
+    pub page: Option<i64>,
+    /// results per page, at most 100, defaults to 20
+    pub per_page: Option<i64>,
+    /// only swaps against this `PersonModel`
+    pub model_id: Option<i32>,
+    /// only swaps created at or after this time
+    pub since: Option<chrono::NaiveDateTime>,
+    /// only swaps created before this time
+    pub until: Option<chrono::NaiveDateTime>,
+}
+
+impl SwapHistoryQuery {
+    pub const DEFAULT_PER_PAGE: i64 = 20;
+    pub const MAX_PER_PAGE: i64 = 100;
+
+    pub fn per_page(&self) -> i64 {
+        self.per_page
+            .unwrap_or(Self::DEFAULT_PER_PAGE)
+            .clamp(1, Self::MAX_PER_PAGE)
+    }
+
+    pub fn offset(&self) -> i64 {
+        crate::models::page_offset(self.page, self.per_page())
+    }
+}
+
+/// One page of a user's swap history
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct SwapHistoryPage {
+    pub swaps: Vec<SwapHistory>,
+    /// total number of swaps matching the filters, across all pages
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl SwapHistory {
+    pub fn insert(
+        conn: &mut diesel::PgConnection,
+        new_swap: &NewSwapHistory,
+    ) -> QueryResult<SwapHistory> {
+        diesel::insert_into(swap_history::table)
+            .values(new_swap)
+            .returning(SwapHistory::as_returning())
+            .get_result(conn)
+    }
+
+    pub fn list(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        query: &SwapHistoryQuery,
+    ) -> QueryResult<SwapHistoryPage> {
+        let filtered = || {
+            let mut statement = swap_history::table
+                .filter(swap_history::username.eq(username.to_owned()))
+                .into_boxed();
+            if let Some(model_id) = query.model_id {
+                statement = statement.filter(swap_history::model_id.eq(model_id));
+            }
+            if let Some(since) = query.since {
+                statement = statement.filter(swap_history::created_at.ge(since));
+            }
+            if let Some(until) = query.until {
+                statement = statement.filter(swap_history::created_at.lt(until));
+            }
+            statement
+        };
+        let total = filtered().count().get_result(conn)?;
+        let swaps = filtered()
+            .order(swap_history::created_at.desc())
+            .limit(query.per_page())
+            .offset(query.offset())
+            .select(SwapHistory::as_select())
+            .load(conn)?;
+        Ok(SwapHistoryPage {
+            swaps,
+            total,
+            page: query.page.unwrap_or(0).max(0),
+            per_page: query.per_page(),
+        })
+    }
+
+    pub fn read(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        id: i32,
+    ) -> QueryResult<SwapHistory> {
+        swap_history::table
+            .filter(swap_history::id.eq(id))
+            .filter(swap_history::username.eq(username))
+            .select(SwapHistory::as_select())
+            .first(conn)
+    }
+
+    pub fn delete(conn: &mut diesel::PgConnection, username: &str, id: i32) -> QueryResult<usize> {
+        diesel::delete(
+            swap_history::table
+                .filter(swap_history::id.eq(id))
+                .filter(swap_history::username.eq(username)),
+        )
+        .execute(conn)
+    }
+}
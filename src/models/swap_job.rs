@@ -0,0 +1,231 @@
+use diesel::prelude::*;
+
+use crate::extra_schemas::SwapPostResponse;
+use crate::models::swap_batch::SwapBatch;
+use crate::schema::swap_jobs;
+
+pub const STATUS_QUEUED: &str = "queued";
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_COMPLETED: &str = "completed";
+pub const STATUS_FAILED: &str = "failed";
+
+/// Error of a job whose worker stopped responding on its last attempt
+pub const STALE_ERROR: &str = "abandoned by its worker after the last attempt";
+
+/// Stale jobs put back on the queue and failed by [`SwapJob::requeue_stale`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StaleJobs {
+    pub requeued: usize,
+    pub failed: usize,
+}
+
+/// Ids of the stale `(id, attempts)` jobs to requeue, and of those to fail as they have had
+/// `max_attempts` attempts
+pub fn partition_stale(stale: &[(i32, i32)], max_attempts: i32) -> (Vec<i32>, Vec<i32>) {
+    let (fail, requeue): (Vec<&(i32, i32)>, Vec<&(i32, i32)>) = stale
+        .iter()
+        .partition(|(_, attempts)| *attempts >= max_attempts);
+    let ids = |jobs: Vec<&(i32, i32)>| jobs.into_iter().map(|(id, _)| *id).collect();
+    (ids(requeue), ids(fail))
+}
+
+/// A queued, running or finished swap; the queue itself is the set of `queued` rows
+#[derive(Debug, Clone, PartialEq, Queryable, QueryableByName, Selectable)]
+#[diesel(table_name = swap_jobs)]
+pub struct SwapJob {
+    pub id: i32,
+    pub username: String,
+    pub model_id: Option<i32>,
+    /// cleared once the job finishes, so uploads are not retained; `None` for a batch's
+    /// children, which use [`SwapBatch::user_img_url`]
+    pub user_img_url: Option<String>,
+    pub model_img_url: String,
+    pub status: String,
+    pub attempts: i32,
+    pub output_url: Option<String>,
+    pub error: Option<String>,
+    pub run_after: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    /// parent batch, when queued through `/v1/swap/batch`
+    pub batch_id: Option<i32>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = swap_jobs)]
+pub struct NewSwapJob<'a> {
+    pub username: &'a str,
+    pub model_id: Option<i32>,
+    pub user_img_url: Option<&'a str>,
+    pub model_img_url: &'a str,
+    pub batch_id: Option<i32>,
+}
+
+impl From<&SwapJob> for SwapPostResponse {
+    fn from(job: &SwapJob) -> Self {
+        SwapPostResponse {
+            id: Some(job.id),
+            output_url: job.output_url.clone().unwrap_or_default(),
+            status: job.status.clone(),
+            error: job.error.clone(),
+        }
+    }
+}
+
+impl SwapJob {
+    /// Enqueue `new_job` unless `capacity` jobs are already waiting or running.
+    ///
+    /// Returns `Ok(None)` when the queue is full.
+    pub fn enqueue(
+        conn: &mut diesel::PgConnection,
+        new_job: &NewSwapJob,
+        capacity: i64,
+    ) -> QueryResult<Option<SwapJob>> {
+        Self::enqueue_many(conn, std::slice::from_ref(new_job), capacity)
+            .map(|jobs| jobs.and_then(|mut jobs| jobs.pop()))
+    }
+
+    /// Enqueue all of `new_jobs`, or none of them if that would exceed `capacity`.
+    ///
+    /// A transaction-scoped advisory lock serialises concurrent enqueues so the
+    /// capacity check cannot be raced.
+    pub fn enqueue_many(
+        conn: &mut diesel::PgConnection,
+        new_jobs: &[NewSwapJob],
+        capacity: i64,
+    ) -> QueryResult<Option<Vec<SwapJob>>> {
+        conn.transaction(|conn| {
+            diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext('swap_jobs'))")
+                .execute(conn)?;
+            let pending: i64 = swap_jobs::table
+                .filter(swap_jobs::status.eq_any([STATUS_QUEUED, STATUS_RUNNING]))
+                .count()
+                .get_result(conn)?;
+            if pending + new_jobs.len() as i64 > capacity {
+                return Ok(None);
+            }
+            diesel::insert_into(swap_jobs::table)
+                .values(new_jobs)
+                .returning(SwapJob::as_returning())
+                .get_results(conn)
+                .map(Some)
+        })
+    }
+
+    /// Atomically take the oldest runnable job, marking it `running`
+    pub fn claim(conn: &mut diesel::PgConnection) -> QueryResult<Option<SwapJob>> {
+        diesel::sql_query(
+            "UPDATE swap_jobs
+             SET status = 'running', attempts = attempts + 1, updated_at = CURRENT_TIMESTAMP
+             WHERE id = (SELECT id
+                         FROM swap_jobs
+                         WHERE status = 'queued' AND run_after <= CURRENT_TIMESTAMP
+                         ORDER BY id
+                         LIMIT 1 FOR UPDATE SKIP LOCKED)
+             RETURNING *",
+        )
+        .get_result(conn)
+        .optional()
+    }
+
+    pub fn complete(
+        conn: &mut diesel::PgConnection,
+        id: i32,
+        response: &SwapPostResponse,
+    ) -> QueryResult<SwapJob> {
+        diesel::update(swap_jobs::table.find(id))
+            .set((
+                swap_jobs::status.eq(STATUS_COMPLETED),
+                swap_jobs::output_url.eq(&response.output_url),
+                swap_jobs::error.eq(None::<String>),
+                swap_jobs::user_img_url.eq(""),
+                swap_jobs::updated_at.eq(diesel::dsl::now),
+            ))
+            .returning(SwapJob::as_returning())
+            .get_result(conn)
+    }
+
+    /// Put a failed job back on the queue, to run no sooner than `retry_in`
+    pub fn retry(
+        conn: &mut diesel::PgConnection,
+        id: i32,
+        error: &str,
+        retry_in: std::time::Duration,
+    ) -> QueryResult<usize> {
+        diesel::update(swap_jobs::table.find(id))
+            .set((
+                swap_jobs::status.eq(STATUS_QUEUED),
+                swap_jobs::error.eq(error),
+                swap_jobs::run_after.eq(diesel::dsl::now + crate::models::interval(retry_in)),
+                swap_jobs::updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)
+    }
+
+    pub fn fail(conn: &mut diesel::PgConnection, id: i32, error: &str) -> QueryResult<usize> {
+        diesel::update(swap_jobs::table.find(id))
+            .set((
+                swap_jobs::status.eq(STATUS_FAILED),
+                swap_jobs::error.eq(error),
+                swap_jobs::user_img_url.eq(""),
+                swap_jobs::updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)
+    }
+
+    /// Requeue jobs left `running` for longer than `stale_after`, e.g., by a crashed worker,
+    /// and fail those that have already had `max_attempts` attempts
+    pub fn requeue_stale(
+        conn: &mut diesel::PgConnection,
+        stale_after: std::time::Duration,
+        max_attempts: i32,
+    ) -> QueryResult<StaleJobs> {
+        conn.transaction(|conn| {
+            let stale: Vec<(i32, i32)> = swap_jobs::table
+                .filter(swap_jobs::status.eq(STATUS_RUNNING))
+                .filter(
+                    swap_jobs::updated_at
+                        .lt(diesel::dsl::now - crate::models::interval(stale_after)),
+                )
+                .select((swap_jobs::id, swap_jobs::attempts))
+                .for_update()
+                .skip_locked()
+                .load(conn)?;
+            let (requeue, fail) = partition_stale(&stale, max_attempts);
+            let requeued = diesel::update(swap_jobs::table.filter(swap_jobs::id.eq_any(&requeue)))
+                .set((
+                    swap_jobs::status.eq(STATUS_QUEUED),
+                    swap_jobs::updated_at.eq(diesel::dsl::now),
+                ))
+                .execute(conn)?;
+            let failed = diesel::update(swap_jobs::table.filter(swap_jobs::id.eq_any(&fail)))
+                .set((
+                    swap_jobs::status.eq(STATUS_FAILED),
+                    swap_jobs::error.eq(STALE_ERROR),
+                    swap_jobs::user_img_url.eq(""),
+                    swap_jobs::updated_at.eq(diesel::dsl::now),
+                ))
+                .execute(conn)?;
+            if failed > 0 {
+                SwapBatch::release_images(conn)?;
+            }
+            Ok(StaleJobs { requeued, failed })
+        })
+    }
+
+    pub fn read_batch(conn: &mut diesel::PgConnection, batch_id: i32) -> QueryResult<Vec<SwapJob>> {
+        swap_jobs::table
+            .filter(swap_jobs::batch_id.eq(batch_id))
+            .order(swap_jobs::id)
+            .select(SwapJob::as_select())
+            .load(conn)
+    }
+
+    pub fn read(conn: &mut diesel::PgConnection, username: &str, id: i32) -> QueryResult<SwapJob> {
+        swap_jobs::table
+            .filter(swap_jobs::id.eq(id))
+            .filter(swap_jobs::username.eq(username))
+            .select(SwapJob::as_select())
+            .first(conn)
+    }
+}
@@ -0,0 +1,153 @@
+use diesel::prelude::*;
+
+use crate::models::access_token::hash_token;
+use crate::schema::{totp_credentials, totp_recovery_codes};
+
+/// A user's TOTP secret, active once `confirmed_at` is set
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = totp_credentials)]
+pub struct TotpCredential {
+    pub username: String,
+    pub secret: Vec<u8>,
+    pub confirmed_at: Option<chrono::NaiveDateTime>,
+    pub last_used_step: Option<i64>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, utoipa::ToSchema)]
+pub struct TotpCodeRequest {
+    /// current code from the authenticator app, or when disabling, also a recovery code
+    pub code: String,
+}
+
+/// A new, unconfirmed TOTP enrolment, to add to an authenticator app
+#[derive(Debug, Clone, PartialEq, serde::Serialize, utoipa::ToSchema)]
+pub struct TotpEnrolment {
+    /// base32 secret, for entering by hand
+    pub secret: String,
+    /// `otpauth://` URL, for rendering as a QR code
+    pub otpauth_url: String,
+}
+
+/// Single-use recovery codes; shown once, when TOTP is confirmed
+#[derive(Debug, Clone, PartialEq, serde::Serialize, utoipa::ToSchema)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+impl TotpCredential {
+    /// Start (or restart) enrolment; refuses to replace a confirmed credential
+    pub fn enrol(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        secret: &[u8],
+    ) -> QueryResult<Option<TotpCredential>> {
+        conn.transaction(|conn| {
+            let confirmed = totp_credentials::table
+                .find(username)
+                .select(totp_credentials::confirmed_at.is_not_null())
+                .for_update()
+                .first::<bool>(conn)
+                .optional()?;
+            if confirmed == Some(true) {
+                return Ok(None);
+            }
+            diesel::insert_into(totp_credentials::table)
+                .values((
+                    totp_credentials::username.eq(username),
+                    totp_credentials::secret.eq(secret),
+                ))
+                .on_conflict(totp_credentials::username)
+                .do_update()
+                .set((
+                    totp_credentials::secret.eq(secret),
+                    totp_credentials::last_used_step.eq(None::<i64>),
+                    totp_credentials::created_at.eq(diesel::dsl::now),
+                ))
+                .returning(TotpCredential::as_returning())
+                .get_result(conn)
+                .map(Some)
+        })
+    }
+
+    pub fn find(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+    ) -> QueryResult<Option<TotpCredential>> {
+        totp_credentials::table
+            .find(username)
+            .select(TotpCredential::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    /// Record that the code for `step` was accepted, unless that step or a later one already was
+    pub fn use_step(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        step: i64,
+    ) -> QueryResult<bool> {
+        diesel::update(
+            totp_credentials::table.find(username).filter(
+                totp_credentials::last_used_step
+                    .is_null()
+                    .or(totp_credentials::last_used_step.lt(step)),
+            ),
+        )
+        .set(totp_credentials::last_used_step.eq(step))
+        .execute(conn)
+        .map(|updated| updated == 1)
+    }
+
+    /// Confirm enrolment, replacing any recovery codes with `recovery_codes`
+    pub fn confirm(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        recovery_codes: &[String],
+    ) -> QueryResult<usize> {
+        conn.transaction(|conn| {
+            diesel::delete(
+                totp_recovery_codes::table.filter(totp_recovery_codes::username.eq(username)),
+            )
+            .execute(conn)?;
+            diesel::insert_into(totp_recovery_codes::table)
+                .values(
+                    recovery_codes
+                        .iter()
+                        .map(|code| {
+                            (
+                                totp_recovery_codes::username.eq(username),
+                                totp_recovery_codes::code_hash.eq(hash_token(code)),
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .execute(conn)?;
+            diesel::update(totp_credentials::table.find(username))
+                .set(totp_credentials::confirmed_at.eq(diesel::dsl::now))
+                .execute(conn)
+        })
+    }
+
+    /// Use up an unused recovery code of `username`
+    pub fn use_recovery_code(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        code: &str,
+    ) -> QueryResult<bool> {
+        diesel::update(
+            totp_recovery_codes::table
+                .filter(totp_recovery_codes::username.eq(username))
+                .filter(totp_recovery_codes::code_hash.eq(hash_token(code)))
+                .filter(totp_recovery_codes::used_at.is_null()),
+        )
+        .set(totp_recovery_codes::used_at.eq(diesel::dsl::now))
+        .execute(conn)
+        .map(|updated| updated > 0)
+    }
+
+    /// Remove the credential and, by cascade, its recovery codes
+    pub fn delete(conn: &mut diesel::PgConnection, username: &str) -> QueryResult<usize> {
+        diesel::delete(totp_credentials::table.find(username)).execute(conn)
+    }
+}
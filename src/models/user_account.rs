@@ -0,0 +1,191 @@
+use diesel::prelude::*;
+
+use crate::auth::roles::Role;
+use crate::schema::user_accounts;
+
+/// serve-replica's state for one user: role, whether disabled, and credits used
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    serde::Serialize,
+    serde::Deserialize,
+    utoipa::ToSchema,
+    Queryable,
+    Selectable,
+)]
+#[diesel(table_name = user_accounts)]
+pub struct UserAccount {
+    pub username: String,
+    /// `user`, `moderator` or `admin`
+    pub role: String,
+    pub disabled_at: Option<chrono::NaiveDateTime>,
+    /// completed swaps since credits were last reset
+    pub credits_used: i64,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_seen_at: chrono::NaiveDateTime,
+}
+
+/// Filters and pagination for listing users
+#[derive(Debug, Clone, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UserAccountQuery {
+    /// 0-indexed page number
+    pub page: Option<i64>,
+    /// results per page, at most 100, defaults to 20
+    pub per_page: Option<i64>,
+    /// only users with this role
+    pub role: Option<String>,
+    /// only disabled, or only enabled, users
+    pub disabled: Option<bool>,
+}
+
+impl UserAccountQuery {
+    pub const DEFAULT_PER_PAGE: i64 = 20;
+    pub const MAX_PER_PAGE: i64 = 100;
+
+    pub fn per_page(&self) -> i64 {
+        self.per_page
+            .unwrap_or(Self::DEFAULT_PER_PAGE)
+            .clamp(1, Self::MAX_PER_PAGE)
+    }
+
+    pub fn offset(&self) -> i64 {
+        crate::models::page_offset(self.page, self.per_page())
+    }
+}
+
+/// One page of users
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct UserAccountPage {
+    pub users: Vec<UserAccount>,
+    /// total number of users matching the filters, across all pages
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct RoleUpdateRequest {
+    pub role: Role,
+}
+
+impl UserAccount {
+    /// Fetch `username`'s account, creating it on first sight, and note that they were seen
+    pub fn touch(conn: &mut diesel::PgConnection, username: &str) -> QueryResult<UserAccount> {
+        diesel::insert_into(user_accounts::table)
+            .values(user_accounts::username.eq(username))
+            .on_conflict(user_accounts::username)
+            .do_update()
+            .set(user_accounts::last_seen_at.eq(diesel::dsl::now))
+            .returning(UserAccount::as_returning())
+            .get_result(conn)
+    }
+
+    pub fn find(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+    ) -> QueryResult<Option<UserAccount>> {
+        user_accounts::table
+            .find(username)
+            .select(UserAccount::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    pub fn is_disabled(conn: &mut diesel::PgConnection, username: &str) -> QueryResult<bool> {
+        Self::find(conn, username)
+            .map(|account| account.is_some_and(|account| account.disabled_at.is_some()))
+    }
+
+    pub fn list(
+        conn: &mut diesel::PgConnection,
+        query: &UserAccountQuery,
+    ) -> QueryResult<UserAccountPage> {
+        let filtered = || {
+            let mut statement = user_accounts::table.into_boxed();
+            if let Some(role) = &query.role {
+                statement = statement.filter(user_accounts::role.eq(role.to_owned()));
+            }
+            match query.disabled {
+                Some(true) => {
+                    statement = statement.filter(user_accounts::disabled_at.is_not_null())
+                }
+                Some(false) => statement = statement.filter(user_accounts::disabled_at.is_null()),
+                None => {}
+            }
+            statement
+        };
+        let total = filtered().count().get_result(conn)?;
+        let users = filtered()
+            .order(user_accounts::username)
+            .limit(query.per_page())
+            .offset(query.offset())
+            .select(UserAccount::as_select())
+            .load(conn)?;
+        Ok(UserAccountPage {
+            users,
+            total,
+            page: query.page.unwrap_or(0).max(0),
+            per_page: query.per_page(),
+        })
+    }
+
+    /// Change the role of an existing account; `None` if `username` has none
+    pub fn set_role(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        role: &str,
+    ) -> QueryResult<Option<UserAccount>> {
+        diesel::update(user_accounts::table.find(username))
+            .set(user_accounts::role.eq(role))
+            .returning(UserAccount::as_returning())
+            .get_result(conn)
+            .optional()
+    }
+
+    /// Disable, or re-enable, an existing account; `None` if `username` has none
+    pub fn set_disabled(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        disabled: bool,
+    ) -> QueryResult<Option<UserAccount>> {
+        let account = diesel::update(user_accounts::table.find(username));
+        if disabled {
+            account
+                .set(user_accounts::disabled_at.eq(diesel::dsl::now.nullable()))
+                .returning(UserAccount::as_returning())
+                .get_result(conn)
+                .optional()
+        } else {
+            account
+                .set(user_accounts::disabled_at.eq(None::<chrono::NaiveDateTime>))
+                .returning(UserAccount::as_returning())
+                .get_result(conn)
+                .optional()
+        }
+    }
+
+    pub fn reset_credits(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+    ) -> QueryResult<UserAccount> {
+        diesel::update(user_accounts::table.find(username))
+            .set(user_accounts::credits_used.eq(0))
+            .returning(UserAccount::as_returning())
+            .get_result(conn)
+    }
+
+    /// Charge `username` one credit, e.g., for a completed swap
+    pub fn use_credit(conn: &mut diesel::PgConnection, username: &str) -> QueryResult<usize> {
+        diesel::insert_into(user_accounts::table)
+            .values((
+                user_accounts::username.eq(username),
+                user_accounts::credits_used.eq(1),
+            ))
+            .on_conflict(user_accounts::username)
+            .do_update()
+            .set(user_accounts::credits_used.eq(user_accounts::credits_used + 1))
+            .execute(conn)
+    }
+}
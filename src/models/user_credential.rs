@@ -0,0 +1,124 @@
+use diesel::prelude::*;
+
+use crate::schema::user_credentials;
+
+/// Login details of a user who registered through `/api/register`
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = user_credentials)]
+pub struct UserCredential {
+    pub username: String,
+    pub email: String,
+    pub password_hash: String,
+    pub email_verified_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = user_credentials)]
+pub struct NewUserCredential<'a> {
+    pub username: &'a str,
+    pub email: &'a str,
+    pub password_hash: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, utoipa::ToSchema)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, utoipa::ToSchema)]
+pub struct VerifyEmailRequest {
+    /// token from the verification email
+    pub token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, utoipa::ToSchema)]
+pub struct EmailRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, utoipa::ToSchema)]
+pub struct ResetPasswordRequest {
+    /// token from the password reset email
+    pub token: String,
+    pub password: String,
+}
+
+/// Hash `password` into a PHC string with Argon2id and a random salt
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    use argon2::password_hash::{PasswordHasher, SaltString};
+
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    argon2::Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+}
+
+impl UserCredential {
+    pub fn insert(
+        conn: &mut diesel::PgConnection,
+        new_credential: &NewUserCredential,
+    ) -> QueryResult<UserCredential> {
+        diesel::insert_into(user_credentials::table)
+            .values(new_credential)
+            .returning(UserCredential::as_returning())
+            .get_result(conn)
+    }
+
+    pub fn find(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+    ) -> QueryResult<Option<UserCredential>> {
+        user_credentials::table
+            .find(username)
+            .select(UserCredential::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    pub fn find_by_email(
+        conn: &mut diesel::PgConnection,
+        email: &str,
+    ) -> QueryResult<Option<UserCredential>> {
+        user_credentials::table
+            .filter(user_credentials::email.eq(email))
+            .select(UserCredential::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    pub fn verify_password(&self, password: &str) -> bool {
+        use argon2::password_hash::{PasswordHash, PasswordVerifier};
+
+        PasswordHash::new(&self.password_hash).is_ok_and(|hash| {
+            argon2::Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+
+    pub fn mark_verified(conn: &mut diesel::PgConnection, username: &str) -> QueryResult<usize> {
+        diesel::update(user_credentials::table.find(username))
+            .set((
+                user_credentials::email_verified_at.eq(diesel::dsl::now),
+                user_credentials::updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)
+    }
+
+    pub fn set_password_hash(
+        conn: &mut diesel::PgConnection,
+        username: &str,
+        password_hash: &str,
+    ) -> QueryResult<usize> {
+        diesel::update(user_credentials::table.find(username))
+            .set((
+                user_credentials::password_hash.eq(password_hash),
+                user_credentials::updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)
+    }
+}
@@ -0,0 +1,61 @@
+//! The client's address: the peer's, unless the peer is a trusted reverse proxy, whose
+//! `Forwarded`/`X-Forwarded-For` then says who the client is
+
+/// Word of `TRUSTED_PROXIES` trusting peers on Unix domain sockets, which have no IP
+pub const UNIX_PEERS: &str = "unix";
+
+/// Peers whose forwarding headers are believed, per `TRUSTED_PROXIES`; none by default
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrustedProxies {
+    pub ips: Vec<std::net::IpAddr>,
+    /// whether peers on Unix domain sockets, e.g., nginx on the same host, are trusted
+    pub unix: bool,
+}
+
+impl std::str::FromStr for TrustedProxies {
+    type Err = String;
+
+    /// Comma-separated IP addresses and [`UNIX_PEERS`]
+    fn from_str(proxies: &str) -> Result<TrustedProxies, String> {
+        let mut trusted = TrustedProxies::default();
+        for proxy in proxies
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+        {
+            match proxy {
+                UNIX_PEERS => trusted.unix = true,
+                ip => trusted.ips.push(ip.parse().map_err(|_| {
+                    format!("{:?} is neither an IP address nor `{}`", ip, UNIX_PEERS)
+                })?),
+            }
+        }
+        Ok(trusted)
+    }
+}
+
+impl TrustedProxies {
+    /// IP of the client behind `req`; `None` for an untrusted peer on a Unix domain socket
+    pub fn client_ip(&self, req: &actix_web::HttpRequest) -> Option<String> {
+        let peer = req.peer_addr().map(|addr| addr.ip());
+        let trusted = match peer {
+            Some(ip) => self.ips.contains(&ip),
+            None => self.unix,
+        };
+        if trusted {
+            req.connection_info()
+                .realip_remote_addr()
+                .map(str::to_owned)
+        } else {
+            peer.map(|ip| ip.to_string())
+        }
+    }
+}
+
+/// [`TrustedProxies::client_ip`] of the app's proxies, trusting none if it has no such data
+pub fn client_ip(req: &actix_web::HttpRequest) -> Option<String> {
+    match req.app_data::<actix_web::web::Data<TrustedProxies>>() {
+        Some(trusted) => trusted.client_ip(req),
+        None => TrustedProxies::default().client_ip(req),
+    }
+}
@@ -0,0 +1,239 @@
+use actix_web::post;
+
+use crate::auth::account::{
+    issue_email_token, username_taken, validate_email, validate_password, validate_username,
+    AccountConfig,
+};
+use crate::auth::throttle::LoginThrottle;
+use crate::errors::ServeReplicaError;
+use crate::mail::Mailer;
+use crate::models::access_token::AccessToken;
+use crate::models::email_token::{EmailToken, PURPOSE_RESET_PASSWORD, PURPOSE_VERIFY_EMAIL};
+use crate::models::refresh_token::RefreshToken;
+use crate::models::user_credential::{
+    hash_password, EmailRequest, NewUserCredential, RegisterRequest, ResetPasswordRequest,
+    UserCredential, VerifyEmailRequest,
+};
+use crate::DbPool;
+
+fn send_or_log(mailer: &dyn Mailer, email: &crate::mail::Email) {
+    // the user can always ask again, so a mail failure should not fail their request
+    if let Err(err) = mailer.send(email) {
+        log::error!("mailing {}: {}", email.to, err);
+    }
+}
+
+/// Count a request to mail `email` against the caller's limit, if logins are throttled
+fn count_mail_request(
+    throttle: Option<&LoginThrottle>,
+    req: &actix_web::HttpRequest,
+    email: &str,
+) -> impl FnOnce() -> Result<(), ServeReplicaError> {
+    let throttle = throttle.cloned();
+    let ip = crate::proxy::client_ip(req).unwrap_or_else(|| String::from("unknown"));
+    let email = email.to_owned();
+    move || match throttle {
+        Some(throttle) => throttle.count_mail_request(&email, &ip),
+        None => Ok(()),
+    }
+}
+
+/// Register a new user; they must verify their email before they can log in.
+///
+/// An email that is already registered gets the same answer, so as not to reveal who is
+/// registered; its owner is mailed instead.
+#[utoipa::path(
+    request_body = RegisterRequest,
+    responses(
+        (status = 201, description = "Registered, or the email already was; either way it was mailed"),
+        (status = 400, description = "Invalid or taken username, or invalid email or password"),
+        (status = 429, description = "Too many emails requested for this address or from this IP")
+    )
+)]
+#[post("/register")]
+pub async fn register(
+    pool: actix_web::web::Data<DbPool>,
+    config: actix_web::web::Data<AccountConfig>,
+    mailer: actix_web::web::Data<dyn Mailer>,
+    throttle: Option<actix_web::web::Data<LoginThrottle>>,
+    req: actix_web::HttpRequest,
+    form: actix_web::web::Json<RegisterRequest>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let form = form.into_inner();
+    let email = form.email.trim().to_lowercase();
+    validate_username(&form.username)?;
+    validate_email(&email)?;
+    validate_password(&form.password)?;
+    let count_mail_request = count_mail_request(throttle.as_deref(), &req, &email);
+    actix_web::web::block(move || {
+        use diesel::Connection;
+
+        let password_hash = hash_password(&form.password)
+            .map_err(|err| ServeReplicaError::BadRequest(err.to_string()))?;
+        let mut conn = pool.get()?;
+        let mail = conn.transaction(|conn| {
+            if username_taken(conn, &form.username)? {
+                return Err(ServeReplicaError::BadRequest(String::from(
+                    "username already taken",
+                )));
+            }
+            count_mail_request()?;
+            if let Some(owner) = UserCredential::find_by_email(conn, &email)? {
+                return Ok(config.already_registered_email(&owner));
+            }
+            let credential = UserCredential::insert(
+                conn,
+                &NewUserCredential {
+                    username: &form.username,
+                    email: &email,
+                    password_hash: &password_hash,
+                },
+            )?;
+            Ok(config.start_verification(conn, &credential)?)
+        })?;
+        send_or_log(&**mailer, &mail);
+        Ok::<_, ServeReplicaError>(())
+    })
+    .await??;
+    Ok(actix_web::HttpResponse::Created().finish())
+}
+
+/// Verify a user's email with the token mailed to them
+#[utoipa::path(
+    request_body = VerifyEmailRequest,
+    responses(
+        (status = 204, description = "Verified"),
+        (status = 400, description = "Invalid, expired or used token")
+    )
+)]
+#[post("/verify_email")]
+pub async fn verify_email(
+    pool: actix_web::web::Data<DbPool>,
+    form: actix_web::web::Json<VerifyEmailRequest>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    actix_web::web::block(move || {
+        use diesel::Connection;
+
+        let mut conn = pool.get()?;
+        conn.transaction(|conn| {
+            let token =
+                EmailToken::consume(conn, &form.token, PURPOSE_VERIFY_EMAIL)?.ok_or_else(|| {
+                    ServeReplicaError::BadRequest(String::from("invalid or expired token"))
+                })?;
+            UserCredential::mark_verified(conn, &token.username)?;
+            EmailToken::expire_outstanding(conn, &token.username, PURPOSE_VERIFY_EMAIL)?;
+            Ok(())
+        })
+    })
+    .await??;
+    Ok(actix_web::HttpResponse::NoContent().finish())
+}
+
+/// Send another verification email; always accepted, so as not to reveal who is registered
+#[utoipa::path(
+    request_body = EmailRequest,
+    responses(
+        (status = 202, description = "Sent, if the email is registered and unverified"),
+        (status = 429, description = "Too many emails requested for this address or from this IP")
+    )
+)]
+#[post("/verify_email/resend")]
+pub async fn resend_verification(
+    pool: actix_web::web::Data<DbPool>,
+    config: actix_web::web::Data<AccountConfig>,
+    mailer: actix_web::web::Data<dyn Mailer>,
+    throttle: Option<actix_web::web::Data<LoginThrottle>>,
+    req: actix_web::HttpRequest,
+    form: actix_web::web::Json<EmailRequest>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let email = form.email.trim().to_lowercase();
+    let count_mail_request = count_mail_request(throttle.as_deref(), &req, &email);
+    actix_web::web::block(move || {
+        count_mail_request()?;
+        let mut conn = pool.get()?;
+        if let Some(credential) = UserCredential::find_by_email(&mut conn, &email)? {
+            if credential.email_verified_at.is_none() {
+                let verification = config.start_verification(&mut conn, &credential)?;
+                send_or_log(&**mailer, &verification);
+            }
+        }
+        Ok::<_, ServeReplicaError>(())
+    })
+    .await??;
+    Ok(actix_web::HttpResponse::Accepted().finish())
+}
+
+/// Mail a password reset link; always accepted, so as not to reveal who is registered
+#[utoipa::path(
+    request_body = EmailRequest,
+    responses(
+        (status = 202, description = "Sent, if the email is registered"),
+        (status = 429, description = "Too many emails requested for this address or from this IP")
+    )
+)]
+#[post("/forgot_password")]
+pub async fn forgot_password(
+    pool: actix_web::web::Data<DbPool>,
+    config: actix_web::web::Data<AccountConfig>,
+    mailer: actix_web::web::Data<dyn Mailer>,
+    throttle: Option<actix_web::web::Data<LoginThrottle>>,
+    req: actix_web::HttpRequest,
+    form: actix_web::web::Json<EmailRequest>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let email = form.email.trim().to_lowercase();
+    let count_mail_request = count_mail_request(throttle.as_deref(), &req, &email);
+    actix_web::web::block(move || {
+        count_mail_request()?;
+        let mut conn = pool.get()?;
+        if let Some(credential) = UserCredential::find_by_email(&mut conn, &email)? {
+            let token = issue_email_token(
+                &mut conn,
+                &credential.username,
+                PURPOSE_RESET_PASSWORD,
+                config.reset_ttl,
+            )?;
+            send_or_log(&**mailer, &config.reset_email(&credential, &token));
+        }
+        Ok::<_, ServeReplicaError>(())
+    })
+    .await??;
+    Ok(actix_web::HttpResponse::Accepted().finish())
+}
+
+/// Set a new password with the token from a reset email. Every existing token of the user is
+/// revoked. Since the reset link was mailed to them, this also verifies their email.
+#[utoipa::path(
+    request_body = ResetPasswordRequest,
+    responses(
+        (status = 204, description = "Password changed"),
+        (status = 400, description = "Invalid, expired or used token, or invalid password")
+    )
+)]
+#[post("/reset_password")]
+pub async fn reset_password(
+    pool: actix_web::web::Data<DbPool>,
+    form: actix_web::web::Json<ResetPasswordRequest>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    validate_password(&form.password)?;
+    actix_web::web::block(move || {
+        use diesel::Connection;
+
+        let password_hash = hash_password(&form.password)
+            .map_err(|err| ServeReplicaError::BadRequest(err.to_string()))?;
+        let mut conn = pool.get()?;
+        conn.transaction(|conn| {
+            let token = EmailToken::consume(conn, &form.token, PURPOSE_RESET_PASSWORD)?
+                .ok_or_else(|| {
+                    ServeReplicaError::BadRequest(String::from("invalid or expired token"))
+                })?;
+            UserCredential::set_password_hash(conn, &token.username, &password_hash)?;
+            UserCredential::mark_verified(conn, &token.username)?;
+            EmailToken::expire_outstanding(conn, &token.username, PURPOSE_RESET_PASSWORD)?;
+            RefreshToken::revoke_user(conn, &token.username)?;
+            AccessToken::revoke_user(conn, &token.username)?;
+            Ok(())
+        })
+    })
+    .await??;
+    Ok(actix_web::HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,259 @@
+use actix_web::{get, post, put};
+use diesel::prelude::*;
+
+use crate::auth::roles::{require_role, Role};
+use crate::auth::AuthenticatedUser;
+use crate::errors::ServeReplicaError;
+use crate::models::audit_event::{AuditEvent, AuditEventPage, AuditEventQuery};
+use crate::models::user_account::{
+    RoleUpdateRequest, UserAccount, UserAccountPage, UserAccountQuery,
+};
+use crate::DbPool;
+
+use replica_backend::models::person_model::PersonModel;
+use replica_backend::models::profile::Profile;
+
+/// List users who have used serve-replica, with their role, status and credits
+#[utoipa::path(
+    params(UserAccountQuery),
+    responses((status = 200, description = "Page of users", body = UserAccountPage))
+)]
+#[get("/users")]
+pub async fn read_users(
+    pool: actix_web::web::Data<DbPool>,
+    query: actix_web::web::Query<UserAccountQuery>,
+) -> Result<actix_web::web::Json<UserAccountPage>, ServeReplicaError> {
+    let page = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        UserAccount::list(&mut conn, &query).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(page))
+}
+
+/// Get one user's account
+#[utoipa::path(
+    params(("username" = String, Path, description = "Username")),
+    responses(
+        (status = 200, description = "User", body = UserAccount),
+        (status = 404, description = "No such user")
+    )
+)]
+#[get("/users/{username}")]
+pub async fn read_user(
+    pool: actix_web::web::Data<DbPool>,
+    username: actix_web::web::Path<String>,
+) -> Result<actix_web::web::Json<UserAccount>, ServeReplicaError> {
+    let username = username.into_inner();
+    let account = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        UserAccount::find(&mut conn, &username)?
+            .ok_or_else(|| ServeReplicaError::NotFound(format!("user {} not found", username)))
+    })
+    .await??;
+    Ok(actix_web::web::Json(account))
+}
+
+/// Change a user's role; admin only
+#[utoipa::path(
+    params(("username" = String, Path, description = "Username")),
+    request_body = RoleUpdateRequest,
+    responses(
+        (status = 200, description = "Updated", body = UserAccount),
+        (status = 404, description = "No such user")
+    )
+)]
+#[put("/users/{username}/role")]
+pub async fn update_role(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    username: actix_web::web::Path<String>,
+    form: actix_web::web::Json<RoleUpdateRequest>,
+) -> Result<actix_web::web::Json<UserAccount>, ServeReplicaError> {
+    require_role(&user, Role::Admin)?;
+    let username = username.into_inner();
+    if username == user.username && form.role < Role::Admin {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "you cannot demote yourself",
+        )));
+    }
+    let account = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        UserAccount::set_role(&mut conn, &username, form.role.as_str())?
+            .ok_or_else(|| ServeReplicaError::NotFound(format!("user {} not found", username)))
+    })
+    .await??;
+    Ok(actix_web::web::Json(account))
+}
+
+/// Disable a user; none of their tokens, API keys or clients work until re-enabled. Admin only
+#[utoipa::path(
+    params(("username" = String, Path, description = "Username")),
+    responses(
+        (status = 200, description = "Disabled", body = UserAccount),
+        (status = 404, description = "No such user")
+    )
+)]
+#[post("/users/{username}/disable")]
+pub async fn disable(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    username: actix_web::web::Path<String>,
+) -> Result<actix_web::web::Json<UserAccount>, ServeReplicaError> {
+    require_role(&user, Role::Admin)?;
+    let username = username.into_inner();
+    if username == user.username {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "you cannot disable yourself",
+        )));
+    }
+    let account = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        UserAccount::set_disabled(&mut conn, &username, true)?
+            .ok_or_else(|| ServeReplicaError::NotFound(format!("user {} not found", username)))
+    })
+    .await??;
+    log::warn!("{} disabled account {}", user.username, account.username);
+    Ok(actix_web::web::Json(account))
+}
+
+/// Re-enable a disabled user; admin only
+#[utoipa::path(
+    params(("username" = String, Path, description = "Username")),
+    responses(
+        (status = 200, description = "Enabled", body = UserAccount),
+        (status = 404, description = "No such user")
+    )
+)]
+#[post("/users/{username}/enable")]
+pub async fn enable(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    username: actix_web::web::Path<String>,
+) -> Result<actix_web::web::Json<UserAccount>, ServeReplicaError> {
+    require_role(&user, Role::Admin)?;
+    let account = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        UserAccount::set_disabled(&mut conn, &username, false)?
+            .ok_or_else(|| ServeReplicaError::NotFound(format!("user {} not found", username)))
+    })
+    .await??;
+    Ok(actix_web::web::Json(account))
+}
+
+/// Reset a user's used credits to zero; admin only
+#[utoipa::path(
+    params(("username" = String, Path, description = "Username")),
+    responses(
+        (status = 200, description = "Reset", body = UserAccount),
+        (status = 404, description = "No such user")
+    )
+)]
+#[post("/users/{username}/credits/reset")]
+pub async fn reset_credits(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    username: actix_web::web::Path<String>,
+) -> Result<actix_web::web::Json<UserAccount>, ServeReplicaError> {
+    require_role(&user, Role::Admin)?;
+    let account = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        UserAccount::reset_credits(&mut conn, &username).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(account))
+}
+
+/// Query the security audit log, newest first; admin only
+#[utoipa::path(
+    params(AuditEventQuery),
+    responses((status = 200, description = "Page of audit events", body = AuditEventPage))
+)]
+#[get("/audit")]
+pub async fn read_audit_events(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: actix_web::web::Query<AuditEventQuery>,
+) -> Result<actix_web::web::Json<AuditEventPage>, ServeReplicaError> {
+    require_role(&user, Role::Admin)?;
+    let page = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        AuditEvent::list(&mut conn, &query).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(page))
+}
+
+/// List any user's `PersonModel`s
+#[utoipa::path(
+    params(("username" = String, Path, description = "Username")),
+    responses((status = 200, description = "PersonModels", body = Vec<PersonModel>))
+)]
+#[get("/users/{username}/models")]
+pub async fn read_models(
+    pool: actix_web::web::Data<DbPool>,
+    username: actix_web::web::Path<String>,
+) -> Result<actix_web::web::Json<Vec<PersonModel>>, ServeReplicaError> {
+    use replica_backend::schema::person_model;
+
+    let person_models = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        person_model::table
+            .filter(person_model::username.eq(username.into_inner()))
+            .order(person_model::id)
+            .load::<PersonModel>(&mut conn)
+            .map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(person_models))
+}
+
+/// Get any `PersonModel`
+#[utoipa::path(
+    params(("id" = i32, Path, description = "ID of PersonModel")),
+    responses(
+        (status = 200, description = "PersonModel", body = PersonModel),
+        (status = 404, description = "No such PersonModel")
+    )
+)]
+#[get("/models/{id}")]
+pub async fn read_model(
+    pool: actix_web::web::Data<DbPool>,
+    id: actix_web::web::Path<i32>,
+) -> Result<actix_web::web::Json<PersonModel>, ServeReplicaError> {
+    let person_model = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        replica_backend::schema::person_model::table
+            .find(id.into_inner())
+            .first::<PersonModel>(&mut conn)
+            .map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(person_model))
+}
+
+/// Get any user's `Profile`
+#[utoipa::path(
+    params(("username" = String, Path, description = "Username")),
+    responses(
+        (status = 200, description = "Profile", body = Profile),
+        (status = 404, description = "User has no Profile")
+    )
+)]
+#[get("/users/{username}/profile")]
+pub async fn read_profile(
+    pool: actix_web::web::Data<DbPool>,
+    username: actix_web::web::Path<String>,
+) -> Result<actix_web::web::Json<Profile>, ServeReplicaError> {
+    use replica_backend::schema::profile;
+
+    let profile = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        profile::table
+            .filter(profile::username.eq(username.into_inner()))
+            .first::<Profile>(&mut conn)
+            .map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(profile))
+}
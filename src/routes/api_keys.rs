@@ -0,0 +1,153 @@
+use actix_web::{delete, get, patch, post};
+
+use crate::auth::scopes::{format_scopes, parse_scopes};
+use crate::auth::token::generate_token;
+use crate::auth::{AuthenticatedUser, Credential};
+use crate::errors::ServeReplicaError;
+use crate::models::access_token::hash_token;
+use crate::models::api_key::{
+    ApiKey, ApiKeyCreateRequest, ApiKeyCreateResponse, ApiKeyRenameRequest, NewApiKey,
+    API_KEY_PREFIX,
+};
+use crate::DbPool;
+
+/// characters of a key kept in the clear, prefix included
+const DISPLAY_PREFIX_LEN: usize = 10;
+
+/// Create a personal API key for server-to-server use; the key is only ever shown in this response.
+///
+/// Only the user's own token will do: a key, or a client's token, could otherwise mint keys
+/// outliving itself.
+#[utoipa::path(
+    request_body = ApiKeyCreateRequest,
+    responses(
+        (status = 201, description = "Created", body = ApiKeyCreateResponse),
+        (status = 400, description = "Invalid name, scope or expiry"),
+        (status = 403, description = "Called with an API key or a client's token")
+    )
+)]
+#[post("/api_keys")]
+pub async fn create(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    form: actix_web::web::Json<ApiKeyCreateRequest>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let form = form.into_inner();
+    if user.credential != Credential::UserToken {
+        return Err(ServeReplicaError::Forbidden(String::from(
+            "API keys can only be created with a user's own token",
+        )));
+    }
+    if form.name.trim().is_empty() {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "name must not be empty",
+        )));
+    }
+    let scopes = parse_scopes(&form.scope)?;
+    if scopes.is_empty() || !scopes.iter().all(|scope| user.has_scope(*scope)) {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "invalid_scope: must be non-empty and within the scopes of the caller",
+        )));
+    }
+
+    let key = format!("{}{}", API_KEY_PREFIX, generate_token());
+    let prefix = key[..DISPLAY_PREFIX_LEN].to_owned();
+    let key_hash = hash_token(&key);
+    let api_key = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        let now = crate::models::db_now(&mut conn)?;
+        if form.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(ServeReplicaError::BadRequest(String::from(
+                "expires_at must be in the future",
+            )));
+        }
+        ApiKey::insert(
+            &mut conn,
+            &NewApiKey {
+                username: &user.username,
+                name: form.name.trim(),
+                prefix: &prefix,
+                key_hash: &key_hash,
+                scopes: &format_scopes(&scopes),
+                expires_at: form.expires_at,
+            },
+        )
+        .map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::HttpResponse::Created().json(ApiKeyCreateResponse { key, api_key }))
+}
+
+/// List the caller's API keys, revoked ones included
+#[utoipa::path(responses((status = 200, description = "API keys", body = Vec<ApiKey>)))]
+#[get("/api_keys")]
+pub async fn read_many(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<actix_web::web::Json<Vec<ApiKey>>, ServeReplicaError> {
+    let api_keys = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        ApiKey::list(&mut conn, &user.username).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(api_keys))
+}
+
+/// Rename one of the caller's API keys
+#[utoipa::path(
+    params(("id" = i32, Path, description = "ID of API key")),
+    request_body = ApiKeyRenameRequest,
+    responses(
+        (status = 200, description = "Renamed", body = ApiKey),
+        (status = 404, description = "No such API key for this user")
+    )
+)]
+#[patch("/api_keys/{id}")]
+pub async fn rename(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    id: actix_web::web::Path<i32>,
+    form: actix_web::web::Json<ApiKeyRenameRequest>,
+) -> Result<actix_web::web::Json<ApiKey>, ServeReplicaError> {
+    if form.name.trim().is_empty() {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "name must not be empty",
+        )));
+    }
+    let api_key = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        ApiKey::rename(&mut conn, &user.username, id.into_inner(), form.name.trim())
+            .map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(api_key))
+}
+
+/// Revoke one of the caller's API keys; it stops working immediately
+#[utoipa::path(
+    params(("id" = i32, Path, description = "ID of API key")),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 404, description = "No such unrevoked API key for this user")
+    )
+)]
+#[delete("/api_keys/{id}")]
+pub async fn revoke(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    id: actix_web::web::Path<i32>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let id = id.into_inner();
+    let revoked = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        ApiKey::revoke(&mut conn, &user.username, id).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    match revoked {
+        0 => Err(ServeReplicaError::NotFound(format!(
+            "API key {} not found",
+            id
+        ))),
+        _ => Ok(actix_web::HttpResponse::NoContent().finish()),
+    }
+}
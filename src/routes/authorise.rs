@@ -0,0 +1,152 @@
+use actix_web::{get, post};
+
+use crate::auth::authorise::{
+    check_client, code_location, requested_scopes, sign_in_page, AuthoriseForm, AuthoriseQuery,
+    CODE_TTL,
+};
+use crate::auth::scopes::format_scopes;
+use crate::auth::throttle::is_login_failure;
+use crate::auth::token::{generate_token, verify_login, INVALID_CREDENTIALS};
+use crate::errors::ServeReplicaError;
+use crate::models::access_token::hash_token;
+use crate::models::authorization_code::{AuthorizationCode, NewAuthorizationCode};
+use crate::models::confidential_client::ConfidentialClient;
+use crate::DbPool;
+
+/// What `/api/authorise` answers with, worked out off the async runtime
+enum Outcome {
+    Page(actix_web::http::StatusCode, String),
+    Redirect(String),
+}
+
+impl Outcome {
+    fn into_response(self) -> actix_web::HttpResponse {
+        match self {
+            Outcome::Page(status, page) => actix_web::HttpResponse::build(status)
+                .content_type(actix_web::http::header::ContentType::html())
+                .insert_header((actix_web::http::header::CACHE_CONTROL, "no-store"))
+                // the page takes passwords, so no one else may frame it
+                .insert_header((actix_web::http::header::X_FRAME_OPTIONS, "DENY"))
+                .insert_header((
+                    actix_web::http::header::CONTENT_SECURITY_POLICY,
+                    "default-src 'none'; form-action 'self'; frame-ancestors 'none'",
+                ))
+                .body(page),
+            Outcome::Redirect(location) => actix_web::HttpResponse::SeeOther()
+                .insert_header((actix_web::http::header::LOCATION, location))
+                .finish(),
+        }
+    }
+}
+
+/// Start the authorization code flow: a sign-in page listing the scopes the client asks for.
+///
+/// Requests from unknown clients, or for unregistered redirect URIs, are refused here; other
+/// bad requests are sent back to the client's redirect URI with an `error`.
+#[utoipa::path(
+    params(AuthoriseQuery),
+    responses(
+        (status = 200, description = "Sign-in page", content_type = "text/html"),
+        (status = 303, description = "Request refused; error sent to the redirect URI"),
+        (status = 400, description = "Unknown client, or unregistered redirect URI")
+    )
+)]
+#[get("/authorise")]
+pub async fn authorise(
+    pool: actix_web::web::Data<DbPool>,
+    query: actix_web::web::Query<AuthoriseQuery>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let query = query.into_inner();
+    let outcome = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        let client = check_client(
+            ConfidentialClient::find(&mut conn, &query.client_id)?,
+            &query,
+        )?;
+        Ok::<_, ServeReplicaError>(match requested_scopes(&client, &query) {
+            Ok(scopes) => Outcome::Page(
+                actix_web::http::StatusCode::OK,
+                sign_in_page(&client, &query, &scopes, None),
+            ),
+            Err(err) => Outcome::Redirect(code_location(&query, Err(&err))),
+        })
+    })
+    .await??;
+    Ok(outcome.into_response())
+}
+
+/// Sign in from the page of [`authorise`], approving the scopes it listed; the user is sent
+/// to the client's redirect URI with a single-use code for them.
+///
+/// Only users who registered through `/api/register` can sign in here.
+#[utoipa::path(
+    request_body(content = AuthoriseForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "Approved, or refused; code or error sent to the redirect URI"),
+        (status = 400, description = "Unknown client, unregistered redirect URI, or the user cannot sign in yet"),
+        (status = 401, description = "Invalid credentials; the sign-in page again")
+    )
+)]
+#[post("/authorise")]
+pub async fn approve(
+    pool: actix_web::web::Data<DbPool>,
+    form: actix_web::web::Form<AuthoriseForm>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let form = form.into_inner();
+    let outcome = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        let query = &form.query;
+        let client = check_client(
+            ConfidentialClient::find(&mut conn, &query.client_id)?,
+            query,
+        )?;
+        let scopes = match requested_scopes(&client, query) {
+            Ok(scopes) => scopes,
+            Err(err) => return Ok(Outcome::Redirect(code_location(query, Err(&err)))),
+        };
+        let refused = match verify_login(
+            &mut conn,
+            &form.username,
+            &form.password,
+            form.otp.as_deref(),
+        ) {
+            Ok(true) => None,
+            // no different from a wrong password, so as not to reveal who has an account
+            Ok(false) => Some(ServeReplicaError::BadRequest(String::from(
+                INVALID_CREDENTIALS,
+            ))),
+            Err(
+                err @ (ServeReplicaError::BadRequest(_)
+                | ServeReplicaError::Unauthorised(_)
+                | ServeReplicaError::Forbidden(_)),
+            ) => Some(err),
+            Err(err) => return Err(err),
+        };
+        if let Some(err) = refused {
+            let status = if is_login_failure(&err) {
+                actix_web::http::StatusCode::UNAUTHORIZED
+            } else {
+                actix_web::http::StatusCode::BAD_REQUEST
+            };
+            let page = sign_in_page(&client, query, &scopes, Some(&err.to_string()));
+            return Ok(Outcome::Page(status, page));
+        }
+        let code = generate_token();
+        AuthorizationCode::insert(
+            &mut conn,
+            &NewAuthorizationCode {
+                code_hash: &hash_token(&code),
+                client_id: &client.client_id,
+                username: &form.username,
+                redirect_uri: &query.redirect_uri,
+                scopes: &format_scopes(&scopes),
+                code_challenge: query.code_challenge.as_deref().unwrap_or_default(),
+                nonce: query.nonce.as_deref(),
+            },
+            CODE_TTL,
+        )?;
+        Ok::<_, ServeReplicaError>(Outcome::Redirect(code_location(query, Ok(&code))))
+    })
+    .await??;
+    Ok(outcome.into_response())
+}
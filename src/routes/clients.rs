@@ -0,0 +1,156 @@
+use actix_web::{delete, get, post};
+
+use crate::auth::authorise::validate_redirect_uri;
+use crate::auth::scopes::{format_scopes, parse_scopes};
+use crate::auth::token::generate_token;
+use crate::auth::AuthenticatedUser;
+use crate::errors::ServeReplicaError;
+use crate::models::access_token::{hash_token, AccessToken};
+use crate::models::confidential_client::{
+    ClientRegistrationRequest, ClientSecretResponse, ConfidentialClient, NewConfidentialClient,
+};
+use crate::DbPool;
+
+/// Register a confidential client for the `client_credentials` grant, acting as the caller,
+/// and, given `redirect_uris`, for the authorization code flow. The secret is only ever shown
+/// in this response.
+#[utoipa::path(
+    request_body = ClientRegistrationRequest,
+    responses(
+        (status = 201, description = "Registered", body = ClientSecretResponse),
+        (status = 400, description = "Invalid name, scope or redirect URI")
+    )
+)]
+#[post("/clients")]
+pub async fn register(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    form: actix_web::web::Json<ClientRegistrationRequest>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let form = form.into_inner();
+    if form.name.trim().is_empty() {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "name must not be empty",
+        )));
+    }
+    let scopes = parse_scopes(&form.scope)?;
+    if scopes.is_empty() || !scopes.iter().all(|scope| user.has_scope(*scope)) {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "invalid_scope: must be non-empty and within the scopes of the caller",
+        )));
+    }
+
+    let redirect_uris = form.redirect_uris.split_whitespace().collect::<Vec<&str>>();
+    for redirect_uri in &redirect_uris {
+        validate_redirect_uri(redirect_uri)?;
+    }
+    let redirect_uris = redirect_uris.join(" ");
+
+    let client_id = generate_token();
+    let client_secret = generate_token();
+    let secret_hash = hash_token(&client_secret);
+    let client = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        ConfidentialClient::insert(
+            &mut conn,
+            &NewConfidentialClient {
+                client_id: &client_id,
+                secret_hash: &secret_hash,
+                name: form.name.trim(),
+                owner: &user.username,
+                scopes: &format_scopes(&scopes),
+                redirect_uris: &redirect_uris,
+            },
+        )
+        .map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(
+        actix_web::HttpResponse::Created().json(ClientSecretResponse {
+            client_secret,
+            client,
+        }),
+    )
+}
+
+/// List the confidential clients the caller registered, revoked ones included
+#[utoipa::path(
+    responses((status = 200, description = "Registered clients", body = Vec<ConfidentialClient>))
+)]
+#[get("/clients")]
+pub async fn read_many(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<actix_web::web::Json<Vec<ConfidentialClient>>, ServeReplicaError> {
+    let clients = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        ConfidentialClient::list(&mut conn, &user.username).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(clients))
+}
+
+/// Issue a new secret for one of the caller's clients; the old secret stops working at once,
+/// tokens already issued stay valid until they expire
+#[utoipa::path(
+    params(("client_id" = String, Path, description = "Client ID")),
+    responses(
+        (status = 200, description = "Rotated", body = ClientSecretResponse),
+        (status = 404, description = "No such unrevoked client for this user")
+    )
+)]
+#[post("/clients/{client_id}/secret")]
+pub async fn rotate_secret(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    client_id: actix_web::web::Path<String>,
+) -> Result<actix_web::web::Json<ClientSecretResponse>, ServeReplicaError> {
+    let client_secret = generate_token();
+    let secret_hash = hash_token(&client_secret);
+    let client = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        ConfidentialClient::rotate_secret(&mut conn, &user.username, &client_id, &secret_hash)
+            .map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(ClientSecretResponse {
+        client_secret,
+        client,
+    }))
+}
+
+/// Revoke one of the caller's clients along with every token issued to it
+#[utoipa::path(
+    params(("client_id" = String, Path, description = "Client ID")),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 404, description = "No such unrevoked client for this user")
+    )
+)]
+#[delete("/clients/{client_id}")]
+pub async fn revoke(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    client_id: actix_web::web::Path<String>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let client_id = client_id.into_inner();
+    let not_found = format!("client {} not found", client_id);
+    let revoked = actix_web::web::block(move || {
+        use diesel::Connection;
+
+        let mut conn = pool.get()?;
+        conn.transaction(|conn| {
+            let revoked = ConfidentialClient::revoke(conn, &user.username, &client_id)?;
+            if revoked > 0 {
+                AccessToken::revoke_family(conn, &ConfidentialClient::token_family(&client_id))?;
+            }
+            Ok(revoked)
+        })
+        .map_err(ServeReplicaError::from)
+    })
+    .await??;
+    match revoked {
+        0 => Err(ServeReplicaError::NotFound(not_found)),
+        _ => Ok(actix_web::HttpResponse::NoContent().finish()),
+    }
+}
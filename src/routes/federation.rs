@@ -0,0 +1,192 @@
+use actix_web::{get, HttpMessage};
+
+use crate::audit::AuditActor;
+use crate::auth::account::AccountConfig;
+use crate::auth::federation::{link_or_create, FederatedProvider, STATE_COOKIE};
+use crate::auth::oidc::OidcProvider;
+use crate::auth::scopes::Scope;
+use crate::auth::token::{
+    ensure_enabled, generate_token, issue_user_token, TokenConfig, TokenResponse,
+};
+use crate::errors::ServeReplicaError;
+use crate::models::access_token::{hash_token, AccessToken, TokenClient};
+use crate::models::federated_login::{FederatedLogin, NewFederatedLogin};
+use crate::DbPool;
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, utoipa::IntoParams)]
+pub struct LoginQuery {
+    /// frontend URL, under `PUBLIC_URL`, to send the token to in its fragment; without one the
+    /// callback answers with the token as JSON
+    pub return_to: Option<String>,
+}
+
+/// What the provider redirects back with
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, utoipa::IntoParams)]
+pub struct CallbackQuery {
+    pub state: String,
+    pub code: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+fn configured(
+    provider: Option<actix_web::web::Data<FederatedProvider>>,
+) -> Result<actix_web::web::Data<FederatedProvider>, ServeReplicaError> {
+    provider.ok_or_else(|| {
+        ServeReplicaError::NotFound(String::from("federated login is not configured"))
+    })
+}
+
+/// Whether `url` is `public_url` or under it, so the callback cannot redirect tokens elsewhere
+fn is_under(url: &str, public_url: &str) -> bool {
+    let public_url = public_url.trim_end_matches('/');
+    url.strip_prefix(public_url)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?', '#']))
+}
+
+/// Start signing in with the external OpenID Connect provider, by redirecting to it
+#[utoipa::path(
+    params(LoginQuery),
+    responses(
+        (status = 302, description = "Redirect to the provider"),
+        (status = 400, description = "`return_to` is not under `PUBLIC_URL`"),
+        (status = 404, description = "No provider configured")
+    )
+)]
+#[get("/federated/login")]
+pub async fn login(
+    pool: actix_web::web::Data<DbPool>,
+    provider: Option<actix_web::web::Data<FederatedProvider>>,
+    account_config: actix_web::web::Data<AccountConfig>,
+    query: actix_web::web::Query<LoginQuery>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let provider = configured(provider)?;
+    let LoginQuery { return_to } = query.into_inner();
+    if let Some(return_to) = &return_to {
+        if !is_under(return_to, &account_config.public_url) {
+            return Err(ServeReplicaError::BadRequest(String::from(
+                "return_to must be under PUBLIC_URL",
+            )));
+        }
+    }
+    let (state, nonce, code_verifier) = (generate_token(), generate_token(), generate_token());
+    let location = provider
+        .authorization_url(&state, &nonce, &code_verifier)
+        .await?;
+    let cookie = provider.config.state_cookie(&state);
+    let login_ttl = provider.config.login_ttl;
+    actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        FederatedLogin::insert(
+            &mut conn,
+            &NewFederatedLogin {
+                state_hash: &hash_token(&state),
+                nonce: &nonce,
+                code_verifier: &code_verifier,
+                return_to: return_to.as_deref(),
+            },
+            login_ttl,
+        )
+        .map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::HttpResponse::Found()
+        .insert_header((actix_web::http::header::LOCATION, location))
+        .cookie(cookie)
+        .finish())
+}
+
+/// Where the provider sends the user back, in the browser [`login`] sent there. Links the
+/// provider's account to a local user, creating one if need be, and issues a normal bearer
+/// token for them.
+#[utoipa::path(
+    params(CallbackQuery),
+    responses(
+        (status = 200, description = "Signed in", body = TokenResponse),
+        (status = 302, description = "Signed in; redirect to `return_to` with the token in its fragment"),
+        (status = 400, description = "Unknown or expired `state`, or not this browser's; the provider refused, or the account is disabled"),
+        (status = 401, description = "The provider's ID token did not verify")
+    )
+)]
+#[get("/federated/callback")]
+pub async fn callback(
+    pool: actix_web::web::Data<DbPool>,
+    provider: Option<actix_web::web::Data<FederatedProvider>>,
+    token_config: actix_web::web::Data<TokenConfig>,
+    oidc: Option<actix_web::web::Data<OidcProvider>>,
+    req: actix_web::HttpRequest,
+    query: actix_web::web::Query<CallbackQuery>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let provider = configured(provider)?;
+    let query = query.into_inner();
+    if !req
+        .cookie(STATE_COOKIE)
+        .is_some_and(|cookie| cookie.value() == query.state)
+    {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "login state does not belong to this browser",
+        )));
+    }
+    let mut spent_cookie = provider.config.state_cookie("");
+    spent_cookie.make_removal();
+    let login = {
+        let pool = pool.clone();
+        actix_web::web::block(move || {
+            let mut conn = pool.get()?;
+            FederatedLogin::consume(&mut conn, &query.state).map_err(ServeReplicaError::from)
+        })
+        .await??
+        .ok_or_else(|| {
+            ServeReplicaError::BadRequest(String::from("unknown or expired login state"))
+        })?
+    };
+    if let Some(error) = query.error {
+        return Err(ServeReplicaError::BadRequest(format!(
+            "{}: {}",
+            error,
+            query.error_description.unwrap_or_default()
+        )));
+    }
+    let code = query
+        .code
+        .ok_or_else(|| ServeReplicaError::BadRequest(String::from("callback has no code")))?;
+    let claims = provider
+        .exchange(&code, &login.code_verifier, &login.nonce)
+        .await?;
+
+    let client = TokenClient::of(&req);
+    let (username, token) = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        let username = link_or_create(&mut conn, &claims.iss, &claims)?;
+        ensure_enabled(&mut conn, &username)?;
+        let token = issue_user_token(
+            &mut conn,
+            &token_config,
+            oidc.as_deref(),
+            &username,
+            &Scope::user_defaults(),
+            None,
+            None,
+        )?;
+        AccessToken::note_client(&mut conn, &token.access_token, &client)?;
+        Ok::<_, ServeReplicaError>((username, token))
+    })
+    .await??;
+    req.extensions_mut().insert(AuditActor(username));
+    match login.return_to {
+        Some(return_to) => {
+            let fragment = serde_urlencoded::to_string(&token)
+                .map_err(|err| ServeReplicaError::Database(err.to_string()))?;
+            Ok(actix_web::HttpResponse::Found()
+                .insert_header((
+                    actix_web::http::header::LOCATION,
+                    format!("{}#{}", return_to, fragment),
+                ))
+                .cookie(spent_cookie)
+                .finish())
+        }
+        None => Ok(actix_web::HttpResponse::Ok()
+            .cookie(spent_cookie)
+            .json(token)),
+    }
+}
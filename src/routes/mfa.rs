@@ -0,0 +1,100 @@
+use actix_web::post;
+
+use crate::auth::mfa::{generate_recovery_codes, generate_secret, totp, verify_code};
+use crate::auth::AuthenticatedUser;
+use crate::errors::ServeReplicaError;
+use crate::models::totp_credential::{
+    RecoveryCodes, TotpCodeRequest, TotpCredential, TotpEnrolment,
+};
+use crate::DbPool;
+
+/// Start enrolling in TOTP two-factor authentication. It takes effect once confirmed with a code.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Secret to add to an authenticator app", body = TotpEnrolment),
+        (status = 400, description = "TOTP already enabled")
+    )
+)]
+#[post("/mfa/totp")]
+pub async fn enrol(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<actix_web::web::Json<TotpEnrolment>, ServeReplicaError> {
+    let enrolment = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        let secret = generate_secret();
+        TotpCredential::enrol(&mut conn, &user.username, &secret)?.ok_or_else(|| {
+            ServeReplicaError::BadRequest(String::from("TOTP already enabled; disable it first"))
+        })?;
+        let totp = totp(&secret, &user.username)?;
+        Ok::<_, ServeReplicaError>(TotpEnrolment {
+            secret: totp.get_secret_base32(),
+            otpauth_url: totp.get_url(),
+        })
+    })
+    .await??;
+    Ok(actix_web::web::Json(enrolment))
+}
+
+/// Confirm TOTP enrolment with a current code, enabling it. Returns recovery codes, each
+/// usable once in place of a code; they are not shown again.
+#[utoipa::path(
+    request_body = TotpCodeRequest,
+    responses(
+        (status = 200, description = "Enabled", body = RecoveryCodes),
+        (status = 400, description = "Invalid code, or no enrolment to confirm")
+    )
+)]
+#[post("/mfa/totp/confirm")]
+pub async fn confirm(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    form: actix_web::web::Json<TotpCodeRequest>,
+) -> Result<actix_web::web::Json<RecoveryCodes>, ServeReplicaError> {
+    let recovery_codes = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        let credential = TotpCredential::find(&mut conn, &user.username)?
+            .filter(|credential| credential.confirmed_at.is_none())
+            .ok_or_else(|| {
+                ServeReplicaError::BadRequest(String::from("no TOTP enrolment to confirm"))
+            })?;
+        if !verify_code(&mut conn, &credential, &form.code, false)? {
+            return Err(ServeReplicaError::BadRequest(String::from("invalid code")));
+        }
+        let recovery_codes = generate_recovery_codes();
+        TotpCredential::confirm(&mut conn, &user.username, &recovery_codes)?;
+        Ok(recovery_codes)
+    })
+    .await??;
+    Ok(actix_web::web::Json(RecoveryCodes { recovery_codes }))
+}
+
+/// Disable TOTP, given a current code or a recovery code
+#[utoipa::path(
+    request_body = TotpCodeRequest,
+    responses(
+        (status = 204, description = "Disabled"),
+        (status = 400, description = "Invalid code"),
+        (status = 404, description = "TOTP is not enabled")
+    )
+)]
+#[post("/mfa/totp/disable")]
+pub async fn disable(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    form: actix_web::web::Json<TotpCodeRequest>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        let credential = TotpCredential::find(&mut conn, &user.username)?
+            .filter(|credential| credential.confirmed_at.is_some())
+            .ok_or_else(|| ServeReplicaError::NotFound(String::from("TOTP is not enabled")))?;
+        if !verify_code(&mut conn, &credential, &form.code, true)? {
+            return Err(ServeReplicaError::BadRequest(String::from("invalid code")));
+        }
+        TotpCredential::delete(&mut conn, &user.username)?;
+        Ok(())
+    })
+    .await??;
+    Ok(actix_web::HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,13 @@
+pub(crate) mod account;
+pub(crate) mod admin;
+pub(crate) mod api_keys;
+pub(crate) mod authorise;
+pub(crate) mod clients;
+pub(crate) mod federation;
+pub(crate) mod mfa;
+pub(crate) mod oidc;
+pub(crate) mod scope;
+pub(crate) mod sessions;
+pub(crate) mod swap;
+pub(crate) mod swaps;
+pub(crate) mod token;
@@ -0,0 +1,70 @@
+use actix_web::get;
+use diesel::prelude::*;
+
+use crate::auth::oidc::{JwkSet, OidcProvider, ProviderMetadata, UserInfo};
+use crate::auth::AuthenticatedUser;
+use crate::errors::ServeReplicaError;
+use crate::models::user_credential::UserCredential;
+use crate::DbPool;
+
+/// OpenID Connect Discovery: endpoints, scopes and algorithms serve-replica supports
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Provider metadata", body = ProviderMetadata)
+    )
+)]
+#[get("/openid-configuration")]
+pub async fn discovery(
+    oidc: actix_web::web::Data<OidcProvider>,
+) -> actix_web::web::Json<ProviderMetadata> {
+    actix_web::web::Json(oidc.metadata())
+}
+
+/// Public keys ID tokens are signed with
+#[utoipa::path(
+    responses(
+        (status = 200, description = "JSON Web Key Set", body = JwkSet)
+    )
+)]
+#[get("/jwks.json")]
+pub async fn jwks(oidc: actix_web::web::Data<OidcProvider>) -> actix_web::web::Json<JwkSet> {
+    actix_web::web::Json(JwkSet {
+        keys: vec![oidc.key.jwk()],
+    })
+}
+
+/// Claims about the bearer token's user, who must have granted the `openid` scope
+#[utoipa::path(
+    responses(
+        (status = 200, description = "User info", body = UserInfo),
+        (status = 403, description = "Token lacks the openid scope")
+    )
+)]
+#[get("")]
+pub async fn userinfo(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+) -> Result<actix_web::web::Json<UserInfo>, ServeReplicaError> {
+    use replica_backend::models::profile::Profile;
+    use replica_backend::schema::profile;
+
+    let userinfo = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        let credential = UserCredential::find(&mut conn, &user.username)?;
+        let replica_profile = profile::table
+            .filter(profile::username.eq(&user.username))
+            .first::<Profile>(&mut conn)
+            .optional()?;
+        Ok::<_, ServeReplicaError>(UserInfo {
+            sub: user.username.clone(),
+            preferred_username: user.username,
+            email_verified: credential
+                .as_ref()
+                .map(|credential| credential.email_verified_at.is_some()),
+            email: credential.map(|credential| credential.email),
+            replica_profile,
+        })
+    })
+    .await??;
+    Ok(actix_web::web::Json(userinfo))
+}
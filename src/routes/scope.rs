@@ -0,0 +1,30 @@
+use actix_web::get;
+
+use crate::auth::scopes::Scope;
+use crate::auth::AuthenticatedUser;
+use crate::errors::ServeReplicaError;
+
+/// Check the bearer token has a scope, e.g., for nginx's `auth_request` in front of `/v1/crawl`
+#[utoipa::path(
+    params(("scope" = String, Path, description = "Scope to check, e.g., \"crawl\"")),
+    responses(
+        (status = 204, description = "Token has the scope"),
+        (status = 400, description = "Unknown scope"),
+        (status = 403, description = "Token lacks the scope")
+    )
+)]
+#[get("/scope/{scope}")]
+pub async fn check(
+    user: AuthenticatedUser,
+    scope: actix_web::web::Path<String>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let scope: Scope = scope.parse()?;
+    if user.has_scope(scope) {
+        Ok(actix_web::HttpResponse::NoContent().finish())
+    } else {
+        Err(ServeReplicaError::Forbidden(format!(
+            "insufficient_scope: requires {}",
+            scope
+        )))
+    }
+}
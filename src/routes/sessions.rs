@@ -0,0 +1,73 @@
+use actix_web::{delete, get};
+
+use crate::auth::sessions::{self, Session};
+use crate::auth::AuthenticatedUser;
+use crate::errors::ServeReplicaError;
+use crate::models::access_token::AccessToken;
+use crate::DbPool;
+
+/// List the caller's active sessions, i.e., the clients they are signed in on
+#[utoipa::path(responses((status = 200, description = "Sessions", body = Vec<Session>)))]
+#[get("/sessions")]
+pub async fn read_many(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    credentials: actix_web_httpauth::extractors::bearer::BearerAuth,
+) -> Result<actix_web::web::Json<Vec<Session>>, ServeReplicaError> {
+    let sessions = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        // `None` when called with, e.g., an API key
+        let current = AccessToken::find(&mut conn, credentials.token())?;
+        sessions::list(&mut conn, &user.username, current.as_ref()).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(sessions))
+}
+
+/// Sign one of the caller's sessions out; its access and refresh tokens stop working immediately
+#[utoipa::path(
+    params(("id" = i32, Path, description = "ID of session")),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 404, description = "No such active session for this user")
+    )
+)]
+#[delete("/sessions/{id}")]
+pub async fn revoke(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    id: actix_web::web::Path<i32>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let id = id.into_inner();
+    let revoked = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        sessions::revoke(&mut conn, &user.username, id).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    if revoked {
+        Ok(actix_web::HttpResponse::NoContent().finish())
+    } else {
+        Err(ServeReplicaError::NotFound(format!(
+            "session {} not found",
+            id
+        )))
+    }
+}
+
+/// Sign the caller out everywhere but the session making this request
+#[utoipa::path(responses((status = 204, description = "Revoked")))]
+#[delete("/sessions")]
+pub async fn revoke_others(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    credentials: actix_web_httpauth::extractors::bearer::BearerAuth,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        let current = AccessToken::find(&mut conn, credentials.token())?;
+        sessions::revoke_others(&mut conn, &user.username, current.as_ref())
+            .map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::HttpResponse::NoContent().finish())
+}
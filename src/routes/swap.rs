@@ -0,0 +1,188 @@
+use actix_web::{get, post};
+
+use crate::auth::AuthenticatedUser;
+use crate::errors::ServeReplicaError;
+use crate::extra_schemas::{
+    SwapBatchPostRequest, SwapBatchPostResponse, SwapPostRequest, SwapPostResponse,
+};
+use crate::models::swap_batch::{aggregate_status, SwapBatch};
+use crate::models::swap_job::{NewSwapJob, SwapJob};
+use crate::swap::preprocess::{check_url, Preprocessor};
+use crate::swap::worker::WorkerConfig;
+use crate::swap::ModelSource;
+use crate::DbPool;
+
+/// POST to faceswap, against a stored `PersonModel` or a raw model image URL
+///
+/// The swap is queued and run by a worker; poll `/v1/swap/{id}` for its output.
+#[utoipa::path(
+    request_body(
+        content = SwapPostRequest,
+        description = "Body to faceswap",
+        example = json!({"user_img_url": "data:image/jpg;base64,fa", "model_id": 5, "model_img_idx": 0})
+    ),
+    responses(
+        (status = 202, description = "Faceswap queued", body = SwapPostResponse),
+        (status = 400, description = "Neither or both of `model_id` and `model_img_url` given, or an image URL that cannot be fetched"),
+        (status = 403, description = "`PersonModel` belongs to someone else"),
+        (status = 404, description = "`PersonModel` not found"),
+        (status = 503, description = "Queue is full; retry after the `Retry-After` header's seconds")
+    )
+)]
+#[post("/swap")]
+pub async fn swap(
+    pool: actix_web::web::Data<DbPool>,
+    worker_config: actix_web::web::Data<WorkerConfig>,
+    user: AuthenticatedUser,
+    body: actix_web::web::Json<SwapPostRequest>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let source = ModelSource::try_from(&body.0)?;
+    check_url(&body.user_img_url).await?;
+    if let ModelSource::Url(url) = &source {
+        check_url(url).await?;
+    }
+    let capacity = worker_config.queue_capacity;
+    let job = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        let (model_id, model_img_url) = source.resolve(&mut conn, &user.username)?;
+        SwapJob::enqueue(
+            &mut conn,
+            &NewSwapJob {
+                username: &user.username,
+                model_id,
+                user_img_url: Some(&body.user_img_url),
+                model_img_url: &model_img_url,
+                batch_id: None,
+            },
+            capacity,
+        )
+        .map_err(ServeReplicaError::from)
+    })
+    .await??
+    .ok_or_else(|| {
+        ServeReplicaError::ServiceUnavailable(
+            String::from("swap queue is full"),
+            worker_config.retry_after_secs(),
+        )
+    })?;
+    Ok(actix_web::HttpResponse::Accepted().json(SwapPostResponse::from(&job)))
+}
+
+/// GET the status, and once completed the output, of a queued faceswap
+#[utoipa::path(
+    params(("id" = i32, Path, description = "ID of swap job")),
+    responses(
+        (status = 200, description = "Faceswap status", body = SwapPostResponse),
+        (status = 404, description = "No such swap job for this user")
+    )
+)]
+#[get("/swap/{id}")]
+pub async fn read(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    id: actix_web::web::Path<i32>,
+) -> Result<actix_web::web::Json<SwapPostResponse>, ServeReplicaError> {
+    let job = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        SwapJob::read(&mut conn, &user.username, id.into_inner()).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(SwapPostResponse::from(&job)))
+}
+
+/// Most models a single batch swap may fan out to
+pub const MAX_BATCH_SIZE: usize = 50;
+
+fn batch_response(batch: &SwapBatch, jobs: &[SwapJob]) -> SwapBatchPostResponse {
+    SwapBatchPostResponse {
+        id: batch.id,
+        status: String::from(aggregate_status(jobs.iter().map(|job| job.status.as_str()))),
+        children: jobs.iter().map(SwapPostResponse::from).collect(),
+    }
+}
+
+/// POST to faceswap one user image against many models, as child jobs of one batch
+#[utoipa::path(
+    request_body(
+        content = SwapBatchPostRequest,
+        description = "User image and models to faceswap it against",
+        example = json!({
+            "user_img_url": "data:image/jpg;base64,fa",
+            "models": [{"model_id": 5}, {"model_id": 6, "model_img_idx": 1}, {"model_img_url": "https://a.com/a.jpg"}]
+        })
+    ),
+    responses(
+        (status = 202, description = "Faceswaps queued", body = SwapBatchPostResponse),
+        (status = 400, description = "No models, too many models, a malformed model, or an image that cannot be fetched or decoded"),
+        (status = 403, description = "A `PersonModel` belongs to someone else"),
+        (status = 404, description = "A `PersonModel` was not found"),
+        (status = 503, description = "Queue cannot fit the batch; retry after the `Retry-After` header's seconds")
+    )
+)]
+#[post("/swap/batch")]
+pub async fn batch(
+    pool: actix_web::web::Data<DbPool>,
+    worker_config: actix_web::web::Data<WorkerConfig>,
+    preprocessor: actix_web::web::Data<Preprocessor>,
+    user: AuthenticatedUser,
+    body: actix_web::web::Json<SwapBatchPostRequest>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    if body.models.is_empty() || body.models.len() > MAX_BATCH_SIZE {
+        return Err(ServeReplicaError::BadRequest(format!(
+            "`models` must have between 1 and {} entries",
+            MAX_BATCH_SIZE
+        )));
+    }
+    let sources = body
+        .models
+        .iter()
+        .map(ModelSource::try_from)
+        .collect::<Result<Vec<ModelSource>, ServeReplicaError>>()?;
+    // preprocessed here, once, rather than by the worker for every child
+    let user_img_url = preprocessor.prepare(&body.user_img_url).await?;
+    for source in &sources {
+        if let ModelSource::Url(url) = source {
+            check_url(url).await?;
+        }
+    }
+    let capacity = worker_config.queue_capacity;
+    let (batch, jobs) = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        let models = sources
+            .into_iter()
+            .map(|source| source.resolve(&mut conn, &user.username))
+            .collect::<Result<Vec<(Option<i32>, String)>, ServeReplicaError>>()?;
+        SwapBatch::create(&mut conn, &user.username, &user_img_url, &models, capacity)
+            .map_err(ServeReplicaError::from)
+    })
+    .await??
+    .ok_or_else(|| {
+        ServeReplicaError::ServiceUnavailable(
+            String::from("swap queue cannot fit this batch"),
+            worker_config.retry_after_secs(),
+        )
+    })?;
+    Ok(actix_web::HttpResponse::Accepted().json(batch_response(&batch, &jobs)))
+}
+
+/// GET the aggregate status, and each child's status and output, of a batch faceswap
+#[utoipa::path(
+    params(("id" = i32, Path, description = "ID of swap batch")),
+    responses(
+        (status = 200, description = "Batch faceswap status", body = SwapBatchPostResponse),
+        (status = 404, description = "No such swap batch for this user")
+    )
+)]
+#[get("/swap/batch/{id}")]
+pub async fn read_batch(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    id: actix_web::web::Path<i32>,
+) -> Result<actix_web::web::Json<SwapBatchPostResponse>, ServeReplicaError> {
+    let (batch, jobs) = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        SwapBatch::read(&mut conn, &user.username, id.into_inner()).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(batch_response(&batch, &jobs)))
+}
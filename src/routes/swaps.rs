@@ -0,0 +1,77 @@
+use actix_web::{delete, get};
+
+use crate::auth::AuthenticatedUser;
+use crate::errors::ServeReplicaError;
+use crate::models::swap_history::{SwapHistory, SwapHistoryPage, SwapHistoryQuery};
+use crate::DbPool;
+
+/// List the caller's past swaps, newest first
+#[utoipa::path(
+    params(SwapHistoryQuery),
+    responses((status = 200, description = "Page of past swaps", body = SwapHistoryPage))
+)]
+#[get("/swaps")]
+pub async fn read_many(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    query: actix_web::web::Query<SwapHistoryQuery>,
+) -> Result<actix_web::web::Json<SwapHistoryPage>, ServeReplicaError> {
+    let page = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        SwapHistory::list(&mut conn, &user.username, &query).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(page))
+}
+
+/// Get one of the caller's past swaps
+#[utoipa::path(
+    params(("id" = i32, Path, description = "ID of swap")),
+    responses(
+        (status = 200, description = "Past swap", body = SwapHistory),
+        (status = 404, description = "No such swap for this user")
+    )
+)]
+#[get("/swaps/{id}")]
+pub async fn read(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    id: actix_web::web::Path<i32>,
+) -> Result<actix_web::web::Json<SwapHistory>, ServeReplicaError> {
+    let swap = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        SwapHistory::read(&mut conn, &user.username, id.into_inner())
+            .map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::web::Json(swap))
+}
+
+/// Delete one of the caller's past swaps from their history
+#[utoipa::path(
+    params(("id" = i32, Path, description = "ID of swap")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "No such swap for this user")
+    )
+)]
+#[delete("/swaps/{id}")]
+pub async fn remove(
+    pool: actix_web::web::Data<DbPool>,
+    user: AuthenticatedUser,
+    id: actix_web::web::Path<i32>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let id = id.into_inner();
+    let deleted = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        SwapHistory::delete(&mut conn, &user.username, id).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    match deleted {
+        0 => Err(ServeReplicaError::NotFound(format!(
+            "swap {} not found",
+            id
+        ))),
+        _ => Ok(actix_web::HttpResponse::NoContent().finish()),
+    }
+}
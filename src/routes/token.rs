@@ -0,0 +1,80 @@
+use actix_web::post;
+
+use crate::auth::introspection::{
+    authenticate_client, introspect, revoke, IntrospectionResponse, TokenLookupForm,
+};
+use crate::auth::token::client_credentials;
+use crate::errors::ServeReplicaError;
+use crate::DbPool;
+
+fn authorization(req: &actix_web::HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// RFC 7662 token introspection, for API gateways and other services validating
+/// serve-replica tokens. Callers authenticate as a confidential client, with HTTP Basic or
+/// `client_id` and `client_secret`.
+#[utoipa::path(
+    request_body(content = TokenLookupForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Whether the token is active, and if so its details", body = IntrospectionResponse),
+        (status = 401, description = "Client authentication failed")
+    )
+)]
+#[post("/token/introspect")]
+pub async fn introspect_token(
+    pool: actix_web::web::Data<DbPool>,
+    req: actix_web::HttpRequest,
+    form: actix_web::web::Form<TokenLookupForm>,
+) -> Result<actix_web::web::Json<IntrospectionResponse>, ServeReplicaError> {
+    let (client_id, client_secret) = client_credentials(
+        authorization(&req),
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    )
+    .ok_or_else(|| {
+        ServeReplicaError::Unauthorised(String::from(
+            "invalid_client: client authentication is required",
+        ))
+    })?;
+    let response = actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        authenticate_client(&mut conn, &client_id, &client_secret)?;
+        introspect(&mut conn, &form.token)
+    })
+    .await??;
+    Ok(actix_web::web::Json(response))
+}
+
+/// RFC 7009 token revocation of an access token, refresh token or API key. Possession of the
+/// token suffices; client credentials, if sent, must be valid. Unknown tokens are not an error.
+#[utoipa::path(
+    request_body(content = TokenLookupForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Revoked, or was never valid"),
+        (status = 401, description = "Client authentication failed")
+    )
+)]
+#[post("/token/revoke")]
+pub async fn revoke_token(
+    pool: actix_web::web::Data<DbPool>,
+    req: actix_web::HttpRequest,
+    form: actix_web::web::Form<TokenLookupForm>,
+) -> Result<actix_web::HttpResponse, ServeReplicaError> {
+    let client = client_credentials(
+        authorization(&req),
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    );
+    actix_web::web::block(move || {
+        let mut conn = pool.get()?;
+        if let Some((client_id, client_secret)) = client {
+            authenticate_client(&mut conn, &client_id, &client_secret)?;
+        }
+        revoke(&mut conn, &form.token).map_err(ServeReplicaError::from)
+    })
+    .await??;
+    Ok(actix_web::HttpResponse::Ok().finish())
+}
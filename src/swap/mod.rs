@@ -0,0 +1,130 @@
+use crate::errors::ServeReplicaError;
+use crate::extra_schemas::{SwapBatchModel, SwapPostRequest, SwapPostResponse};
+
+pub(crate) mod model;
+pub(crate) mod preprocess;
+pub(crate) mod worker;
+
+/// Faceswap service that `/v1/swap` forwards resolved requests to
+#[derive(Debug, Clone)]
+pub struct SwapBackend {
+    /// base URL of the faceswap service, e.g., "http://localhost:3003"
+    pub url: String,
+    pub preprocessor: preprocess::Preprocessor,
+}
+
+impl SwapBackend {
+    pub fn new(url: String, preprocessor: preprocess::Preprocessor) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            preprocessor,
+        }
+    }
+
+    /// Preprocess then submit one user image / model image pair to the faceswap service
+    pub async fn submit(
+        &self,
+        user_img_url: &str,
+        model_img_url: &str,
+    ) -> Result<SwapPostResponse, ServeReplicaError> {
+        let user_img_url = self.preprocessor.prepare(user_img_url).await?;
+        self.submit_prepared(&user_img_url, model_img_url).await
+    }
+
+    /// As [`SwapBackend::submit`], for a user image that has already been preprocessed
+    pub async fn submit_prepared(
+        &self,
+        user_img_url: &str,
+        model_img_url: &str,
+    ) -> Result<SwapPostResponse, ServeReplicaError> {
+        let model_img_url = self.preprocessor.prepare(model_img_url).await?;
+        let mut response = awc::Client::default()
+            .post(format!("{}/v1/swap", self.url))
+            .send_json(&serde_json::json!({
+                "user_img_url": user_img_url,
+                "model_img_url": model_img_url
+            }))
+            .await
+            .map_err(|err| ServeReplicaError::Upstream(err.to_string()))?;
+        if !response.status().is_success() {
+            return Err(ServeReplicaError::Upstream(format!(
+                "faceswap service responded with {}",
+                response.status()
+            )));
+        }
+        response
+            .json::<SwapPostResponse>()
+            .await
+            .map_err(|err| ServeReplicaError::Upstream(err.to_string()))
+    }
+}
+
+/// Where the model image of a [`SwapPostRequest`] comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelSource {
+    /// a stored `PersonModel`, and which of its images to use
+    Stored { model_id: i32, img_idx: usize },
+    /// a raw image URL given by the caller
+    Url(String),
+}
+
+impl ModelSource {
+    pub fn new(
+        model_id: Option<i32>,
+        model_img_url: Option<&String>,
+        model_img_idx: Option<usize>,
+    ) -> Result<Self, ServeReplicaError> {
+        match (model_id, model_img_url) {
+            (Some(_), Some(_)) => Err(ServeReplicaError::BadRequest(String::from(
+                "specify only one of `model_id` and `model_img_url`",
+            ))),
+            (Some(model_id), None) => Ok(ModelSource::Stored {
+                model_id,
+                img_idx: model_img_idx.unwrap_or(0),
+            }),
+            (None, Some(url)) => Ok(ModelSource::Url(url.to_owned())),
+            (None, None) => Err(ServeReplicaError::BadRequest(String::from(
+                "one of `model_id` or `model_img_url` is required",
+            ))),
+        }
+    }
+
+    /// Resolve to `(model_id, model_img_url)`, checking a stored model belongs to `username`
+    pub fn resolve(
+        self,
+        conn: &mut diesel::PgConnection,
+        username: &str,
+    ) -> Result<(Option<i32>, String), ServeReplicaError> {
+        match self {
+            ModelSource::Url(url) => Ok((None, url)),
+            ModelSource::Stored { model_id, img_idx } => Ok((
+                Some(model_id),
+                model::resolve_model_img_url(conn, username, model_id, img_idx)?,
+            )),
+        }
+    }
+}
+
+impl TryFrom<&SwapPostRequest> for ModelSource {
+    type Error = ServeReplicaError;
+
+    fn try_from(request: &SwapPostRequest) -> Result<Self, Self::Error> {
+        ModelSource::new(
+            request.model_id,
+            request.model_img_url.as_ref(),
+            request.model_img_idx,
+        )
+    }
+}
+
+impl TryFrom<&SwapBatchModel> for ModelSource {
+    type Error = ServeReplicaError;
+
+    fn try_from(model: &SwapBatchModel) -> Result<Self, Self::Error> {
+        ModelSource::new(
+            model.model_id,
+            model.model_img_url.as_ref(),
+            model.model_img_idx,
+        )
+    }
+}
@@ -0,0 +1,32 @@
+use diesel::prelude::*;
+
+use crate::errors::ServeReplicaError;
+
+/// Resolve the image URL of a stored `PersonModel`, checking that `username` may use it
+pub fn resolve_model_img_url(
+    conn: &mut diesel::PgConnection,
+    username: &str,
+    model_id: i32,
+    img_idx: usize,
+) -> Result<String, ServeReplicaError> {
+    let person_model: replica_backend::models::person_model::PersonModel =
+        replica_backend::schema::person_model::table
+            .find(model_id)
+            .first(conn)
+            .optional()?
+            .ok_or_else(|| {
+                ServeReplicaError::NotFound(format!("PersonModel {} not found", model_id))
+            })?;
+    if person_model.username != username {
+        return Err(ServeReplicaError::Forbidden(format!(
+            "PersonModel {} does not belong to you",
+            model_id
+        )));
+    }
+    person_model.images.get(img_idx).cloned().ok_or_else(|| {
+        ServeReplicaError::BadRequest(format!(
+            "PersonModel {} has no image at index {}",
+            model_id, img_idx
+        ))
+    })
+}
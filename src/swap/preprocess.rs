@@ -0,0 +1,322 @@
+use base64::Engine;
+
+use crate::errors::ServeReplicaError;
+
+/// Upper bound on the size of a fetched or inlined swap input, before decoding
+pub const MAX_INPUT_BYTES: usize = 20 * 1024 * 1024;
+
+/// Format that every swap input is normalised to
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Jpeg,
+    Png,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = ServeReplicaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "png" => Ok(OutputFormat::Png),
+            _ => Err(ServeReplicaError::BadRequest(format!(
+                "unsupported output format {:?}, expected \"jpeg\" or \"png\"",
+                s
+            ))),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn mime(&self) -> mime::Mime {
+        match self {
+            OutputFormat::Jpeg => mime::IMAGE_JPEG,
+            OutputFormat::Png => mime::IMAGE_PNG,
+        }
+    }
+}
+
+/// Normalises swap inputs before they reach the faceswap service.
+///
+/// EXIF orientation is applied to the pixels, then the image is re-encoded without any
+/// metadata (so GPS and camera details are dropped), downscaled to fit `max_dimension`,
+/// and converted to `format`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preprocessor {
+    /// longest edge, in pixels, an image is downscaled to
+    pub max_dimension: u32,
+    pub format: OutputFormat,
+    /// JPEG quality, 1-100; unused for PNG
+    pub jpeg_quality: u8,
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self {
+            max_dimension: 2048,
+            format: OutputFormat::default(),
+            jpeg_quality: 90,
+        }
+    }
+}
+
+impl Preprocessor {
+    /// Fetch (or decode a `data:` URL), normalise, and return as a `data:` URL
+    pub async fn prepare(&self, url: &str) -> Result<String, ServeReplicaError> {
+        let bytes = load(url).await?;
+        let preprocessor = self.clone();
+        let normalised = actix_web::web::block(move || preprocessor.normalise(&bytes)).await??;
+        Ok(format!(
+            "data:{};base64,{}",
+            self.format.mime(),
+            base64::engine::general_purpose::STANDARD.encode(normalised)
+        ))
+    }
+
+    /// Decode `bytes`, apply orientation, downscale, and re-encode without metadata
+    pub fn normalise(&self, bytes: &[u8]) -> Result<Vec<u8>, ServeReplicaError> {
+        let img = decode(bytes)?;
+        let img = if img.width() > self.max_dimension || img.height() > self.max_dimension {
+            img.resize(
+                self.max_dimension,
+                self.max_dimension,
+                image::imageops::FilterType::Lanczos3,
+            )
+        } else {
+            img
+        };
+
+        let mut out = std::io::Cursor::new(Vec::new());
+        match self.format {
+            OutputFormat::Jpeg => image::DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(
+                image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, self.jpeg_quality),
+            ),
+            OutputFormat::Png => {
+                img.write_with_encoder(image::codecs::png::PngEncoder::new(&mut out))
+            }
+        }
+        .map_err(|err| ServeReplicaError::BadRequest(err.to_string()))?;
+        Ok(out.into_inner())
+    }
+}
+
+/// Decode any supported input format into pixels with EXIF orientation already applied
+fn decode(bytes: &[u8]) -> Result<image::DynamicImage, ServeReplicaError> {
+    use image::ImageDecoder;
+
+    if is_heif(bytes) {
+        return decode_heif(bytes);
+    }
+    let mut decoder = image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|err| ServeReplicaError::BadRequest(err.to_string()))?
+        .into_decoder()
+        .map_err(|err| ServeReplicaError::BadRequest(err.to_string()))?;
+    let orientation = decoder
+        .orientation()
+        .unwrap_or(image::metadata::Orientation::NoTransforms);
+    let mut img = image::DynamicImage::from_decoder(decoder)
+        .map_err(|err| ServeReplicaError::BadRequest(err.to_string()))?;
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+/// HEIF/HEIC files start with an `ftyp` box naming one of these brands
+fn is_heif(bytes: &[u8]) -> bool {
+    bytes.len() >= 12
+        && &bytes[4..8] == b"ftyp"
+        && matches!(
+            &bytes[8..12],
+            b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1"
+        )
+}
+
+#[cfg(feature = "heic")]
+fn decode_heif(bytes: &[u8]) -> Result<image::DynamicImage, ServeReplicaError> {
+    let to_err = |err: libheif_rs::HeifError| ServeReplicaError::BadRequest(err.to_string());
+    let lib_heif = libheif_rs::LibHeif::new();
+    let ctx = libheif_rs::HeifContext::read_from_bytes(bytes).map_err(to_err)?;
+    let handle = ctx.primary_image_handle().map_err(to_err)?;
+    // libheif applies the container's rotation/mirroring transforms while decoding
+    let decoded = lib_heif
+        .decode(
+            &handle,
+            libheif_rs::ColorSpace::Rgb(libheif_rs::RgbChroma::Rgb),
+            None,
+        )
+        .map_err(to_err)?;
+    let plane = decoded.planes().interleaved.ok_or_else(|| {
+        ServeReplicaError::BadRequest(String::from("HEIC image has no interleaved plane"))
+    })?;
+    let row_len = plane.width as usize * 3;
+    let pixels = plane
+        .data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .flat_map(|row| &row[..row_len])
+        .copied()
+        .collect();
+    image::RgbImage::from_raw(plane.width, plane.height, pixels)
+        .map(image::DynamicImage::ImageRgb8)
+        .ok_or_else(|| ServeReplicaError::BadRequest(String::from("truncated HEIC image")))
+}
+
+#[cfg(not(feature = "heic"))]
+fn decode_heif(_bytes: &[u8]) -> Result<image::DynamicImage, ServeReplicaError> {
+    Err(ServeReplicaError::BadRequest(String::from(
+        "HEIC/HEIF images need serve-replica built with the `heic` feature",
+    )))
+}
+
+/// Read the raw bytes behind a `data:` or `http(s):` URL
+async fn load(url: &str) -> Result<Vec<u8>, ServeReplicaError> {
+    if let Some(data_url) = url.strip_prefix("data:") {
+        return decode_data_url(data_url);
+    }
+    let address = fetch_address(url).await?;
+    // a redirect could lead anywhere, so it is answered like any other non-2xx status
+    let mut response = awc::Client::builder()
+        .disable_redirects()
+        .finish()
+        .get(url)
+        .address(address)
+        .send()
+        .await
+        .map_err(|err| ServeReplicaError::BadRequest(format!("fetching {}: {}", url, err)))?;
+    if !response.status().is_success() {
+        return Err(ServeReplicaError::BadRequest(format!(
+            "fetching {}: {}",
+            url,
+            response.status()
+        )));
+    }
+    response
+        .body()
+        .limit(MAX_INPUT_BYTES)
+        .await
+        .map(|body| body.to_vec())
+        .map_err(|err| ServeReplicaError::BadRequest(format!("fetching {}: {}", url, err)))
+}
+
+/// Resolve the host of an `http(s):` `url` to the address to fetch it from, refusing hosts with
+/// any address that is not [`is_public_ip`], so swap inputs cannot reach this host or its network
+pub(crate) async fn fetch_address(url: &str) -> Result<std::net::SocketAddr, ServeReplicaError> {
+    let uri: awc::http::Uri = url
+        .parse()
+        .map_err(|_| ServeReplicaError::BadRequest(format!("invalid URL {:?}", url)))?;
+    let default_port = match uri.scheme_str() {
+        Some("http") => 80,
+        Some("https") => 443,
+        _ => {
+            return Err(ServeReplicaError::BadRequest(String::from(
+                "only data:, http: and https: URLs are supported",
+            )))
+        }
+    };
+    let host = uri
+        .host()
+        .ok_or_else(|| ServeReplicaError::BadRequest(format!("no host in {:?}", url)))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_owned();
+    let port = uri.port_u16().unwrap_or(default_port);
+    let addresses = actix_web::web::block(move || {
+        std::net::ToSocketAddrs::to_socket_addrs(&(host.as_str(), port))
+            .map(Iterator::collect::<Vec<std::net::SocketAddr>>)
+    })
+    .await?
+    .map_err(|err| ServeReplicaError::BadRequest(format!("resolving {}: {}", url, err)))?;
+    match addresses.first() {
+        Some(address) if addresses.iter().all(|address| is_public_ip(address.ip())) => Ok(*address),
+        Some(_) => Err(ServeReplicaError::BadRequest(format!(
+            "{} resolves to a non-public address",
+            url
+        ))),
+        None => Err(ServeReplicaError::BadRequest(format!(
+            "{} resolves to no address",
+            url
+        ))),
+    }
+}
+
+/// Whether `ip` may be fetched from: not loopback, private, link-local, unspecified, multicast,
+/// reserved or otherwise internal, including IPv4 addresses embedded in IPv6 ones
+pub(crate) fn is_public_ip(ip: std::net::IpAddr) -> bool {
+    match ip {
+        std::net::IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_multicast()
+                // 0.0.0.0/8, "this network"
+                || a == 0
+                // 100.64.0.0/10, shared address space of carrier-grade NAT
+                || (a == 100 && b & 0xc0 == 64)
+                // 192.0.0.0/24, IETF protocol assignments
+                || (a == 192 && b == 0 && c == 0)
+                // 198.18.0.0/15, benchmarking
+                || (a == 198 && b & 0xfe == 18)
+                // 240.0.0.0/4, reserved, and the broadcast address
+                || a >= 240)
+        }
+        std::net::IpAddr::V6(ip) => match embedded_ipv4(&ip) {
+            Some(ip) => is_public_ip(std::net::IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_multicast()
+                    // fc00::/7, unique local
+                    || first & 0xfe00 == 0xfc00
+                    // fe80::/10, link-local
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The IPv4 address `ip` reaches: IPv4-mapped `::ffff:a.b.c.d`, IPv4-compatible `::a.b.c.d`,
+/// NAT64 `64:ff9b::a.b.c.d` or 6to4 `2002:aabb:ccdd::`
+fn embedded_ipv4(ip: &std::net::Ipv6Addr) -> Option<std::net::Ipv4Addr> {
+    let [.., a, b, c, d] = ip.octets();
+    match ip.segments() {
+        // `::` and `::1` fall in here too, as the non-public 0.0.0.0 and 0.0.0.1
+        [0, 0, 0, 0, 0, 0 | 0xffff, _, _] | [0x64, 0xff9b, 0, 0, 0, 0, _, _] => {
+            Some(std::net::Ipv4Addr::new(a, b, c, d))
+        }
+        [0x2002, high, low, ..] => Some(std::net::Ipv4Addr::from(
+            (u32::from(high) << 16) | u32::from(low),
+        )),
+        _ => None,
+    }
+}
+
+/// Refuse an input URL that can never be loaded, before a swap is queued: a scheme other than
+/// `data:`, `http:` or `https:`, a malformed `data:` URL, or a host that is not public
+pub(crate) async fn check_url(url: &str) -> Result<(), ServeReplicaError> {
+    match url.strip_prefix("data:") {
+        Some(data_url) => decode_data_url(data_url).map(|_| ()),
+        None => fetch_address(url).await.map(|_| ()),
+    }
+}
+
+/// Decode the part of a `data:` URL after the scheme, e.g., "image/jpg;base64,fa"
+pub(crate) fn decode_data_url(data_url: &str) -> Result<Vec<u8>, ServeReplicaError> {
+    let (header, payload) = data_url.split_once(',').ok_or_else(|| {
+        ServeReplicaError::BadRequest(String::from("malformed data URL, missing ','"))
+    })?;
+    if !header.ends_with(";base64") {
+        return Err(ServeReplicaError::BadRequest(String::from(
+            "only base64 data URLs are supported",
+        )));
+    }
+    if payload.len() / 4 * 3 > MAX_INPUT_BYTES {
+        return Err(ServeReplicaError::BadRequest(format!(
+            "image exceeds {} bytes",
+            MAX_INPUT_BYTES
+        )));
+    }
+    base64::engine::general_purpose::STANDARD
+        .decode(payload)
+        .map_err(|err| ServeReplicaError::BadRequest(err.to_string()))
+}
@@ -0,0 +1,221 @@
+use diesel::Connection;
+
+use crate::errors::ServeReplicaError;
+use crate::extra_schemas::SwapPostResponse;
+use crate::models::swap_batch::SwapBatch;
+use crate::models::swap_history::{NewSwapHistory, SwapHistory};
+use crate::models::swap_job::SwapJob;
+use crate::models::user_account::UserAccount;
+use crate::swap::SwapBackend;
+use crate::DbPool;
+
+/// Sizing, timeouts and retry policy of the swap worker pool
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerConfig {
+    /// number of jobs run concurrently
+    pub concurrency: usize,
+    /// queued plus running jobs beyond which `/v1/swap` answers 503
+    pub queue_capacity: i64,
+    /// time one attempt may take before it is abandoned
+    pub job_timeout: std::time::Duration,
+    /// attempts before a job is marked failed
+    pub max_attempts: i32,
+    /// delay before a retry, multiplied by the number of attempts so far
+    pub retry_backoff: std::time::Duration,
+    /// how long an idle worker waits before looking for work again
+    pub poll_interval: std::time::Duration,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            queue_capacity: 100,
+            job_timeout: std::time::Duration::from_secs(120),
+            max_attempts: 3,
+            retry_backoff: std::time::Duration::from_secs(10),
+            poll_interval: std::time::Duration::from_secs(1),
+        }
+    }
+}
+
+impl WorkerConfig {
+    /// Suggested `Retry-After`, in seconds, for callers turned away by a full queue
+    pub fn retry_after_secs(&self) -> u64 {
+        self.job_timeout.as_secs().max(1)
+    }
+
+    /// How long a job may stay `running` before its worker is presumed gone
+    pub fn stale_after(&self) -> std::time::Duration {
+        self.job_timeout * 2
+    }
+
+    /// How often stale jobs are looked for, at least every second
+    pub fn requeue_interval(&self) -> std::time::Duration {
+        self.job_timeout.max(std::time::Duration::from_secs(1))
+    }
+}
+
+/// Spawn `config.concurrency` workers onto the current actix system, and a task requeueing the
+/// jobs of workers that died mid-job
+pub fn spawn(pool: DbPool, backend: SwapBackend, config: WorkerConfig) {
+    {
+        let pool = pool.clone();
+        let config = config.clone();
+        actix_web::rt::spawn(async move { requeue_stale(pool, config).await });
+    }
+    for worker_id in 0..config.concurrency {
+        let pool = pool.clone();
+        let backend = backend.clone();
+        let config = config.clone();
+        actix_web::rt::spawn(async move { run(worker_id, pool, backend, config).await });
+    }
+}
+
+/// Requeue stale jobs at startup, then every [`WorkerConfig::requeue_interval`], as workers of
+/// this or another instance may die at any time
+async fn requeue_stale(pool: DbPool, config: WorkerConfig) {
+    let mut interval = actix_web::rt::time::interval(config.requeue_interval());
+    loop {
+        interval.tick().await;
+        let (pool, stale_after, max_attempts) =
+            (pool.clone(), config.stale_after(), config.max_attempts);
+        match actix_web::web::block(move || {
+            let mut conn = pool.get().map_err(|err| err.to_string())?;
+            SwapJob::requeue_stale(&mut conn, stale_after, max_attempts)
+                .map_err(|err| err.to_string())
+        })
+        .await
+        {
+            Ok(Ok(stale)) => {
+                if stale.requeued > 0 {
+                    log::warn!("requeued {} stale swap jobs", stale.requeued);
+                }
+                if stale.failed > 0 {
+                    log::warn!("failed {} stale swap jobs out of attempts", stale.failed);
+                }
+            }
+            Ok(Err(err)) => log::error!("requeueing stale swap jobs: {}", err),
+            Err(err) => log::error!("requeueing stale swap jobs: {}", err),
+        }
+    }
+}
+
+async fn run(worker_id: usize, pool: DbPool, backend: SwapBackend, config: WorkerConfig) {
+    loop {
+        let claim_pool = pool.clone();
+        let claimed = actix_web::web::block(move || {
+            let mut conn = claim_pool.get().map_err(|err| err.to_string())?;
+            SwapJob::claim(&mut conn).map_err(|err| err.to_string())
+        })
+        .await;
+        match claimed {
+            Ok(Ok(Some(job))) => process(worker_id, &pool, &backend, &config, job).await,
+            Ok(Ok(None)) => actix_web::rt::time::sleep(config.poll_interval).await,
+            Ok(Err(err)) => {
+                log::error!("swap worker {} claiming job: {}", worker_id, err);
+                actix_web::rt::time::sleep(config.poll_interval).await
+            }
+            Err(err) => {
+                log::error!("swap worker {} claiming job: {}", worker_id, err);
+                actix_web::rt::time::sleep(config.poll_interval).await
+            }
+        }
+    }
+}
+
+pub(crate) async fn process(
+    worker_id: usize,
+    pool: &DbPool,
+    backend: &SwapBackend,
+    config: &WorkerConfig,
+    job: SwapJob,
+) {
+    log::debug!(
+        "swap worker {} running job {} (attempt {})",
+        worker_id,
+        job.id,
+        job.attempts
+    );
+    let outcome =
+        match actix_web::rt::time::timeout(config.job_timeout, submit(pool, backend, &job)).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(err)) => Err(err.to_string()),
+            Err(_) => Err(format!("timed out after {}s", config.job_timeout.as_secs())),
+        };
+
+    let pool = pool.clone();
+    let max_attempts = config.max_attempts;
+    let retry_in = config.retry_backoff * job.attempts.max(1) as u32;
+    let recorded = actix_web::web::block(move || -> Result<(), String> {
+        let mut conn = pool.get().map_err(|err| err.to_string())?;
+        match outcome {
+            // the output, its history and the credit spent on it land together, or not at all
+            Ok(response) => conn
+                .transaction(|conn| {
+                    SwapJob::complete(conn, job.id, &response)?;
+                    SwapHistory::insert(
+                        conn,
+                        &NewSwapHistory {
+                            username: &job.username,
+                            model_id: job.model_id,
+                            model_img_url: &job.model_img_url,
+                            output_url: &response.output_url,
+                            status: &response.status,
+                        },
+                    )?;
+                    UserAccount::use_credit(conn, &job.username)?;
+                    SwapBatch::release_images(conn)
+                })
+                .map(|_| ())
+                .map_err(|err: diesel::result::Error| err.to_string())?,
+            Err(error) if job.attempts < max_attempts => {
+                log::warn!("swap job {} failed, will retry: {}", job.id, error);
+                SwapJob::retry(&mut conn, job.id, &error, retry_in)
+                    .map_err(|err| err.to_string())?;
+            }
+            Err(error) => {
+                log::error!("swap job {} failed permanently: {}", job.id, error);
+                conn.transaction(|conn| {
+                    SwapJob::fail(conn, job.id, &error)?;
+                    SwapBatch::release_images(conn)
+                })
+                .map_err(|err: diesel::result::Error| err.to_string())?;
+            }
+        }
+        Ok(())
+    })
+    .await;
+    match recorded {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => log::error!("swap worker {} recording outcome: {}", worker_id, err),
+        Err(err) => log::error!("swap worker {} recording outcome: {}", worker_id, err),
+    }
+}
+
+/// Submit `job` to the faceswap service; a batch's child is run with the batch's user image,
+/// which was preprocessed once when the batch was queued
+async fn submit(
+    pool: &DbPool,
+    backend: &SwapBackend,
+    job: &SwapJob,
+) -> Result<SwapPostResponse, ServeReplicaError> {
+    match (&job.user_img_url, job.batch_id) {
+        (Some(user_img_url), _) => backend.submit(user_img_url, &job.model_img_url).await,
+        (None, Some(batch_id)) => {
+            let pool = pool.clone();
+            let user_img_url = actix_web::web::block(move || {
+                let mut conn = pool.get()?;
+                SwapBatch::user_img_url(&mut conn, batch_id).map_err(ServeReplicaError::from)
+            })
+            .await??;
+            backend
+                .submit_prepared(&user_img_url, &job.model_img_url)
+                .await
+        }
+        (None, None) => Err(ServeReplicaError::BadRequest(format!(
+            "swap job {} has no user image",
+            job.id
+        ))),
+    }
+}
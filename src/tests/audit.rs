@@ -0,0 +1,51 @@
+use actix_web::http::{Method, StatusCode};
+
+use crate::audit::{event_for, outcome, LOGIN, LOGIN_FAILED, LOGIN_THROTTLED};
+
+#[test]
+fn test_event_for() {
+    assert_eq!(event_for(&Method::POST, "/api/token"), Some(LOGIN));
+    assert_eq!(event_for(&Method::POST, "/secured/logout"), Some("logout"));
+    assert_eq!(
+        event_for(&Method::POST, "/api/token/revoke"),
+        Some("token_revoked")
+    );
+    assert_eq!(
+        event_for(&Method::DELETE, "/secured/api_keys/7"),
+        Some("api_key_revoked")
+    );
+    assert_eq!(
+        event_for(&Method::DELETE, "/secured/sessions/7"),
+        Some("session_revoked")
+    );
+    assert_eq!(
+        event_for(&Method::DELETE, "/secured/sessions"),
+        Some("other_sessions_revoked")
+    );
+    assert_eq!(event_for(&Method::GET, "/secured/sessions"), None);
+    assert_eq!(
+        event_for(&Method::POST, "/api/v0/model"),
+        Some("person_model_upserted")
+    );
+    assert_eq!(
+        event_for(&Method::PUT, "/api/v0/profile/"),
+        Some("profile_upserted")
+    );
+    assert_eq!(event_for(&Method::GET, "/api/v0/profile"), None);
+    assert_eq!(event_for(&Method::GET, "/api/token"), None);
+    assert_eq!(event_for(&Method::POST, "/api/token/introspect"), None);
+}
+
+#[test]
+fn test_outcome() {
+    assert_eq!(outcome(LOGIN, StatusCode::OK), Some(LOGIN));
+    assert_eq!(outcome(LOGIN, StatusCode::FOUND), Some(LOGIN));
+    assert_eq!(outcome(LOGIN, StatusCode::BAD_REQUEST), Some(LOGIN_FAILED));
+    assert_eq!(
+        outcome(LOGIN, StatusCode::TOO_MANY_REQUESTS),
+        Some(LOGIN_THROTTLED)
+    );
+    assert_eq!(outcome(LOGIN, StatusCode::BAD_GATEWAY), None);
+    assert_eq!(outcome("logout", StatusCode::OK), Some("logout"));
+    assert_eq!(outcome("logout", StatusCode::UNAUTHORIZED), None);
+}
@@ -0,0 +1,1235 @@
+use actix_web::http::Method;
+
+use crate::auth::scopes::{format_scopes, parse_scopes, required_scope, Scope};
+
+#[test]
+fn test_parse_scopes() {
+    assert_eq!(
+        parse_scopes("swap models:read swap").unwrap(),
+        vec![Scope::ModelsRead, Scope::Swap]
+    );
+    assert_eq!(parse_scopes("").unwrap(), vec![]);
+    assert!(parse_scopes("models:read admin:everything").is_err());
+    assert_eq!(
+        format_scopes(&parse_scopes("profile:write crawl").unwrap()),
+        "profile:write crawl"
+    );
+}
+
+#[test]
+fn test_required_scope() {
+    assert_eq!(
+        required_scope(&Method::GET, "/api/v0/models"),
+        Some(Scope::ModelsRead)
+    );
+    assert_eq!(
+        required_scope(&Method::POST, "/api/v0/model"),
+        Some(Scope::ModelsWrite)
+    );
+    assert_eq!(
+        required_scope(&Method::POST, "/api/v0/profile"),
+        Some(Scope::ProfileWrite)
+    );
+    assert_eq!(
+        required_scope(&Method::DELETE, "/api/v0/swaps/3"),
+        Some(Scope::Swap)
+    );
+    assert_eq!(
+        required_scope(&Method::POST, "/v1/swap/batch"),
+        Some(Scope::Swap)
+    );
+    assert_eq!(
+        required_scope(&Method::POST, "/v1/crawl"),
+        Some(Scope::Crawl)
+    );
+    assert_eq!(
+        required_scope(&Method::GET, "/userinfo"),
+        Some(Scope::OpenId)
+    );
+    assert_eq!(
+        required_scope(&Method::GET, "/api/admin/users"),
+        Some(Scope::Admin)
+    );
+    assert_eq!(required_scope(&Method::GET, "/secured/secret"), None);
+    assert!(!Scope::user_defaults().contains(&Scope::Admin));
+}
+
+#[test]
+fn test_granted_scopes() {
+    use crate::auth::token::granted_scopes;
+
+    assert_eq!(granted_scopes(None).unwrap(), Scope::user_defaults());
+    assert_eq!(granted_scopes(Some("swap")).unwrap(), vec![Scope::Swap]);
+    assert!(granted_scopes(Some("swap everything")).is_err());
+}
+
+#[test]
+fn test_authorise_request() {
+    use crate::auth::authorise::{
+        check_client, code_location, pkce_challenge, requested_scopes, validate_redirect_uri,
+        verify_pkce, AuthoriseQuery,
+    };
+    use crate::models::confidential_client::ConfidentialClient;
+
+    let code_verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    let client = ConfidentialClient {
+        id: 1,
+        client_id: String::from("app"),
+        secret_hash: String::new(),
+        name: String::from("App"),
+        owner: String::from("alice"),
+        scopes: String::from("openid models:read swap"),
+        created_at: chrono::Utc::now().naive_utc(),
+        secret_rotated_at: chrono::Utc::now().naive_utc(),
+        revoked_at: None,
+        redirect_uris: String::from("https://app.example/cb http://localhost:8080/cb"),
+    };
+    let query = AuthoriseQuery {
+        response_type: String::from("code"),
+        client_id: String::from("app"),
+        redirect_uri: String::from("https://app.example/cb"),
+        scope: Some(String::from("openid swap")),
+        state: Some(String::from("xyz")),
+        code_challenge: Some(pkce_challenge(code_verifier)),
+        code_challenge_method: Some(String::from("S256")),
+        nonce: None,
+    };
+    assert!(validate_redirect_uri("https://app.example/cb?tenant=1").is_ok());
+    assert!(validate_redirect_uri("http://localhost:8080/cb").is_ok());
+    assert!(validate_redirect_uri("http://app.example/cb").is_err());
+    assert!(validate_redirect_uri("https://app.example/cb#token").is_err());
+    assert!(validate_redirect_uri("/cb").is_err());
+    assert!(validate_redirect_uri("javascript:alert(1)").is_err());
+
+    assert!(check_client(Some(client.clone()), &query).is_ok());
+    assert!(check_client(None, &query).is_err());
+    assert!(check_client(
+        Some(client.clone()),
+        &AuthoriseQuery {
+            redirect_uri: String::from("https://app.example/cb/elsewhere"),
+            ..query.clone()
+        }
+    )
+    .is_err());
+
+    assert_eq!(
+        requested_scopes(&client, &query).unwrap(),
+        vec![Scope::OpenId, Scope::Swap]
+    );
+    let refused = |query: AuthoriseQuery| requested_scopes(&client, &query).unwrap_err().error;
+    assert_eq!(
+        refused(AuthoriseQuery {
+            scope: Some(String::from("swap admin")),
+            ..query.clone()
+        }),
+        "invalid_scope"
+    );
+    // the user defaults include scopes this client was not registered for
+    assert_eq!(
+        refused(AuthoriseQuery {
+            scope: None,
+            ..query.clone()
+        }),
+        "invalid_scope"
+    );
+    assert_eq!(
+        refused(AuthoriseQuery {
+            code_challenge_method: Some(String::from("plain")),
+            ..query.clone()
+        }),
+        "invalid_request"
+    );
+    assert_eq!(
+        refused(AuthoriseQuery {
+            code_challenge: None,
+            ..query.clone()
+        }),
+        "invalid_request"
+    );
+    assert_eq!(
+        refused(AuthoriseQuery {
+            response_type: String::from("token"),
+            ..query.clone()
+        }),
+        "unsupported_response_type"
+    );
+
+    assert!(verify_pkce(
+        code_verifier,
+        "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+    ));
+    assert!(!verify_pkce(
+        "not-the-verifier-but-long-enough-to-be-one-0123",
+        query.code_challenge.as_deref().unwrap()
+    ));
+    assert_eq!(
+        code_location(&query, Ok("abc")),
+        "https://app.example/cb?code=abc&state=xyz"
+    );
+    let err = requested_scopes(
+        &client,
+        &AuthoriseQuery {
+            response_type: String::from("token"),
+            ..query.clone()
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        code_location(&query, Err(&err)),
+        "https://app.example/cb?error=unsupported_response_type&error_description=response_type+must+be+code&state=xyz"
+    );
+}
+
+#[test]
+fn test_security_requirements() {
+    let openapi = serde_json::to_value(crate::openapi_spec(None)).unwrap();
+    let security = |path: &str, method: &str| openapi["paths"][path][method]["security"].clone();
+
+    assert_eq!(
+        security("/api/admin/users", "get"),
+        serde_json::json!([{"password": ["admin"]}])
+    );
+    assert_eq!(
+        security("/secured/clients", "get"),
+        serde_json::json!([{"password": []}])
+    );
+    assert!(security("/api/register", "post").is_null());
+    assert!(crate::auth::scopes::requires_bearer("/v1/swap"));
+    assert!(!crate::auth::scopes::requires_bearer("/v10"));
+}
+
+#[test]
+fn test_generate_token() {
+    use crate::auth::token::generate_token;
+
+    let token = generate_token();
+    assert_eq!(token.len(), 43);
+    assert_ne!(token, generate_token());
+}
+
+#[test]
+fn test_token_form_refresh_grant() {
+    use crate::auth::token::TokenForm;
+
+    let form: TokenForm =
+        serde_urlencoded::from_str("grant_type=refresh_token&refresh_token=abc&scope=swap")
+            .unwrap();
+    assert_eq!(form.grant_type, "refresh_token");
+    assert_eq!(form.refresh_token.as_deref(), Some("abc"));
+    assert_eq!(form.scope.as_deref(), Some("swap"));
+    assert_eq!(form.username, None);
+}
+
+#[test]
+fn test_api_key_create_request_defaults() {
+    use crate::models::api_key::ApiKeyCreateRequest;
+
+    let form: ApiKeyCreateRequest =
+        serde_json::from_str(r#"{"name": "nightly crawl", "scope": "crawl models:read"}"#).unwrap();
+    assert_eq!(form.name, "nightly crawl");
+    assert_eq!(form.expires_at, None);
+}
+
+#[test]
+fn test_client_credentials_prefers_basic_auth() {
+    use crate::auth::token::client_credentials;
+
+    let (form_id, form_secret) = (Some("form-id"), Some("form-secret"));
+    // base64("basic-id:basic-secret")
+    assert_eq!(
+        client_credentials(
+            Some("Basic YmFzaWMtaWQ6YmFzaWMtc2VjcmV0"),
+            form_id,
+            form_secret
+        ),
+        Some((String::from("basic-id"), String::from("basic-secret")))
+    );
+    assert_eq!(
+        client_credentials(Some("Bearer abc"), form_id, form_secret),
+        Some((String::from("form-id"), String::from("form-secret")))
+    );
+    assert_eq!(client_credentials(None, form_id, None), None);
+}
+
+#[test]
+fn test_roles() {
+    use crate::auth::roles::{require_role, Role};
+    use crate::auth::AuthenticatedUser;
+
+    assert_eq!("moderator".parse::<Role>().unwrap(), Role::Moderator);
+    assert!("root".parse::<Role>().is_err());
+    assert_eq!(Role::default(), Role::User);
+
+    let moderator = AuthenticatedUser {
+        username: String::from("mod"),
+        scopes: Vec::new(),
+        role: Role::Moderator,
+        credential: crate::auth::Credential::UserToken,
+    };
+    assert!(require_role(&moderator, Role::User).is_ok());
+    assert!(require_role(&moderator, Role::Moderator).is_ok());
+    assert!(require_role(&moderator, Role::Admin).is_err());
+}
+
+#[test]
+fn test_inactive_introspection_response() {
+    use crate::auth::introspection::IntrospectionResponse;
+
+    assert_eq!(
+        serde_json::to_value(IntrospectionResponse::inactive()).unwrap(),
+        serde_json::json!({"active": false})
+    );
+}
+
+#[test]
+fn test_account_validation() {
+    use crate::auth::account::{validate_email, validate_password, validate_username};
+
+    assert!(validate_username("alice.smith-1").is_ok());
+    assert!(validate_username("").is_err());
+    assert!(validate_username("alice smith").is_err());
+    assert!(validate_email("alice@example.com").is_ok());
+    assert!(validate_email("alice@localhost").is_err());
+    assert!(validate_email("@example.com").is_err());
+    assert!(validate_password("correct horse").is_ok());
+    assert!(validate_password("short").is_err());
+}
+
+#[test]
+fn test_password_hash_round_trip() {
+    use crate::models::user_credential::{hash_password, UserCredential};
+
+    let now = chrono::Utc::now().naive_utc();
+    let credential = UserCredential {
+        username: String::from("alice"),
+        email: String::from("alice@example.com"),
+        password_hash: hash_password("correct horse").unwrap(),
+        email_verified_at: None,
+        created_at: now,
+        updated_at: now,
+    };
+    assert!(credential.password_hash.starts_with("$argon2id$"));
+    assert!(credential.verify_password("correct horse"));
+    assert!(!credential.verify_password("battery staple"));
+}
+
+#[test]
+fn test_file_mailer() {
+    use crate::mail::{Email, FileMailer, Mailer};
+
+    let path = std::env::temp_dir().join(format!("serve-replica-mail-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    FileMailer::new(&path)
+        .send(&Email {
+            to: String::from("alice@example.com"),
+            subject: String::from("Verify your email"),
+            body: String::from("token=abc"),
+        })
+        .unwrap();
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(written.starts_with("To: alice@example.com\nSubject: Verify your email\n"));
+    assert!(written.contains("token=abc"));
+}
+
+#[test]
+fn test_username_taken_checks_scaffold_users() {
+    use crate::auth::account::scaffold_user;
+
+    let query = scaffold_user("alice");
+    let sql = diesel::debug_query::<diesel::pg::Pg, _>(&query).to_string();
+    assert!(sql.contains(r#"FROM "users""#), "{}", sql);
+    assert!(sql.contains(r#""users"."username" = $1"#), "{}", sql);
+    assert!(sql.contains(r#"["alice"]"#), "{}", sql);
+}
+
+#[test]
+fn test_is_login_failure() {
+    use crate::auth::throttle::is_login_failure;
+    use crate::errors::ServeReplicaError;
+
+    let bad_request = |msg: &str| ServeReplicaError::BadRequest(String::from(msg));
+    assert!(is_login_failure(&bad_request(
+        crate::auth::token::INVALID_CREDENTIALS
+    )));
+    assert!(is_login_failure(&bad_request(
+        crate::auth::mfa::INVALID_OTP
+    )));
+    assert!(!is_login_failure(&bad_request(
+        "mfa_required: send the code from your authenticator app as otp"
+    )));
+    assert!(!is_login_failure(&bad_request(
+        "invalid_request: missing field `grant_type`"
+    )));
+    assert!(!is_login_failure(&bad_request(
+        "invalid_grant: account disabled"
+    )));
+}
+
+#[test]
+fn test_throttle_delay() {
+    use crate::auth::throttle::ThrottleConfig;
+
+    let config = ThrottleConfig::default();
+    assert_eq!(config.delay(0), std::time::Duration::ZERO);
+    assert_eq!(config.delay(1), config.base_delay);
+    assert_eq!(config.delay(2), config.base_delay * 2);
+    assert_eq!(config.delay(40), config.max_delay);
+}
+
+#[test]
+fn test_memory_attempt_store_locks_out() {
+    use crate::auth::throttle::{AttemptStore, MemoryAttemptStore};
+
+    let store = MemoryAttemptStore::default();
+    let (window, lockout) = (
+        std::time::Duration::from_secs(60),
+        std::time::Duration::from_secs(60),
+    );
+    for failures in 1..3 {
+        let status = store
+            .record_failure("user:alice", 3, window, lockout)
+            .unwrap();
+        assert_eq!(status.failures, failures);
+        assert_eq!(status.locked_for, None);
+    }
+    let status = store
+        .record_failure("user:alice", 3, window, lockout)
+        .unwrap();
+    assert!(status.locked_for.is_some());
+    assert_eq!(store.status("user:bob").unwrap().failures, 0);
+
+    store.reset("user:alice").unwrap();
+    assert_eq!(store.status("user:alice").unwrap(), Default::default());
+}
+
+#[test]
+fn test_memory_attempt_store_releases_attempts() {
+    use crate::auth::throttle::{AttemptStore, MemoryAttemptStore};
+
+    let store = MemoryAttemptStore::default();
+    let window = std::time::Duration::from_secs(60);
+    assert_eq!(
+        store
+            .record_attempt("ip:192.0.2.1", window)
+            .unwrap()
+            .failures,
+        1
+    );
+    assert_eq!(
+        store
+            .record_attempt("ip:192.0.2.1", window)
+            .unwrap()
+            .failures,
+        2
+    );
+    store.release("ip:192.0.2.1").unwrap();
+    assert_eq!(store.status("ip:192.0.2.1").unwrap().failures, 1);
+    store.release("ip:192.0.2.1").unwrap();
+    store.release("ip:192.0.2.1").unwrap();
+    assert_eq!(store.status("ip:192.0.2.1").unwrap().failures, 0);
+    store.release("ip:192.0.2.9").unwrap();
+    assert_eq!(store.status("ip:192.0.2.9").unwrap(), Default::default());
+}
+
+#[actix_web::test]
+async fn test_login_throttle_counts_attempts_in_flight() {
+    use crate::auth::throttle::{
+        login_throttle, LoginThrottle, MemoryAttemptStore, ThrottleConfig,
+    };
+
+    let throttle = LoginThrottle {
+        config: ThrottleConfig {
+            max_user_failures: 1,
+            ..ThrottleConfig::default()
+        },
+        store: std::sync::Arc::new(MemoryAttemptStore::default()),
+    };
+    let app = actix_web::test::init_service(
+        actix_web::App::new()
+            .app_data(actix_web::web::Data::new(throttle))
+            .wrap(actix_web::middleware::from_fn(login_throttle))
+            .route(
+                crate::auth::token::TOKEN_PATH,
+                actix_web::web::post().to(|| async {
+                    actix_web::rt::time::sleep(std::time::Duration::from_secs(1)).await;
+                    actix_web::HttpResponse::BadRequest().finish()
+                }),
+            ),
+    )
+    .await;
+    let attempt = || {
+        actix_web::test::TestRequest::post()
+            .uri(crate::auth::token::TOKEN_PATH)
+            .set_form([
+                ("grant_type", "password"),
+                ("username", "alice"),
+                ("password", "wrong"),
+            ])
+            .to_request()
+    };
+
+    let mut first = Box::pin(actix_web::test::call_service(&app, attempt()));
+    // let the first attempt get as far as its handler, where it is held
+    assert!(
+        actix_web::rt::time::timeout(std::time::Duration::from_millis(200), &mut first)
+            .await
+            .is_err()
+    );
+    let second = actix_web::test::try_call_service(&app, attempt())
+        .await
+        .unwrap_err()
+        .error_response();
+    assert_eq!(
+        second.status(),
+        actix_web::http::StatusCode::TOO_MANY_REQUESTS
+    );
+    assert!(second
+        .headers()
+        .contains_key(actix_web::http::header::RETRY_AFTER));
+    assert_eq!(
+        first.await.status(),
+        actix_web::http::StatusCode::BAD_REQUEST
+    );
+
+    // the first attempt's failure locks the username out
+    let third = actix_web::test::try_call_service(&app, attempt())
+        .await
+        .unwrap_err()
+        .error_response();
+    assert_eq!(
+        third.status(),
+        actix_web::http::StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[test]
+fn test_mail_requests_are_limited() {
+    use crate::auth::throttle::{
+        LoginThrottle, MemoryAttemptStore, ThrottleConfig, MAX_MAIL_REQUESTS,
+    };
+    use crate::errors::ServeReplicaError;
+
+    let throttle = LoginThrottle {
+        config: ThrottleConfig::default(),
+        store: std::sync::Arc::new(MemoryAttemptStore::default()),
+    };
+    for _ in 0..MAX_MAIL_REQUESTS {
+        throttle
+            .count_mail_request("alice@example.com", "192.0.2.1")
+            .unwrap();
+    }
+    assert!(matches!(
+        throttle.count_mail_request("alice@example.com", "192.0.2.2"),
+        Err(ServeReplicaError::TooManyRequests(_, retry_after)) if (1..=15 * 60).contains(&retry_after)
+    ));
+    // other addresses are not held up by it
+    throttle
+        .count_mail_request("bob@example.com", "192.0.2.1")
+        .unwrap();
+}
+
+#[test]
+fn test_totp_matching_step() {
+    use crate::auth::mfa::{matching_step, totp};
+
+    // RFC 6238 appendix B: SHA1 at T = 59 gives 94287082, whose last 6 digits are the 6-digit code
+    let totp = totp(b"12345678901234567890", "alice").unwrap();
+    assert_eq!(matching_step(&totp, "287082", 59), Some(1));
+    // still accepted one step later, for clock drift, but not two
+    assert_eq!(matching_step(&totp, "287082", 59 + 30), Some(1));
+    assert_eq!(matching_step(&totp, "287082", 59 + 60), None);
+    assert_eq!(matching_step(&totp, "000000", 59), None);
+}
+
+#[test]
+fn test_recovery_codes() {
+    use crate::auth::mfa::{generate_recovery_codes, normalise_recovery_code, RECOVERY_CODE_COUNT};
+
+    let codes = generate_recovery_codes();
+    assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+    assert!(codes
+        .iter()
+        .all(|code| code.len() == 11 && code.as_bytes()[5] == b'-'));
+    assert_eq!(normalise_recovery_code(&codes[0].to_uppercase()), codes[0]);
+    assert_eq!(normalise_recovery_code(" K3XW9PQ7MA "), "k3xw9-pq7ma");
+}
+
+#[test]
+fn test_describe_device() {
+    use crate::auth::sessions::describe_device;
+
+    assert_eq!(
+        describe_device("Mozilla/5.0 (X11; Linux x86_64; rv:133.0) Gecko/20100101 Firefox/133.0")
+            .as_deref(),
+        Some("Firefox on Linux")
+    );
+    assert_eq!(
+        describe_device(
+            "Mozilla/5.0 (Linux; Android 14) AppleWebKit/537.36 (KHTML, like Gecko) \
+             Chrome/131.0.0.0 Mobile Safari/537.36"
+        )
+        .as_deref(),
+        Some("Chrome on Android")
+    );
+    assert_eq!(
+        describe_device(
+            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) \
+             Chrome/131.0.0.0 Safari/537.36 Edg/131.0.0.0"
+        )
+        .as_deref(),
+        Some("Edge on Windows")
+    );
+    assert_eq!(describe_device("curl/8.5.0").as_deref(), Some("curl"));
+    assert_eq!(describe_device("serve-replica-batch"), None);
+}
+
+#[test]
+fn test_id_token_verifies_against_jwks() {
+    use base64::Engine;
+    use rsa::signature::Verifier;
+
+    use crate::auth::oidc::{id_token_for, IdTokenClaims, OidcProvider, SigningKey};
+
+    let provider = OidcProvider::new(
+        "https://replica.example/",
+        SigningKey::generate(1024).unwrap(),
+    );
+    assert_eq!(
+        id_token_for(
+            Some(&provider),
+            &Scope::user_defaults(),
+            "alice",
+            None,
+            None
+        )
+        .unwrap(),
+        None
+    );
+    let id_token = id_token_for(
+        Some(&provider),
+        &[Scope::OpenId],
+        "alice",
+        Some("app"),
+        Some("n-0S6_WzA2Mj"),
+    )
+    .unwrap()
+    .unwrap();
+
+    let decode = |part: &str| {
+        base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(part)
+            .unwrap()
+    };
+    let (signing_input, signature) = id_token.rsplit_once('.').unwrap();
+    let jwk = provider.key.jwk();
+    let public_key = rsa::RsaPublicKey::new(
+        rsa::BigUint::from_bytes_be(&decode(&jwk.n)),
+        rsa::BigUint::from_bytes_be(&decode(&jwk.e)),
+    )
+    .unwrap();
+    rsa::pkcs1v15::VerifyingKey::<sha2::Sha256>::new(public_key)
+        .verify(
+            signing_input.as_bytes(),
+            &rsa::pkcs1v15::Signature::try_from(decode(signature).as_slice()).unwrap(),
+        )
+        .unwrap();
+
+    let (header, claims) = signing_input.split_once('.').unwrap();
+    let header: serde_json::Value = serde_json::from_slice(&decode(header)).unwrap();
+    assert_eq!(header["alg"], "RS256");
+    assert_eq!(header["kid"], jwk.kid.as_str());
+    let claims: IdTokenClaims = serde_json::from_slice(&decode(claims)).unwrap();
+    assert_eq!(claims.iss, "https://replica.example");
+    assert_eq!(claims.sub, "alice");
+    assert_eq!(claims.aud, "app");
+    assert_eq!(claims.nonce.as_deref(), Some("n-0S6_WzA2Mj"));
+    assert_eq!(claims.exp - claims.iat, 60 * 60);
+    assert_eq!(
+        provider.metadata().jwks_uri,
+        "https://replica.example/.well-known/jwks.json"
+    );
+    let metadata = serde_json::to_value(provider.metadata()).unwrap();
+    assert_eq!(
+        metadata["authorization_endpoint"],
+        "https://replica.example/api/authorise"
+    );
+    assert_eq!(
+        metadata["response_types_supported"],
+        serde_json::json!(["code"])
+    );
+    assert_eq!(
+        metadata["code_challenge_methods_supported"],
+        serde_json::json!(["S256"])
+    );
+    assert!(metadata["grant_types_supported"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!("authorization_code")));
+}
+
+#[actix_web::test]
+async fn test_federated_login_with_mock_idp() {
+    use crate::auth::federation::{candidate_username, FederatedProvider, FederationConfig};
+    use crate::auth::token::generate_token;
+    use crate::tests::mock_idp::{MockIdp, MockUser, MOCK_CLIENT_ID, MOCK_CLIENT_SECRET};
+
+    let idp = MockIdp::start(MockUser {
+        sub: String::from("248289761001"),
+        email: Some(String::from("Jane.Doe@example.com")),
+        email_verified: true,
+        preferred_username: None,
+    })
+    .await
+    .unwrap();
+    let provider = FederatedProvider::new(FederationConfig::new(
+        &idp.issuer,
+        MOCK_CLIENT_ID,
+        MOCK_CLIENT_SECRET,
+        "http://localhost:3000",
+    ));
+    let code_verifier = generate_token();
+    let url = provider
+        .authorization_url("some-state", "some-nonce", &code_verifier)
+        .await
+        .unwrap();
+
+    let (code, state) = idp.sign_in(&url).await;
+    assert_eq!(state, "some-state");
+    let claims = provider
+        .exchange(&code, &code_verifier, "some-nonce")
+        .await
+        .unwrap();
+    assert_eq!(claims.iss, idp.issuer);
+    assert_eq!(claims.sub, "248289761001");
+    assert_eq!(claims.email_verified, Some(true));
+    assert_eq!(candidate_username(&claims), "Jane.Doe");
+    // codes work once
+    assert!(provider
+        .exchange(&code, &code_verifier, "some-nonce")
+        .await
+        .is_err());
+
+    let (code, _) = idp.sign_in(&url).await;
+    assert!(provider
+        .exchange(&code, "not-the-verifier", "some-nonce")
+        .await
+        .is_err());
+    let (code, _) = idp.sign_in(&url).await;
+    assert!(provider
+        .exchange(&code, &code_verifier, "another-nonce")
+        .await
+        .is_err());
+    idp.stop().await;
+}
+
+#[actix_web::test]
+async fn test_federated_callback_requires_state_cookie() {
+    use crate::auth::federation::{FederatedProvider, FederationConfig, CALLBACK_PATH};
+
+    let config = FederationConfig::new(
+        "https://idp.example",
+        "replica",
+        "secret",
+        "https://replica.example",
+    );
+    let cookie = config.state_cookie("some-state");
+    assert_eq!(cookie.path(), Some(CALLBACK_PATH));
+    assert_eq!(cookie.http_only(), Some(true));
+    assert_eq!(cookie.secure(), Some(true));
+    assert_eq!(cookie.same_site(), Some(actix_web::cookie::SameSite::Lax));
+    assert_eq!(
+        cookie.max_age(),
+        Some(actix_web::cookie::time::Duration::minutes(10))
+    );
+
+    // never connects: the state is checked before the database is
+    let pool: crate::DbPool = diesel::r2d2::Pool::builder().build_unchecked(
+        diesel::r2d2::ConnectionManager::new("postgres://localhost/unused"),
+    );
+    let app = actix_web::test::init_service(
+        actix_web::App::new()
+            .app_data(actix_web::web::Data::new(pool))
+            .app_data(actix_web::web::Data::new(FederatedProvider::new(config)))
+            .app_data(actix_web::web::Data::new(
+                crate::auth::token::TokenConfig::default(),
+            ))
+            .service(actix_web::web::scope("/api").service(crate::routes::federation::callback)),
+    )
+    .await;
+    let callback = || {
+        actix_web::test::TestRequest::get()
+            .uri("/api/federated/callback?state=some-state&code=some-code")
+    };
+    for req in [
+        callback(),
+        callback().cookie(actix_web::cookie::Cookie::new(
+            crate::auth::federation::STATE_COOKIE,
+            "another-state",
+        )),
+    ] {
+        let res = actix_web::test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+}
+
+#[actix_web::test]
+async fn test_authorization_code_flow() {
+    use base64::Engine;
+
+    use crate::auth::authorise::pkce_challenge;
+    use crate::auth::oidc::{IdTokenClaims, OidcProvider, SigningKey};
+    use crate::auth::token::generate_token;
+    use crate::models::access_token::hash_token;
+    use crate::models::confidential_client::{ConfidentialClient, NewConfidentialClient};
+    use crate::tests::db::{insert_user, test_app, test_pool, TEST_PASSWORD};
+
+    let Some(pool) = test_pool() else {
+        return;
+    };
+    {
+        let mut conn = pool.get().unwrap();
+        insert_user(&mut conn, "alice");
+        ConfidentialClient::insert(
+            &mut conn,
+            &NewConfidentialClient {
+                client_id: "app",
+                secret_hash: &hash_token("app-secret"),
+                name: "App",
+                owner: "alice",
+                scopes: "openid models:read",
+                redirect_uris: "https://app.example/cb",
+            },
+        )
+        .unwrap();
+    }
+    let app = test_app(
+        pool,
+        OidcProvider::new(
+            "https://replica.example",
+            SigningKey::generate(1024).unwrap(),
+        ),
+    )
+    .await;
+
+    let code_verifier = generate_token();
+    let code_challenge = pkce_challenge(&code_verifier);
+    let request = [
+        ("response_type", "code"),
+        ("client_id", "app"),
+        ("redirect_uri", "https://app.example/cb"),
+        ("scope", "openid models:read"),
+        ("state", "xyz"),
+        ("code_challenge", code_challenge.as_str()),
+        ("code_challenge_method", "S256"),
+        ("nonce", "n-0S6_WzA2Mj"),
+    ];
+    let res = actix_web::test::call_service(
+        &app,
+        actix_web::test::TestRequest::get()
+            .uri(&format!(
+                "/api/authorise?{}",
+                serde_urlencoded::to_string(request).unwrap()
+            ))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::OK);
+    let page = actix_web::test::read_body(res).await;
+    assert!(String::from_utf8_lossy(&page).contains("Sign in to App"));
+
+    let sign_in = |password: &'static str| {
+        let mut form = request.to_vec();
+        form.extend([("username", "alice"), ("password", password)]);
+        actix_web::test::TestRequest::post()
+            .uri("/api/authorise")
+            .set_form(form)
+            .to_request()
+    };
+    let res = actix_web::test::call_service(&app, sign_in("not the password")).await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+    let res = actix_web::test::call_service(&app, sign_in(TEST_PASSWORD)).await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::SEE_OTHER);
+    let location = res
+        .headers()
+        .get(actix_web::http::header::LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let params: std::collections::HashMap<String, String> =
+        serde_urlencoded::from_str(location.strip_prefix("https://app.example/cb?").unwrap())
+            .unwrap();
+    assert_eq!(params["state"], "xyz");
+    let code = params["code"].clone();
+
+    let exchange = |code_verifier: &str| {
+        actix_web::test::TestRequest::post()
+            .uri("/api/token")
+            .set_form([
+                ("grant_type", "authorization_code"),
+                ("code", code.as_str()),
+                ("redirect_uri", "https://app.example/cb"),
+                ("client_id", "app"),
+                ("code_verifier", code_verifier),
+            ])
+            .to_request()
+    };
+    let token: serde_json::Value =
+        actix_web::test::call_and_read_body_json(&app, exchange(&code_verifier)).await;
+    assert_eq!(token["scope"], "openid models:read");
+    assert!(token["refresh_token"].is_string());
+    let claims = token["id_token"]
+        .as_str()
+        .unwrap()
+        .split('.')
+        .nth(1)
+        .unwrap();
+    let claims: IdTokenClaims = serde_json::from_slice(
+        &base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(claims)
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(claims.sub, "alice");
+    assert_eq!(claims.aud, "app");
+    assert_eq!(claims.nonce.as_deref(), Some("n-0S6_WzA2Mj"));
+    // each code works once
+    let res = actix_web::test::call_service(&app, exchange(&code_verifier)).await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+    let res = actix_web::test::call_service(
+        &app,
+        actix_web::test::TestRequest::get()
+            .uri("/api/v0/models")
+            .insert_header((
+                actix_web::http::header::AUTHORIZATION,
+                format!("Bearer {}", token["access_token"].as_str().unwrap()),
+            ))
+            .to_request(),
+    )
+    .await;
+    assert!(res.status().is_success());
+}
+
+#[actix_web::test]
+async fn test_api_keys_narrow_scopes_and_expire() {
+    use crate::auth::oidc::{OidcProvider, SigningKey};
+    use crate::models::access_token::hash_token;
+    use crate::models::api_key::{ApiKey, NewApiKey};
+    use crate::tests::db::{insert_user, password_token, test_app, test_pool};
+
+    let Some(pool) = test_pool() else {
+        return;
+    };
+    insert_user(&mut pool.get().unwrap(), "alice");
+    {
+        let mut conn = pool.get().unwrap();
+        ApiKey::insert(
+            &mut conn,
+            &NewApiKey {
+                username: "alice",
+                name: "old",
+                prefix: "sr_expired",
+                key_hash: &hash_token("sr_expired"),
+                scopes: "swap",
+                expires_at: Some(
+                    chrono::NaiveDate::from_ymd_opt(2000, 1, 1)
+                        .and_then(|date| date.and_hms_opt(0, 0, 0))
+                        .unwrap(),
+                ),
+            },
+        )
+        .unwrap();
+    }
+    let app = test_app(
+        pool,
+        OidcProvider::new(
+            "https://replica.example",
+            SigningKey::generate(1024).unwrap(),
+        ),
+    )
+    .await;
+    let token = password_token(&app, "alice", "models:read swap").await;
+    let call = |method: actix_web::http::Method,
+                uri: &str,
+                bearer: &str,
+                body: Option<serde_json::Value>| {
+        let req = actix_web::test::TestRequest::default()
+            .method(method)
+            .uri(uri)
+            .insert_header((
+                actix_web::http::header::AUTHORIZATION,
+                format!("Bearer {}", bearer),
+            ));
+        match body {
+            Some(body) => req.set_json(body),
+            None => req,
+        }
+        .to_request()
+    };
+    let create = |bearer: &str, body: serde_json::Value| {
+        call(
+            actix_web::http::Method::POST,
+            "/secured/api_keys",
+            bearer,
+            Some(body),
+        )
+    };
+
+    for body in [
+        serde_json::json!({"name": "ci", "scope": "swap admin"}),
+        serde_json::json!({"name": "ci", "scope": "swap", "expires_at": "2000-01-01T00:00:00"}),
+    ] {
+        let res = actix_web::test::call_service(&app, create(&token, body)).await;
+        assert_eq!(res.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+    let res = actix_web::test::call_service(
+        &app,
+        create(&token, serde_json::json!({"name": "ci", "scope": "swap"})),
+    )
+    .await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::CREATED);
+    let created: serde_json::Value = actix_web::test::read_body_json(res).await;
+    let key = created["key"].as_str().unwrap();
+
+    // the key has only the scope it was narrowed to
+    let res = actix_web::test::call_service(
+        &app,
+        call(actix_web::http::Method::GET, "/api/v0/swaps", key, None),
+    )
+    .await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::OK);
+    let res = actix_web::test::call_service(
+        &app,
+        call(actix_web::http::Method::GET, "/api/v0/models", key, None),
+    )
+    .await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::FORBIDDEN);
+    // nor can it mint keys outliving itself
+    let res = actix_web::test::call_service(
+        &app,
+        create(key, serde_json::json!({"name": "ci2", "scope": "swap"})),
+    )
+    .await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::FORBIDDEN);
+
+    let res = actix_web::test::call_service(
+        &app,
+        call(
+            actix_web::http::Method::GET,
+            "/api/v0/swaps",
+            "sr_expired",
+            None,
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn test_refresh_token_rotation() {
+    use crate::auth::oidc::{OidcProvider, SigningKey};
+    use crate::tests::db::{insert_user, test_app, test_pool, TEST_PASSWORD};
+
+    let Some(pool) = test_pool() else {
+        return;
+    };
+    insert_user(&mut pool.get().unwrap(), "alice");
+    let app = test_app(
+        pool,
+        OidcProvider::new(
+            "https://replica.example",
+            SigningKey::generate(1024).unwrap(),
+        ),
+    )
+    .await;
+    let token_request = |form: &[(&str, &str)]| {
+        actix_web::test::TestRequest::post()
+            .uri("/api/token")
+            .set_form(form)
+            .to_request()
+    };
+    let refresh = |refresh_token: &str, scope: &str| {
+        token_request(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("scope", scope),
+        ])
+    };
+    let models = |access_token: &str| {
+        actix_web::test::TestRequest::get()
+            .uri("/api/v0/models")
+            .insert_header((
+                actix_web::http::header::AUTHORIZATION,
+                format!("Bearer {}", access_token),
+            ))
+            .to_request()
+    };
+
+    let first: serde_json::Value = actix_web::test::call_and_read_body_json(
+        &app,
+        token_request(&[
+            ("grant_type", "password"),
+            ("username", "alice"),
+            ("password", TEST_PASSWORD),
+            ("scope", "models:read swap"),
+        ]),
+    )
+    .await;
+    let first_refresh = first["refresh_token"].as_str().unwrap();
+
+    // a refresh cannot widen the grant; being refused does not use the token up
+    let res =
+        actix_web::test::call_service(&app, refresh(first_refresh, "models:read swap admin")).await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    let body = actix_web::test::read_body(res).await;
+    assert!(String::from_utf8_lossy(&body).contains("invalid_scope"));
+
+    // but it can narrow it, and is rotated
+    let second: serde_json::Value =
+        actix_web::test::call_and_read_body_json(&app, refresh(first_refresh, "models:read")).await;
+    assert_eq!(second["scope"], "models:read");
+    let second_refresh = second["refresh_token"].as_str().unwrap();
+    assert_ne!(second_refresh, first_refresh);
+    let res =
+        actix_web::test::call_service(&app, models(second["access_token"].as_str().unwrap())).await;
+    assert!(res.status().is_success());
+
+    // presenting the rotated token again revokes its whole family
+    let res = actix_web::test::call_service(&app, refresh(first_refresh, "models:read")).await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    let res = actix_web::test::call_service(&app, refresh(second_refresh, "models:read")).await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    for token in [&first, &second] {
+        let res = actix_web::test::try_call_service(
+            &app,
+            models(token["access_token"].as_str().unwrap()),
+        )
+        .await;
+        assert!(res
+            .map_or_else(|err| err.error_response().status(), |res| res.status())
+            .is_client_error());
+    }
+}
+
+#[actix_web::test]
+async fn test_token_introspection_and_revocation() {
+    use crate::auth::oidc::{OidcProvider, SigningKey};
+    use crate::models::access_token::{hash_token, AccessToken, NewAccessToken};
+    use crate::models::api_key::{ApiKey, NewApiKey};
+    use crate::models::confidential_client::{ConfidentialClient, NewConfidentialClient};
+    use crate::models::user_account::UserAccount;
+    use crate::tests::db::{insert_user, password_token, test_app, test_pool};
+
+    let Some(pool) = test_pool() else {
+        return;
+    };
+    {
+        let mut conn = pool.get().unwrap();
+        insert_user(&mut conn, "alice");
+        insert_user(&mut conn, "bob");
+        ConfidentialClient::insert(
+            &mut conn,
+            &NewConfidentialClient {
+                client_id: "gateway",
+                secret_hash: &hash_token("gateway-secret"),
+                name: "Gateway",
+                owner: "alice",
+                scopes: "models:read",
+                redirect_uris: "",
+            },
+        )
+        .unwrap();
+        AccessToken::record(
+            &mut conn,
+            &NewAccessToken {
+                token_hash: &hash_token("expired-token"),
+                username: Some("alice"),
+                scopes: "models:read",
+                expires_at: Some(
+                    chrono::NaiveDate::from_ymd_opt(2000, 1, 1)
+                        .and_then(|date| date.and_hms_opt(0, 0, 0))
+                        .unwrap(),
+                ),
+                family_id: None,
+                local: true,
+            },
+        )
+        .unwrap();
+        ApiKey::insert(
+            &mut conn,
+            &NewApiKey {
+                username: "alice",
+                name: "ci",
+                prefix: "sr_live",
+                key_hash: &hash_token("sr_live"),
+                scopes: "swap",
+                expires_at: None,
+            },
+        )
+        .unwrap();
+    }
+    let app = test_app(
+        pool.clone(),
+        OidcProvider::new(
+            "https://replica.example",
+            SigningKey::generate(1024).unwrap(),
+        ),
+    )
+    .await;
+    let alice_token = password_token(&app, "alice", "models:read").await;
+    let bob_token = password_token(&app, "bob", "models:read").await;
+    {
+        let mut conn = pool.get().unwrap();
+        UserAccount::touch(&mut conn, "bob").unwrap();
+        UserAccount::set_disabled(&mut conn, "bob", true).unwrap();
+    }
+    let lookup = |path: &str, token: &str, client_secret: Option<&str>| {
+        let mut form = vec![("token", token)];
+        if let Some(client_secret) = client_secret {
+            form.extend([("client_id", "gateway"), ("client_secret", client_secret)]);
+        }
+        actix_web::test::TestRequest::post()
+            .uri(path)
+            .set_form(form)
+            .to_request()
+    };
+    let introspect = |token: &str| lookup("/api/token/introspect", token, Some("gateway-secret"));
+
+    let active: serde_json::Value =
+        actix_web::test::call_and_read_body_json(&app, introspect(&alice_token)).await;
+    assert_eq!(active["active"], true);
+    assert_eq!(active["username"], "alice");
+    assert_eq!(active["scope"], "models:read");
+    assert!(active["client_id"].is_null());
+    let api_key: serde_json::Value =
+        actix_web::test::call_and_read_body_json(&app, introspect("sr_live")).await;
+    assert_eq!(api_key["active"], true);
+    assert_eq!(api_key["scope"], "swap");
+    for inactive in ["expired-token", bob_token.as_str(), "never-issued"] {
+        let res: serde_json::Value =
+            actix_web::test::call_and_read_body_json(&app, introspect(inactive)).await;
+        // nothing but `active` is told about an inactive token
+        assert_eq!(res, serde_json::json!({"active": false}));
+    }
+
+    for client_secret in [None, Some("wrong-secret")] {
+        let res = actix_web::test::call_service(
+            &app,
+            lookup("/api/token/introspect", &alice_token, client_secret),
+        )
+        .await;
+        assert_eq!(res.status(), actix_web::http::StatusCode::UNAUTHORIZED);
+    }
+
+    let res =
+        actix_web::test::call_service(&app, lookup("/api/token/revoke", "never-issued", None))
+            .await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::OK);
+    let res =
+        actix_web::test::call_service(&app, lookup("/api/token/revoke", &alice_token, None)).await;
+    assert_eq!(res.status(), actix_web::http::StatusCode::OK);
+    let revoked: serde_json::Value =
+        actix_web::test::call_and_read_body_json(&app, introspect(&alice_token)).await;
+    assert_eq!(revoked["active"], false);
+}
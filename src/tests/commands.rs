@@ -0,0 +1,109 @@
+use crate::commands::{probe, probe_address, response_status, MigrateCommand, UserCommand};
+use crate::listen::Listen;
+use crate::{Cli, Command};
+
+fn parse(args: &[&str]) -> Cli {
+    clap::Parser::try_parse_from(std::iter::once("serve-replica").chain(args.iter().copied()))
+        .unwrap()
+}
+
+#[test]
+fn test_cli_debug_assert() {
+    <Cli as clap::CommandFactory>::command().debug_assert();
+}
+
+#[test]
+fn test_cli_serves_without_subcommand() {
+    let cli = parse(&["--port", "8080", "--no-migrate"]);
+    assert!(cli.command.is_none());
+    assert_eq!(cli.serve.port, Some(8080));
+    assert!(cli.serve.no_migrate);
+    assert!(matches!(
+        parse(&["serve", "--port", "8080"]).command,
+        Some(Command::Serve(serve)) if serve.port == Some(8080)
+    ));
+}
+
+#[test]
+fn test_cli_subcommands() {
+    assert!(matches!(
+        parse(&["migrate"]).command,
+        Some(Command::Migrate { command: None })
+    ));
+    assert!(matches!(
+        parse(&["migrate", "revert", "--steps", "2"]).command,
+        Some(Command::Migrate {
+            command: Some(MigrateCommand::Revert { steps: 2 })
+        })
+    ));
+    assert!(matches!(
+        parse(&["-e", "FOO=bar", "user", "set-role", "alice", "admin"]).command,
+        Some(Command::User {
+            command: UserCommand::SetRole { username, role }
+        }) if username == "alice" && role == "admin"
+    ));
+    assert!(matches!(
+        parse(&["healthcheck"]).command,
+        Some(Command::Healthcheck(_))
+    ));
+    // global options are taken after the subcommand too
+    assert_eq!(
+        parse(&["openapi", "-e", "CRAWL_URL=http://localhost:3002"]).env,
+        Some(vec![String::from("CRAWL_URL=http://localhost:3002")])
+    );
+    // serve's options belong to serve
+    assert!(
+        clap::Parser::try_parse_from(["serve-replica", "migrate", "--port", "8080"])
+            .map(|_: Cli| ())
+            .is_err()
+    );
+}
+
+#[test]
+fn test_probe_address() {
+    assert_eq!(
+        probe_address(Listen::Tcp(String::from("0.0.0.0"), 3000)),
+        Listen::Tcp(String::from("127.0.0.1"), 3000)
+    );
+    assert_eq!(
+        probe_address(Listen::Tcp(String::from("::"), 3000)),
+        Listen::Tcp(String::from("::1"), 3000)
+    );
+    assert_eq!(
+        probe_address(Listen::Tcp(String::from("localhost"), 3000)),
+        Listen::Tcp(String::from("localhost"), 3000)
+    );
+    assert_eq!(
+        probe_address(Listen::Unix(std::path::PathBuf::from(
+            "/run/serve-replica.sock"
+        ))),
+        Listen::Unix(std::path::PathBuf::from("/run/serve-replica.sock"))
+    );
+}
+
+#[test]
+fn test_response_status() {
+    assert_eq!(
+        response_status(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}"),
+        Some(200)
+    );
+    assert_eq!(
+        response_status(b"HTTP/1.0 503 Service Unavailable\r\n"),
+        Some(503)
+    );
+    assert_eq!(response_status(b"SSH-2.0-OpenSSH_9.6\r\n"), None);
+    assert_eq!(response_status(b""), None);
+}
+
+#[test]
+fn test_probe_over_tls_only_on_loopback() {
+    assert!(crate::tls::loopback_client_config().is_ok());
+    let err = probe(
+        &Listen::Tcp(String::from("192.0.2.1"), 443),
+        "/api",
+        std::time::Duration::from_secs(1),
+        true,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
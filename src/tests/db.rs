@@ -0,0 +1,99 @@
+//! Harness for tests against PostgreSQL at `TEST_DATABASE_URL`; without it they are skipped.
+//! Each test's pool has one connection, in a transaction that is rolled back when it closes.
+
+use crate::DbPool;
+
+pub(crate) const TEST_PASSWORD: &str = "correct horse battery staple";
+
+/// Pool for one test, or `None` to skip it; migrations are run once, on first use
+pub(crate) fn test_pool() -> Option<DbPool> {
+    static MIGRATED: std::sync::Once = std::sync::Once::new();
+
+    let url = std::env::var("TEST_DATABASE_URL").ok()?;
+    MIGRATED.call_once(|| {
+        use diesel::Connection;
+
+        let mut conn =
+            diesel::PgConnection::establish(&url).expect("connecting to TEST_DATABASE_URL");
+        crate::run_migrations(&mut conn).expect("running migrations");
+    });
+    let pool = diesel::r2d2::Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(diesel::r2d2::TestCustomizer))
+        .build(diesel::r2d2::ConnectionManager::new(url))
+        .expect("connecting to TEST_DATABASE_URL");
+    Some(pool)
+}
+
+/// A user registered through `/api/register`, email verified, with [`TEST_PASSWORD`]
+pub(crate) fn insert_user(conn: &mut diesel::PgConnection, username: &str) {
+    use crate::models::user_credential::{hash_password, NewUserCredential, UserCredential};
+
+    UserCredential::insert(
+        conn,
+        &NewUserCredential {
+            username,
+            email: &format!("{}@example.com", username),
+            password_hash: &hash_password(TEST_PASSWORD).unwrap(),
+        },
+    )
+    .unwrap();
+    UserCredential::mark_verified(conn, username).unwrap();
+}
+
+/// The app as `main` serves it, minus login throttling and federation
+pub(crate) async fn test_app(
+    pool: DbPool,
+    oidc: crate::auth::oidc::OidcProvider,
+) -> impl actix_web::dev::Service<
+    actix_http::Request,
+    Response = actix_web::dev::ServiceResponse<impl actix_web::body::MessageBody>,
+    Error = actix_web::Error,
+> {
+    use utoipa_actix_web::AppExt;
+
+    actix_web::test::init_service(
+        actix_web::App::new()
+            .into_utoipa_app()
+            .app_data(actix_web::web::Data::new(pool))
+            .app_data(actix_web::web::Data::new(
+                crate::auth::token::TokenConfig::default(),
+            ))
+            .app_data(actix_web::web::Data::new(oidc))
+            .app_data(actix_web::web::Data::new(
+                crate::swap::worker::WorkerConfig::default(),
+            ))
+            .app_data(actix_web::web::Data::new(
+                crate::swap::preprocess::Preprocessor::default(),
+            ))
+            .configure(crate::routes)
+            .into_app(),
+    )
+    .await
+}
+
+/// An access token for `username`, from the password grant, with `scope`
+pub(crate) async fn password_token<S, B>(app: &S, username: &str, scope: &str) -> String
+where
+    S: actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse<B>,
+        Error = actix_web::Error,
+    >,
+    B: actix_web::body::MessageBody,
+{
+    let token: serde_json::Value = actix_web::test::call_and_read_body_json(
+        app,
+        actix_web::test::TestRequest::post()
+            .uri("/api/token")
+            .set_form([
+                ("grant_type", "password"),
+                ("username", username),
+                ("password", TEST_PASSWORD),
+                ("scope", scope),
+            ])
+            .to_request(),
+    )
+    .await;
+    token["access_token"].as_str().unwrap().to_owned()
+}
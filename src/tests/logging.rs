@@ -0,0 +1,106 @@
+use crate::logging::{redact_headers, redact_query, REDACTED};
+
+#[test]
+fn test_redact_query() {
+    assert_eq!(redact_query("page=2&per_page=20"), "page=2&per_page=20");
+    assert_eq!(
+        redact_query("state=abc&code=s3cret&return_to=x"),
+        format!("state=abc&code={}&return_to=x", REDACTED)
+    );
+    assert_eq!(
+        redact_query("access_token=s3cret"),
+        format!("access_token={}", REDACTED)
+    );
+}
+
+#[test]
+fn test_redact_headers() {
+    let mut headers = actix_web::http::header::HeaderMap::new();
+    headers.insert(
+        actix_web::http::header::AUTHORIZATION,
+        actix_web::http::header::HeaderValue::from_static("Bearer s3cret"),
+    );
+    headers.insert(
+        actix_web::http::header::COOKIE,
+        actix_web::http::header::HeaderValue::from_static("session=s3cret"),
+    );
+    headers.insert(
+        actix_web::http::header::USER_AGENT,
+        actix_web::http::header::HeaderValue::from_static("curl/8.5.0"),
+    );
+    assert_eq!(
+        redact_headers(&headers),
+        vec![
+            (String::from("authorization"), String::from(REDACTED)),
+            (String::from("cookie"), String::from(REDACTED)),
+            (String::from("user-agent"), String::from("curl/8.5.0")),
+        ]
+    );
+}
+
+#[test]
+fn test_client_ip_trusts_only_configured_proxies() {
+    use crate::proxy::TrustedProxies;
+
+    let request = |peer: &str| {
+        actix_web::test::TestRequest::default()
+            .peer_addr(peer.parse().unwrap())
+            .insert_header(("X-Forwarded-For", "203.0.113.7"))
+            .to_http_request()
+    };
+    let trusted = TrustedProxies {
+        ips: vec!["10.0.0.2".parse().unwrap()],
+        unix: false,
+    };
+    assert_eq!(
+        trusted.client_ip(&request("10.0.0.2:41000")).as_deref(),
+        Some("203.0.113.7")
+    );
+    assert_eq!(
+        trusted.client_ip(&request("198.51.100.1:41000")).as_deref(),
+        Some("198.51.100.1")
+    );
+    assert_eq!(
+        TrustedProxies::default()
+            .client_ip(&request("10.0.0.2:41000"))
+            .as_deref(),
+        Some("10.0.0.2")
+    );
+}
+
+#[test]
+fn test_settings_from_merged_vars() {
+    use crate::logging::{settings, LogFormat};
+
+    let vars = |pairs: &[(&str, &str)]| -> indexmap::IndexMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+    assert_eq!(
+        settings(None, None, &vars(&[])),
+        Ok((String::from("info"), LogFormat::Text))
+    );
+    assert_eq!(
+        settings(
+            None,
+            None,
+            &vars(&[("RUST_LOG", "serve_replica=debug"), ("LOG_FORMAT", "json")])
+        ),
+        Ok((String::from("serve_replica=debug"), LogFormat::Json))
+    );
+    // flags win over the variables
+    assert_eq!(
+        settings(
+            Some("warn"),
+            Some(LogFormat::Text),
+            &vars(&[("RUST_LOG", "debug"), ("LOG_FORMAT", "json")])
+        ),
+        Ok((String::from("warn"), LogFormat::Text))
+    );
+    assert_eq!(
+        settings(None, None, &vars(&[("LOG_FORMAT", "xml")])),
+        Err(String::from("LOG_FORMAT must be text or json, not \"xml\""))
+    );
+}
@@ -0,0 +1,185 @@
+//! Minimal OpenID Connect provider on a local port, to test federated login against
+
+use crate::auth::authorise::pkce_challenge;
+use crate::auth::oidc::SigningKey;
+use crate::auth::token::{client_credentials, generate_token};
+
+pub const MOCK_CLIENT_ID: &str = "serve-replica";
+pub const MOCK_CLIENT_SECRET: &str = "mock-secret";
+
+/// Whoever signs in at the mock provider
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockUser {
+    pub sub: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+}
+
+struct PendingCode {
+    redirect_uri: String,
+    nonce: String,
+    code_challenge: String,
+}
+
+struct MockState {
+    issuer: String,
+    key: SigningKey,
+    user: MockUser,
+    codes: std::sync::Mutex<std::collections::HashMap<String, PendingCode>>,
+}
+
+type Params = actix_web::web::Query<std::collections::HashMap<String, String>>;
+
+async fn discovery(state: actix_web::web::Data<MockState>) -> actix_web::HttpResponse {
+    actix_web::HttpResponse::Ok().json(serde_json::json!({
+        "issuer": state.issuer,
+        "authorization_endpoint": format!("{}/authorize", state.issuer),
+        "token_endpoint": format!("{}/token", state.issuer),
+        "jwks_uri": format!("{}/jwks", state.issuer),
+    }))
+}
+
+async fn jwks(state: actix_web::web::Data<MockState>) -> actix_web::HttpResponse {
+    actix_web::HttpResponse::Ok().json(serde_json::json!({ "keys": [state.key.jwk()] }))
+}
+
+/// Signs `user` in straight away, redirecting back with a code
+async fn authorize(
+    state: actix_web::web::Data<MockState>,
+    params: Params,
+) -> actix_web::HttpResponse {
+    let param = |name: &str| params.get(name).cloned().unwrap_or_default();
+    if param("client_id") != MOCK_CLIENT_ID || param("code_challenge_method") != "S256" {
+        return actix_web::HttpResponse::BadRequest().finish();
+    }
+    let code = generate_token();
+    state.codes.lock().unwrap().insert(
+        code.clone(),
+        PendingCode {
+            redirect_uri: param("redirect_uri"),
+            nonce: param("nonce"),
+            code_challenge: param("code_challenge"),
+        },
+    );
+    let query = serde_urlencoded::to_string([("code", code), ("state", param("state"))]).unwrap();
+    actix_web::HttpResponse::Found()
+        .insert_header((
+            actix_web::http::header::LOCATION,
+            format!("{}?{}", param("redirect_uri"), query),
+        ))
+        .finish()
+}
+
+async fn token(
+    state: actix_web::web::Data<MockState>,
+    req: actix_web::HttpRequest,
+    form: actix_web::web::Form<std::collections::HashMap<String, String>>,
+) -> actix_web::HttpResponse {
+    let authorization = req
+        .headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if client_credentials(authorization, None, None)
+        != Some((MOCK_CLIENT_ID.to_owned(), MOCK_CLIENT_SECRET.to_owned()))
+    {
+        return actix_web::HttpResponse::Unauthorized()
+            .json(serde_json::json!({ "error": "invalid_client" }));
+    }
+    let param = |name: &str| form.get(name).cloned().unwrap_or_default();
+    let pending = state.codes.lock().unwrap().remove(&param("code"));
+    let Some(pending) = pending.filter(|pending| {
+        pending.redirect_uri == param("redirect_uri")
+            && pending.code_challenge == pkce_challenge(&param("code_verifier"))
+    }) else {
+        return actix_web::HttpResponse::BadRequest()
+            .json(serde_json::json!({ "error": "invalid_grant" }));
+    };
+    let iat = chrono::Utc::now().timestamp();
+    let id_token = state
+        .key
+        .sign(&serde_json::json!({
+            "iss": state.issuer,
+            "sub": state.user.sub,
+            "aud": MOCK_CLIENT_ID,
+            "exp": iat + 300,
+            "iat": iat,
+            "nonce": pending.nonce,
+            "email": state.user.email,
+            "email_verified": state.user.email_verified,
+            "preferred_username": state.user.preferred_username,
+        }))
+        .unwrap();
+    actix_web::HttpResponse::Ok().json(serde_json::json!({
+        "access_token": generate_token(),
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token,
+    }))
+}
+
+/// A running mock provider; its issuer URL is what `FEDERATION_ISSUER` would be set to
+pub struct MockIdp {
+    pub issuer: String,
+    server: actix_web::dev::ServerHandle,
+}
+
+impl MockIdp {
+    pub async fn start(user: MockUser) -> std::io::Result<MockIdp> {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+        let issuer = format!("http://{}", listener.local_addr()?);
+        let state = actix_web::web::Data::new(MockState {
+            issuer: issuer.clone(),
+            key: SigningKey::generate(1024).map_err(std::io::Error::other)?,
+            user,
+            codes: Default::default(),
+        });
+        let server = actix_web::HttpServer::new(move || {
+            actix_web::App::new()
+                .app_data(state.clone())
+                .route(
+                    "/.well-known/openid-configuration",
+                    actix_web::web::get().to(discovery),
+                )
+                .route("/jwks", actix_web::web::get().to(jwks))
+                .route("/authorize", actix_web::web::get().to(authorize))
+                .route("/token", actix_web::web::post().to(token))
+        })
+        .workers(1)
+        .listen(listener)?
+        .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        Ok(MockIdp {
+            issuer,
+            server: handle,
+        })
+    }
+
+    /// Follow `authorization_url` as a browser would, returning the `code` and `state` the
+    /// provider redirects back with
+    pub async fn sign_in(&self, authorization_url: &str) -> (String, String) {
+        let response = awc::Client::builder()
+            .disable_redirects()
+            .finish()
+            .get(authorization_url)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), actix_web::http::StatusCode::FOUND);
+        let location = response
+            .headers()
+            .get(actix_web::http::header::LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let (_, query) = location.split_once('?').unwrap();
+        let params: std::collections::HashMap<String, String> =
+            serde_urlencoded::from_str(query).unwrap();
+        (params["code"].clone(), params["state"].clone())
+    }
+
+    pub async fn stop(self) {
+        self.server.stop(true).await
+    }
+}
@@ -1,2 +1,18 @@
 #[cfg(test)]
+mod audit;
+#[cfg(test)]
+mod auth;
+#[cfg(test)]
+mod commands;
+#[cfg(test)]
+mod config;
+#[cfg(test)]
+mod db;
+#[cfg(test)]
+mod logging;
+#[cfg(test)]
+mod mock_idp;
+#[cfg(test)]
 mod routes;
+#[cfg(test)]
+mod swap;
@@ -0,0 +1,454 @@
+use crate::extra_schemas::SwapPostRequest;
+use crate::swap::ModelSource;
+
+#[test]
+fn test_model_source_from_model_id() {
+    let request = SwapPostRequest {
+        user_img_url: String::from("data:image/jpg;base64,fa"),
+        model_id: Some(5),
+        ..SwapPostRequest::default()
+    };
+    assert_eq!(
+        ModelSource::try_from(&request).unwrap(),
+        ModelSource::Stored {
+            model_id: 5,
+            img_idx: 0
+        }
+    );
+}
+
+#[test]
+fn test_model_source_from_url() {
+    let request = SwapPostRequest {
+        user_img_url: String::from("data:image/jpg;base64,fa"),
+        model_img_url: Some(String::from("https://a.com/a.jpg")),
+        ..SwapPostRequest::default()
+    };
+    assert_eq!(
+        ModelSource::try_from(&request).unwrap(),
+        ModelSource::Url(String::from("https://a.com/a.jpg"))
+    );
+}
+
+#[test]
+fn test_model_source_requires_exactly_one() {
+    let neither = SwapPostRequest::default();
+    assert!(ModelSource::try_from(&neither).is_err());
+    let both = SwapPostRequest {
+        model_img_url: Some(String::from("https://a.com/a.jpg")),
+        model_id: Some(5),
+        ..SwapPostRequest::default()
+    };
+    assert!(ModelSource::try_from(&both).is_err());
+}
+
+#[test]
+fn test_swap_history_query_pagination() {
+    use crate::models::swap_history::SwapHistoryQuery;
+
+    let query = SwapHistoryQuery::default();
+    assert_eq!(query.per_page(), SwapHistoryQuery::DEFAULT_PER_PAGE);
+    assert_eq!(query.offset(), 0);
+
+    let query = SwapHistoryQuery {
+        page: Some(2),
+        per_page: Some(1_000),
+        ..SwapHistoryQuery::default()
+    };
+    assert_eq!(query.per_page(), SwapHistoryQuery::MAX_PER_PAGE);
+    assert_eq!(query.offset(), 2 * SwapHistoryQuery::MAX_PER_PAGE);
+
+    let query = SwapHistoryQuery {
+        page: Some(i64::MAX),
+        ..SwapHistoryQuery::default()
+    };
+    assert_eq!(query.offset(), i64::MAX);
+}
+
+#[test]
+fn test_preprocess_downscales_and_normalises() {
+    use crate::swap::preprocess::{OutputFormat, Preprocessor};
+
+    let mut png = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(image::RgbaImage::new(64, 32))
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    let preprocessor = Preprocessor {
+        max_dimension: 16,
+        format: OutputFormat::Jpeg,
+        ..Preprocessor::default()
+    };
+    let normalised = preprocessor.normalise(png.get_ref()).unwrap();
+    assert_eq!(
+        image::guess_format(&normalised).unwrap(),
+        image::ImageFormat::Jpeg
+    );
+    let img = image::load_from_memory(&normalised).unwrap();
+    assert_eq!((img.width(), img.height()), (16, 8));
+}
+
+#[test]
+fn test_decode_data_url() {
+    use crate::swap::preprocess::decode_data_url;
+
+    assert_eq!(decode_data_url("image/jpg;base64,AAEC").unwrap(), [0, 1, 2]);
+    assert!(decode_data_url("image/jpg,AAEC").is_err());
+    assert!(decode_data_url("image/jpg;base64").is_err());
+}
+
+#[test]
+fn test_is_public_ip() {
+    use crate::swap::preprocess::is_public_ip;
+
+    for ip in [
+        "127.0.0.1",
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.1.1",
+        "169.254.169.254",
+        "0.0.0.0",
+        "100.64.0.1",
+        "::1",
+        "::",
+        "fd00::1",
+        "fe80::1",
+        "::ffff:127.0.0.1",
+    ] {
+        assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+    }
+    for ip in ["93.184.215.14", "2606:2800:21f:cb07:6820:80da:af6b:8b2c"] {
+        assert!(is_public_ip(ip.parse().unwrap()), "{}", ip);
+    }
+}
+
+#[test]
+fn test_is_public_ip_reserved_ipv4() {
+    use crate::swap::preprocess::is_public_ip;
+
+    // 0.0.0.0/8
+    assert!(!is_public_ip("0.1.2.3".parse().unwrap()));
+    // 192.0.0.0/24, but not the rest of 192.0.0.0/16
+    assert!(!is_public_ip("192.0.0.8".parse().unwrap()));
+    assert!(is_public_ip("192.0.1.8".parse().unwrap()));
+    // 198.18.0.0/15, but not its neighbours
+    assert!(!is_public_ip("198.18.0.1".parse().unwrap()));
+    assert!(!is_public_ip("198.19.255.254".parse().unwrap()));
+    assert!(is_public_ip("198.17.255.254".parse().unwrap()));
+    assert!(is_public_ip("198.20.0.1".parse().unwrap()));
+    // 240.0.0.0/4, up to the broadcast address
+    assert!(!is_public_ip("240.0.0.1".parse().unwrap()));
+    assert!(!is_public_ip("255.255.255.255".parse().unwrap()));
+    // multicast
+    assert!(!is_public_ip("224.0.0.1".parse().unwrap()));
+    assert!(!is_public_ip("239.255.255.250".parse().unwrap()));
+    assert!(!is_public_ip("ff02::1".parse().unwrap()));
+}
+
+#[test]
+fn test_is_public_ip_embedded_ipv4() {
+    use crate::swap::preprocess::is_public_ip;
+
+    // IPv4-mapped
+    assert!(!is_public_ip("::ffff:10.0.0.1".parse().unwrap()));
+    assert!(is_public_ip("::ffff:93.184.215.14".parse().unwrap()));
+    // IPv4-compatible
+    assert!(!is_public_ip("::127.0.0.1".parse().unwrap()));
+    assert!(!is_public_ip("::169.254.169.254".parse().unwrap()));
+    assert!(is_public_ip("::93.184.215.14".parse().unwrap()));
+    // NAT64
+    assert!(!is_public_ip("64:ff9b::127.0.0.1".parse().unwrap()));
+    assert!(!is_public_ip("64:ff9b::192.168.0.1".parse().unwrap()));
+    assert!(is_public_ip("64:ff9b::93.184.215.14".parse().unwrap()));
+    // 6to4, 2002:7f00:1:: being 127.0.0.1
+    assert!(!is_public_ip("2002:7f00:1::".parse().unwrap()));
+    assert!(!is_public_ip("2002:a9fe:a9fe::1".parse().unwrap()));
+    assert!(is_public_ip("2002:5db8:d70e::1".parse().unwrap()));
+}
+
+#[actix_web::test]
+async fn test_check_url() {
+    use crate::swap::preprocess::check_url;
+
+    assert!(check_url("data:image/jpg;base64,AAEC").await.is_ok());
+    assert!(check_url("data:image/jpg,AAEC").await.is_err());
+    assert!(check_url("https://93.184.215.14/a.jpg").await.is_ok());
+    assert!(check_url("https://[::ffff:127.0.0.1]/a.jpg").await.is_err());
+    assert!(check_url("file:///etc/passwd").await.is_err());
+    assert!(check_url("a.jpg").await.is_err());
+}
+
+#[actix_web::test]
+async fn test_fetch_address_refuses_internal_urls() {
+    use crate::swap::preprocess::fetch_address;
+
+    assert!(fetch_address("http://127.0.0.1:8080/a.jpg").await.is_err());
+    assert!(fetch_address("http://[::1]/a.jpg").await.is_err());
+    assert!(fetch_address("http://169.254.169.254/latest/meta-data")
+        .await
+        .is_err());
+    assert!(fetch_address("file:///etc/passwd").await.is_err());
+    assert!(fetch_address("ftp://93.184.215.14/a.jpg").await.is_err());
+    assert_eq!(
+        fetch_address("https://93.184.215.14/a.jpg").await.unwrap(),
+        "93.184.215.14:443".parse().unwrap()
+    );
+}
+
+#[test]
+fn test_batch_aggregate_status() {
+    use crate::models::swap_batch::aggregate_status;
+
+    assert_eq!(aggregate_status(["queued", "queued"]), "queued");
+    assert_eq!(aggregate_status(["queued", "completed"]), "running");
+    assert_eq!(aggregate_status(["running", "failed"]), "running");
+    assert_eq!(aggregate_status(["completed", "completed"]), "completed");
+    assert_eq!(aggregate_status(["completed", "failed"]), "partial");
+    assert_eq!(aggregate_status(["failed", "failed"]), "failed");
+}
+
+#[test]
+fn test_partition_stale_fails_jobs_out_of_attempts() {
+    use crate::models::swap_job::partition_stale;
+
+    assert_eq!(
+        partition_stale(&[(1, 1), (2, 3), (3, 2), (4, 4)], 3),
+        (vec![1, 3], vec![2, 4])
+    );
+    assert_eq!(partition_stale(&[], 3), (vec![], vec![]));
+}
+
+#[test]
+fn test_worker_requeue_schedule() {
+    use crate::swap::worker::WorkerConfig;
+
+    let config = WorkerConfig::default();
+    assert_eq!(config.stale_after(), std::time::Duration::from_secs(240));
+    assert_eq!(
+        config.requeue_interval(),
+        std::time::Duration::from_secs(120)
+    );
+    // a zero period would make the interval panic
+    let config = WorkerConfig {
+        job_timeout: std::time::Duration::ZERO,
+        ..WorkerConfig::default()
+    };
+    assert_eq!(config.requeue_interval(), std::time::Duration::from_secs(1));
+}
+
+fn queued_job<'a>(username: &'a str, img_url: &'a str) -> crate::models::swap_job::NewSwapJob<'a> {
+    crate::models::swap_job::NewSwapJob {
+        username,
+        model_id: None,
+        user_img_url: Some(img_url),
+        model_img_url: img_url,
+        batch_id: None,
+    }
+}
+
+/// A tiny PNG, as a `data:` URL the preprocessor accepts
+fn png_data_url() -> String {
+    use base64::Engine;
+
+    let mut png = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(image::RgbaImage::new(4, 4))
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(png.get_ref())
+    )
+}
+
+#[test]
+fn test_enqueue_refuses_beyond_capacity() {
+    use crate::models::swap_job::SwapJob;
+
+    let Some(pool) = crate::tests::db::test_pool() else {
+        return;
+    };
+    let mut conn = pool.get().unwrap();
+    let job = queued_job("alice", "data:image/png;base64,AAEC");
+
+    assert!(SwapJob::enqueue(&mut conn, &job, 1).unwrap().is_some());
+    assert!(SwapJob::enqueue(&mut conn, &job, 1).unwrap().is_none());
+    // a batch is queued whole or not at all
+    assert!(
+        SwapJob::enqueue_many(&mut conn, &[job.clone(), job.clone()], 2)
+            .unwrap()
+            .is_none()
+    );
+    let jobs = SwapJob::enqueue_many(&mut conn, &[job.clone(), job.clone()], 3)
+        .unwrap()
+        .unwrap();
+    assert_eq!(jobs.len(), 2);
+}
+
+#[actix_web::test]
+async fn test_swap_answers_503_with_retry_after_when_queue_full() {
+    use crate::auth::oidc::{OidcProvider, SigningKey};
+    use crate::models::swap_job::SwapJob;
+    use crate::swap::worker::WorkerConfig;
+    use crate::tests::db::{insert_user, password_token, test_app, test_pool};
+
+    let Some(pool) = test_pool() else {
+        return;
+    };
+    let config = WorkerConfig::default();
+    {
+        let mut conn = pool.get().unwrap();
+        insert_user(&mut conn, "alice");
+        let job = queued_job("alice", "data:image/png;base64,AAEC");
+        let full = vec![job; config.queue_capacity as usize];
+        assert!(
+            SwapJob::enqueue_many(&mut conn, &full, config.queue_capacity)
+                .unwrap()
+                .is_some()
+        );
+    }
+    let app = test_app(
+        pool,
+        OidcProvider::new(
+            "https://replica.example",
+            SigningKey::generate(1024).unwrap(),
+        ),
+    )
+    .await;
+    let token = password_token(&app, "alice", "swap").await;
+
+    let res = actix_web::test::call_service(
+        &app,
+        actix_web::test::TestRequest::post()
+            .uri("/v1/swap")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(serde_json::json!({
+                "user_img_url": "data:image/png;base64,AAEC",
+                "model_img_url": "data:image/png;base64,AAEC"
+            }))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(
+        res.status(),
+        actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(
+        res.headers()
+            .get(actix_web::http::header::RETRY_AFTER)
+            .unwrap(),
+        config.retry_after_secs().to_string().as_str()
+    );
+}
+
+#[test]
+fn test_retried_job_waits_for_run_after() {
+    use crate::models::swap_job::{SwapJob, STATUS_QUEUED};
+
+    let Some(pool) = crate::tests::db::test_pool() else {
+        return;
+    };
+    let mut conn = pool.get().unwrap();
+    SwapJob::enqueue(
+        &mut conn,
+        &queued_job("alice", "data:image/png;base64,AAEC"),
+        1,
+    )
+    .unwrap()
+    .unwrap();
+    let job = SwapJob::claim(&mut conn).unwrap().unwrap();
+    assert_eq!(job.attempts, 1);
+
+    SwapJob::retry(
+        &mut conn,
+        job.id,
+        "upstream down",
+        std::time::Duration::from_secs(60),
+    )
+    .unwrap();
+    let retried = SwapJob::read(&mut conn, "alice", job.id).unwrap();
+    assert_eq!(retried.status, STATUS_QUEUED);
+    assert_eq!(retried.error.as_deref(), Some("upstream down"));
+    assert!(SwapJob::claim(&mut conn).unwrap().is_none());
+
+    // the test's transaction pins the database clock, so no wait is due
+    SwapJob::retry(
+        &mut conn,
+        job.id,
+        "upstream down",
+        std::time::Duration::ZERO,
+    )
+    .unwrap();
+    let claimed = SwapJob::claim(&mut conn).unwrap().unwrap();
+    assert_eq!((claimed.id, claimed.attempts), (job.id, 2));
+}
+
+#[actix_web::test]
+async fn test_timed_out_job_is_retried() {
+    use crate::models::swap_job::{SwapJob, STATUS_QUEUED};
+    use crate::swap::preprocess::Preprocessor;
+    use crate::swap::worker::{process, WorkerConfig};
+    use crate::swap::SwapBackend;
+
+    let Some(pool) = crate::tests::db::test_pool() else {
+        return;
+    };
+    let img_url = png_data_url();
+    let job = {
+        let mut conn = pool.get().unwrap();
+        SwapJob::enqueue(&mut conn, &queued_job("alice", &img_url), 1)
+            .unwrap()
+            .unwrap();
+        SwapJob::claim(&mut conn).unwrap().unwrap()
+    };
+    // accepts connections, but never answers them
+    let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let backend = SwapBackend::new(
+        format!("http://{}", silent.local_addr().unwrap()),
+        Preprocessor::default(),
+    );
+    let config = WorkerConfig {
+        job_timeout: std::time::Duration::from_millis(200),
+        ..WorkerConfig::default()
+    };
+
+    process(0, &pool, &backend, &config, job.clone()).await;
+
+    let mut conn = pool.get().unwrap();
+    let retried = SwapJob::read(&mut conn, "alice", job.id).unwrap();
+    assert_eq!(retried.status, STATUS_QUEUED);
+    assert!(retried.error.unwrap().starts_with("timed out"));
+    assert!(retried.run_after > job.run_after);
+}
+
+#[test]
+fn test_batch_holds_user_image_until_children_finish() {
+    use crate::models::swap_batch::SwapBatch;
+    use crate::models::swap_job::SwapJob;
+
+    let Some(pool) = crate::tests::db::test_pool() else {
+        return;
+    };
+    let mut conn = pool.get().unwrap();
+    let models = [
+        (None, String::from("https://a.com/a.jpg")),
+        (None, String::from("https://a.com/b.jpg")),
+    ];
+    let (batch, jobs) =
+        SwapBatch::create(&mut conn, "alice", "data:image/png;base64,AAEC", &models, 2)
+            .unwrap()
+            .unwrap();
+    assert!(jobs.iter().all(|job| job.user_img_url.is_none()));
+    assert_eq!(
+        SwapBatch::user_img_url(&mut conn, batch.id).unwrap(),
+        "data:image/png;base64,AAEC"
+    );
+
+    SwapJob::fail(&mut conn, jobs[0].id, "upstream down").unwrap();
+    SwapBatch::release_images(&mut conn).unwrap();
+    assert_eq!(
+        SwapBatch::user_img_url(&mut conn, batch.id).unwrap(),
+        "data:image/png;base64,AAEC"
+    );
+    SwapJob::fail(&mut conn, jobs[1].id, "upstream down").unwrap();
+    SwapBatch::release_images(&mut conn).unwrap();
+    assert_eq!(SwapBatch::user_img_url(&mut conn, batch.id).unwrap(), "");
+}
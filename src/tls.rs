@@ -0,0 +1,236 @@
+//! HTTPS with rustls: certificates reloaded without a restart, and the plain HTTP redirect
+
+/// How often the certificate and key files are checked for changes
+pub const RELOAD_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Where the certificate chain and private key are, both PEM
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub cert_path: std::path::PathBuf,
+    pub key_path: std::path::PathBuf,
+    /// port to answer plain HTTP on with a redirect to HTTPS, if any
+    pub redirect_port: Option<u16>,
+}
+
+fn load_certified_key(config: &TlsConfig) -> Result<rustls::sign::CertifiedKey, String> {
+    let open = |path: &std::path::Path| {
+        std::fs::File::open(path)
+            .map(std::io::BufReader::new)
+            .map_err(|err| format!("{}: {}", path.display(), err))
+    };
+    let certs = rustls_pemfile::certs(&mut open(&config.cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("{}: {}", config.cert_path.display(), err))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates", config.cert_path.display()));
+    }
+    let key = rustls_pemfile::private_key(&mut open(&config.key_path)?)
+        .map_err(|err| format!("{}: {}", config.key_path.display(), err))?
+        .ok_or_else(|| format!("{}: no private key", config.key_path.display()))?;
+    let key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|err| format!("{}: {}", config.key_path.display(), err))?;
+    Ok(rustls::sign::CertifiedKey::new(certs, key))
+}
+
+/// Latest modification time of the certificate and key files
+fn modified(config: &TlsConfig) -> Option<std::time::SystemTime> {
+    [&config.cert_path, &config.key_path]
+        .iter()
+        .filter_map(|path| {
+            std::fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .ok()
+        })
+        .max()
+}
+
+/// Hands every TLS handshake the certificate last loaded, so it can be swapped while serving
+#[derive(Debug)]
+pub struct CertResolver {
+    config: TlsConfig,
+    current: std::sync::RwLock<std::sync::Arc<rustls::sign::CertifiedKey>>,
+    loaded_modified: std::sync::Mutex<Option<std::time::SystemTime>>,
+}
+
+impl CertResolver {
+    pub fn load(config: TlsConfig) -> Result<CertResolver, String> {
+        let loaded_modified = modified(&config);
+        let current = load_certified_key(&config)?;
+        Ok(CertResolver {
+            config,
+            current: std::sync::RwLock::new(std::sync::Arc::new(current)),
+            loaded_modified: std::sync::Mutex::new(loaded_modified),
+        })
+    }
+
+    /// Load the files again; on failure the certificate in use is kept
+    pub fn reload(&self) -> Result<(), String> {
+        let loaded_modified = modified(&self.config);
+        let certified_key = load_certified_key(&self.config)?;
+        *self.current.write().unwrap() = std::sync::Arc::new(certified_key);
+        *self.loaded_modified.lock().unwrap() = loaded_modified;
+        Ok(())
+    }
+
+    /// [`CertResolver::reload`] if either file changed since the last load
+    pub fn reload_if_changed(&self) -> Result<bool, String> {
+        let changed = modified(&self.config) != *self.loaded_modified.lock().unwrap();
+        if changed {
+            self.reload()?;
+        }
+        Ok(changed)
+    }
+}
+
+impl rustls::server::ResolvesServerCert for CertResolver {
+    fn resolve(
+        &self,
+        _client_hello: rustls::server::ClientHello<'_>,
+    ) -> Option<std::sync::Arc<rustls::sign::CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+pub fn server_config(
+    resolver: std::sync::Arc<CertResolver>,
+) -> Result<rustls::ServerConfig, String> {
+    Ok(
+        rustls::ServerConfig::builder_with_provider(std::sync::Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver),
+    )
+}
+
+/// Accepts any certificate, though handshake signatures must still match it: for `healthcheck`
+/// probing this server on loopback, where its certificate for the public hostname cannot verify
+#[derive(Debug)]
+struct AnyCertificate(rustls::crypto::WebPkiSupportedAlgorithms);
+
+impl rustls::client::danger::ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+/// Client config for reaching this server on loopback without verifying its certificate; never
+/// use it for any other address
+pub fn loopback_client_config() -> Result<rustls::ClientConfig, String> {
+    let provider = rustls::crypto::ring::default_provider();
+    let verifier = AnyCertificate(provider.signature_verification_algorithms);
+    Ok(
+        rustls::ClientConfig::builder_with_provider(std::sync::Arc::new(provider))
+            .with_safe_default_protocol_versions()
+            .map_err(|err| err.to_string())?
+            .dangerous()
+            .with_custom_certificate_verifier(std::sync::Arc::new(verifier))
+            .with_no_client_auth(),
+    )
+}
+
+/// Reload `resolver`'s certificate on SIGHUP, and whenever its files change
+pub fn watch(resolver: std::sync::Arc<CertResolver>) -> std::io::Result<()> {
+    let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    let on_hangup = resolver.clone();
+    actix_web::rt::spawn(async move {
+        while hangups.recv().await.is_some() {
+            match on_hangup.reload() {
+                Ok(()) => log::info!("SIGHUP: reloaded TLS certificate"),
+                Err(err) => log::error!("SIGHUP: keeping TLS certificate: {}", err),
+            }
+        }
+    });
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(RELOAD_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            match resolver.reload_if_changed() {
+                Ok(true) => log::info!("TLS certificate changed; reloaded"),
+                Ok(false) => {}
+                Err(err) => log::error!("keeping TLS certificate: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Where to redirect a plain HTTP request for `host` (with or without port) and
+/// `path_and_query` to, given HTTPS is served on `https_port`
+pub fn https_location(host: &str, path_and_query: &str, https_port: u16) -> String {
+    // an IPv6 literal is bracketed, so its colons are not a port separator
+    let hostname = match host.rsplit_once(':') {
+        Some((hostname, port)) if !port.contains(']') => hostname,
+        _ => host,
+    };
+    match https_port {
+        443 => format!("https://{}{}", hostname, path_and_query),
+        port => format!("https://{}:{}{}", hostname, port, path_and_query),
+    }
+}
+
+async fn redirect(
+    req: actix_web::HttpRequest,
+    https_port: actix_web::web::Data<u16>,
+) -> actix_web::HttpResponse {
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+    actix_web::HttpResponse::PermanentRedirect()
+        .insert_header((
+            actix_web::http::header::LOCATION,
+            https_location(req.connection_info().host(), path_and_query, **https_port),
+        ))
+        .finish()
+}
+
+/// Server answering every plain HTTP request on `port` of each of `hosts` with a redirect to
+/// HTTPS on `https_port`
+pub fn redirect_server(
+    hosts: &[&str],
+    port: u16,
+    https_port: u16,
+) -> std::io::Result<actix_web::dev::Server> {
+    let mut server = actix_web::HttpServer::new(move || {
+        actix_web::App::new()
+            .app_data(actix_web::web::Data::new(https_port))
+            .default_service(actix_web::web::to(redirect))
+    })
+    .workers(1);
+    for host in hosts {
+        server = server.bind((*host, port))?;
+    }
+    Ok(server.run())
+}